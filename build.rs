@@ -25,8 +25,10 @@ fn write_rom_inserts(file: &mut File) -> Result<(), io::Error> {
         "    pub static ref ROMS: HashMap<String, Vec<u8>> = {{"
     )?;
     writeln!(file, "        let mut ret = HashMap::new();")?;
-    for entry in read_dir(format!("src/{}", GAMES_DIR))? {
-        let entry = entry?;
+    // Directory order varies between filesystems, sort so the output is the same everywhere
+    let mut entries = read_dir(format!("src/{}", GAMES_DIR))?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
         let name = entry
             .file_name()
             .to_str()
@@ -48,7 +50,7 @@ fn write_rom_inserts(file: &mut File) -> Result<(), io::Error> {
 }
 
 fn generate_module() -> Result<(), io::Error> {
-    let mut module = File::create(&format!("src/{}.rs", MODULE_NAME))?;
+    let mut module = File::create(format!("src/{}.rs", MODULE_NAME))?;
     // use statements
     write_imports(&mut module)?;
     // rom array
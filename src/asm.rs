@@ -0,0 +1,626 @@
+//! Turn assembly source into ROM bytes.
+//!
+//! The syntax is the one the disassembler writes, so its output assembles back to the same ROM:
+//! ```text
+//! org 0x200               ; where the image is loaded, 0x200 if left out
+//! SPEED equ 2             ; a named constant
+//!
+//! start:
+//!     ld i, ball          ; labels and constants work anywhere a number does
+//!     drw v0, v1, 2
+//!     add v0, SPEED
+//!     jp start
+//!
+//! ball:
+//!     db ".##.....", "#..#...."   ; sprite rows, # for a lit pixel
+//!     dw 0x1234                   ; big-endian words
+//! include "more.s"        ; pull in another file, relative to this one
+//! ```
+//! Numbers are decimal, `0x` hex or `0b` binary, and can be added and subtracted, like `table + 2`.
+//! Mnemonics, registers and directives are case insensitive, labels and constants aren't.
+
+use crate::{
+    emulator::mnemonic::{self, long_operand, MnemonicError, Token},
+    RawOpcode,
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+/// Where the image is loaded if the source doesn't say.
+const DEFAULT_ORG: u16 = 0x200;
+
+/// A problem with the source, and where it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// The file the problem is in.  Source passed in directly is called `<input>`.
+    pub file: String,
+    /// 1-based line number, or 0 if the problem isn't with any one line.
+    pub line: usize,
+    /// 1-based column number, or 0 if the problem isn't with any one line.
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(
+                f,
+                "{}:{}:{}: {}",
+                self.file, self.line, self.column, self.message
+            )
+        }
+    }
+}
+
+impl Error for AsmError {}
+
+/// Assemble source text into a ROM image, starting at the `org` address.
+/// Includes are looked up relative to the current directory.
+/// ```
+/// # use chip8::assemble;
+/// # use pretty_assertions::assert_eq;
+/// let rom = assemble("loop: ld v1, 0x20\n jp loop").unwrap();
+/// assert_eq!(rom, vec![0x61, 0x20, 0x12, 0x00]);
+///
+/// let err = assemble("ld v1, 0x20\ndrw v0, vz, 5").unwrap_err();
+/// assert_eq!(err.to_string(), "<input>:2:9: Expected a register, found `vz`");
+/// ```
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler::default();
+    assembler.parse("<input>", Path::new(""), source)?;
+    assembler.emit()
+}
+
+/// Assemble a file on disk.  Includes are looked up relative to the file that includes them.
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler::default();
+    assembler.include(path.as_ref(), None)?;
+    assembler.emit()
+}
+
+/// A source line, for error messages.
+#[derive(Debug, Clone)]
+struct Location {
+    file: String,
+    line: usize,
+}
+
+impl Location {
+    fn error<S: Into<String>>(&self, column: usize, message: S) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.line,
+            column,
+            message: message.into(),
+        }
+    }
+
+    /// Place a problem with one instruction on this line.
+    fn located(&self, e: MnemonicError) -> AsmError {
+        self.error(e.column, e.message)
+    }
+}
+
+/// One line split into its parts.
+#[derive(Debug)]
+struct SourceLine {
+    label: Option<Token>,
+    /// The mnemonic or directive, plus `equ` for a constant
+    words: Vec<Token>,
+    operands: Vec<Token>,
+}
+
+/// Anything that produces bytes.  Operands are kept as text until every label is known.
+#[derive(Debug)]
+enum ItemKind {
+    Instruction {
+        mnemonic: Token,
+        operands: Vec<Token>,
+    },
+    Bytes(Vec<Token>),
+    Words(Vec<Token>),
+}
+
+#[derive(Debug)]
+struct Item {
+    kind: ItemKind,
+    addr: u32,
+    location: Location,
+}
+
+/// Two passes - `parse` lays everything out and finds every label, then `emit` encodes it.
+#[derive(Debug)]
+struct Assembler {
+    items: Vec<Item>,
+    /// Labels and constants share one namespace
+    symbols: HashMap<String, i64>,
+    /// Address of the first byte, fixed once anything is output
+    org: Option<u16>,
+    /// Where the next item goes
+    addr: u32,
+    /// Files currently being read, to catch an include cycle
+    including: Vec<PathBuf>,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            symbols: HashMap::new(),
+            org: None,
+            addr: DEFAULT_ORG as u32,
+            including: Vec::new(),
+        }
+    }
+}
+
+impl Assembler {
+    /// Read a file and parse it.  `location` is the include directive that asked for it, if any.
+    fn include(
+        &mut self,
+        path: &Path,
+        location: Option<(&Location, usize)>,
+    ) -> Result<(), AsmError> {
+        let error = |message: String| match location {
+            Some((location, column)) => location.error(column, message),
+            None => AsmError {
+                file: path.display().to_string(),
+                line: 0,
+                column: 0,
+                message,
+            },
+        };
+        let canonical = path
+            .canonicalize()
+            .map_err(|e| error(format!("Can't read {}: {}", path.display(), e)))?;
+        if self.including.contains(&canonical) {
+            return Err(error(format!("{} includes itself", path.display())));
+        }
+        let source = fs::read_to_string(path)
+            .map_err(|e| error(format!("Can't read {}: {}", path.display(), e)))?;
+        self.including.push(canonical);
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        self.parse(&path.display().to_string(), dir, &source)?;
+        self.including.pop();
+        Ok(())
+    }
+
+    /// First pass over some source - record labels and constants and work out where everything goes.
+    fn parse(&mut self, file: &str, dir: &Path, source: &str) -> Result<(), AsmError> {
+        for (i, line) in source.lines().enumerate() {
+            let location = Location {
+                file: file.to_string(),
+                line: i + 1,
+            };
+            let SourceLine {
+                label,
+                words,
+                operands,
+            } = split_line(line, &location)?;
+            if let Some(label) = label {
+                self.define(&location, &label, self.addr as i64)?;
+            }
+            let head = match words.first() {
+                Some(head) => head,
+                None => continue,
+            };
+
+            // `NAME equ VALUE`
+            if matches!(words.get(1), Some(w) if w.text.eq_ignore_ascii_case("equ")) {
+                let value = match operands.as_slice() {
+                    [value] => self.evaluate(&location, value)?,
+                    _ => return Err(location.error(words[1].column, "`equ` takes one value")),
+                };
+                self.define(&location, head, value)?;
+                continue;
+            }
+
+            let size = match head.text.to_ascii_lowercase().as_str() {
+                "org" => {
+                    let addr = match operands.as_slice() {
+                        [addr] => self.evaluate(&location, addr)?,
+                        _ => return Err(location.error(head.column, "`org` takes one address")),
+                    };
+                    let addr = check_range(&location, &operands[0], addr, 0, 0xFFFF)? as u32;
+                    // Before any output this just moves the start, afterwards it pads with zeroes
+                    if self.org.is_some() && addr < self.addr {
+                        return Err(location.error(
+                            operands[0].column,
+                            format!(
+                                "Can't move back to {:#05X}, already at {:#05X}",
+                                addr, self.addr
+                            ),
+                        ));
+                    }
+                    self.addr = addr;
+                    continue;
+                }
+                "include" => {
+                    let name = match operands.as_slice() {
+                        [name] => name,
+                        _ => {
+                            return Err(location.error(head.column, "`include` takes one file name"))
+                        }
+                    };
+                    let path = dir.join(name.text.trim_matches('"'));
+                    self.include(&path, Some((&location, name.column)))?;
+                    continue;
+                }
+                "db" => {
+                    let size = operands.len() as u32;
+                    self.push(ItemKind::Bytes(operands), location);
+                    size
+                }
+                "dw" => {
+                    let size = operands.len() as u32 * 2;
+                    self.push(ItemKind::Words(operands), location);
+                    size
+                }
+                mnemonic => {
+                    // Only `ld i, long NNNN` takes up four bytes
+                    let long = mnemonic == "ld"
+                        && matches!(operands.get(1), Some(o) if long_operand(o).is_some());
+                    self.push(
+                        ItemKind::Instruction {
+                            mnemonic: head.clone(),
+                            operands,
+                        },
+                        location,
+                    );
+                    if long {
+                        4
+                    } else {
+                        2
+                    }
+                }
+            };
+            self.addr += size;
+            if self.addr > 0x10000 {
+                let location = &self.items.last().unwrap().location;
+                return Err(location.error(head.column, "Program doesn't fit in memory"));
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, kind: ItemKind, location: Location) {
+        if self.org.is_none() {
+            self.org = Some(self.addr as u16);
+        }
+        self.items.push(Item {
+            kind,
+            addr: self.addr,
+            location,
+        });
+    }
+
+    fn define(&mut self, location: &Location, name: &Token, value: i64) -> Result<(), AsmError> {
+        let valid = matches!(name.text.chars().next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+            && name
+                .text
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(location.error(name.column, format!("Invalid name `{}`", name.text)));
+        }
+        if self.symbols.insert(name.text.clone(), value).is_some() {
+            return Err(location.error(name.column, format!("`{}` is already defined", name.text)));
+        }
+        Ok(())
+    }
+
+    /// Work out an expression - numbers and symbols added or subtracted together.
+    fn evaluate(&self, location: &Location, token: &Token) -> Result<i64, AsmError> {
+        let mut total = 0i64;
+        let mut sign = 1;
+        let mut term_start = 0;
+        let text = &token.text;
+        for (i, c) in text
+            .char_indices()
+            .chain(std::iter::once((text.len(), '+')))
+        {
+            if (c == '+' || c == '-') && i >= term_start {
+                let term = text[term_start..i].trim();
+                let column = token.column
+                    + term_start
+                    + (text[term_start..i].len() - text[term_start..i].trim_start().len());
+                if term.is_empty() {
+                    // Allow a leading sign
+                    if i == 0 && c == '-' {
+                        sign = -1;
+                        term_start = 1;
+                        continue;
+                    }
+                    return Err(location.error(column, "Missing value"));
+                }
+                total += sign * self.term(location, term, column)?;
+                sign = if c == '-' { -1 } else { 1 };
+                term_start = i + 1;
+            }
+        }
+        Ok(total)
+    }
+
+    fn term(&self, location: &Location, term: &str, column: usize) -> Result<i64, AsmError> {
+        let parsed = if term.starts_with('"') {
+            sprite_row(term)
+        } else {
+            mnemonic::parse_number(term).or_else(|| self.symbols.get(term).copied())
+        };
+        parsed.ok_or_else(|| {
+            if term.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+                location.error(column, format!("Unknown symbol `{}`", term))
+            } else {
+                location.error(column, format!("Invalid number `{}`", term))
+            }
+        })
+    }
+
+    /// Second pass - encode every item now all the labels are known.
+    fn emit(&self) -> Result<Vec<u8>, AsmError> {
+        let org = self.org.unwrap_or(DEFAULT_ORG) as u32;
+        let mut ret = Vec::new();
+        for item in &self.items {
+            // An `org` may have left a gap
+            ret.resize((item.addr - org) as usize, 0);
+            let location = &item.location;
+            match &item.kind {
+                ItemKind::Bytes(values) => {
+                    for value in values {
+                        let n = self.evaluate(location, value)?;
+                        ret.push(check_range(location, value, n, -128, 0xFF)? as u8);
+                    }
+                }
+                ItemKind::Words(values) => {
+                    for value in values {
+                        let n = self.evaluate(location, value)?;
+                        let word = check_range(location, value, n, -32768, 0xFFFF)? as u16;
+                        ret.extend_from_slice(&word.to_be_bytes());
+                    }
+                }
+                ItemKind::Instruction { mnemonic, operands } => {
+                    let (opcode, long) = mnemonic::encode(mnemonic, operands, |token| {
+                        self.evaluate(location, token)
+                            .map_err(|e| MnemonicError::new(e.column, e.message))
+                    })
+                    .map_err(|e| location.located(e))?;
+                    ret.extend_from_slice(&u16::from(RawOpcode::from(opcode)).to_be_bytes());
+                    if let Some(long) = long {
+                        ret.extend_from_slice(&long.to_be_bytes());
+                    }
+                }
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// Break a line into an optional label, the words before the first operand, and the comma separated operands.
+fn split_line(line: &str, location: &Location) -> Result<SourceLine, AsmError> {
+    // Drop the comment, minding sprite literals
+    let mut quoted = false;
+    let end = line
+        .char_indices()
+        .find(|&(_, c)| {
+            if c == '"' {
+                quoted = !quoted;
+            }
+            c == ';' && !quoted
+        })
+        .map(|(i, _)| i)
+        .unwrap_or_else(|| line.len());
+    let code = &line[..end];
+
+    // Words up to the first operand are split on whitespace
+    let mut pos = 0;
+    let next_word = |pos: &mut usize| {
+        let rest = &code[*pos..];
+        let start = *pos + rest.len() - rest.trim_start().len();
+        let len = code[start..]
+            .find(char::is_whitespace)
+            .unwrap_or(code.len() - start);
+        *pos = start + len;
+        if len == 0 {
+            None
+        } else {
+            Some(Token {
+                text: code[start..start + len].to_string(),
+                column: start + 1,
+            })
+        }
+    };
+
+    let mut label = None;
+    let mut words = Vec::new();
+    let mut first = next_word(&mut pos);
+    if let Some(word) = &first {
+        if word.text.len() > 1 && word.text.ends_with(':') {
+            label = Some(Token {
+                text: word.text.trim_end_matches(':').to_string(),
+                column: word.column,
+            });
+            first = next_word(&mut pos);
+        }
+    }
+    if let Some(word) = first {
+        words.push(word);
+        // A second word is only allowed for `equ`
+        let save = pos;
+        match next_word(&mut pos) {
+            Some(word) if word.text.eq_ignore_ascii_case("equ") => words.push(word),
+            _ => pos = save,
+        }
+    }
+
+    let mut operands = Vec::new();
+    let rest = &code[pos..];
+    if !rest.trim().is_empty() {
+        let mut start = pos;
+        for piece in rest.split(',') {
+            let trimmed = piece.trim();
+            let column = start + piece.len() - piece.trim_start().len() + 1;
+            if trimmed.is_empty() {
+                return Err(location.error(column, "Missing operand"));
+            }
+            operands.push(Token {
+                text: trimmed.to_string(),
+                column,
+            });
+            start += piece.len() + 1;
+        }
+    }
+    Ok(SourceLine {
+        label,
+        words,
+        operands,
+    })
+}
+
+/// Parse a quoted sprite row like `"..####.."`, most significant bit first.
+fn sprite_row(literal: &str) -> Option<i64> {
+    let row = literal.strip_prefix('"')?.strip_suffix('"')?;
+    if row.is_empty() || row.len() > 8 {
+        return None;
+    }
+    row.chars().enumerate().try_fold(0, |acc, (i, c)| match c {
+        '#' | 'X' | 'x' | '1' => Some(acc | 0x80 >> i),
+        '.' | ' ' | '0' => Some(acc),
+        _ => None,
+    })
+}
+
+fn check_range(
+    location: &Location,
+    token: &Token,
+    n: i64,
+    min: i64,
+    max: i64,
+) -> Result<i64, AsmError> {
+    mnemonic::check_range(token, n, min, max).map_err(|e| location.located(e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{disassemble, ROMS};
+    use pretty_assertions::assert_eq;
+
+    fn error(source: &str) -> String {
+        assemble(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_round_trip_bundled_roms() {
+        for (name, rom) in ROMS.iter() {
+            let source = disassemble(rom, 0x200).to_string();
+            assert_eq!(&assemble(&source).unwrap(), rom, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_constants_and_expressions() {
+        let source = "
+            WIDTH equ 0x40
+            HALF equ WIDTH - 0x20 + 1
+            ld v0, HALF
+            add v1, -1
+            ld i, table + 2
+        table:
+            db 1, 2, 0b101
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0x60, 0x21, 0x71, 0xFF, 0xA2, 0x08, 0x01, 0x02, 0x05]
+        );
+    }
+
+    #[test]
+    fn test_data_directives() {
+        let source = r##"
+            db "#......#", "..##"
+            dw 0x1234, end
+        end:
+        "##;
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0x81, 0x30, 0x12, 0x34, 0x02, 0x06]
+        );
+    }
+
+    #[test]
+    fn test_org() {
+        // A later org pads with zeroes
+        let source = "org 0x300\n cls\n org 0x306\n ret";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0x00, 0xE0, 0, 0, 0, 0, 0x00, 0xEE]
+        );
+        assert_eq!(
+            error("cls\norg 0x200"),
+            "<input>:2:5: Can't move back to 0x200, already at 0x202"
+        );
+    }
+
+    #[test]
+    fn test_xo_chip_instructions() {
+        let source = "ld i, long data\n plane 3\n audio\n save v1, v4\n data: db 0xAA";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0xF0, 0x00, 0x02, 0x0A, 0xF3, 0x01, 0xF0, 0x02, 0x51, 0x42, 0xAA]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            error("  frob v1"),
+            "<input>:1:3: Unknown instruction `frob`"
+        );
+        assert_eq!(
+            error("drw v0, v1"),
+            "<input>:1:1: `drw` takes 3 operands, found 2"
+        );
+        assert_eq!(error("jp nowhere"), "<input>:1:4: Unknown symbol `nowhere`");
+        assert_eq!(
+            error("ld v0, 0x100"),
+            "<input>:1:8: `0x100` is out of range, expected -128 to 0xFF"
+        );
+        assert_eq!(error("ld v0, 12z"), "<input>:1:8: Invalid number `12z`");
+        assert_eq!(error("a:\na:"), "<input>:2:1: `a` is already defined");
+        assert_eq!(error("ld v0,, v1"), "<input>:1:7: Missing operand");
+        assert_eq!(
+            error("jp v1, 0x200"),
+            "<input>:1:4: Computed jumps can only use v0"
+        );
+        assert_eq!(error("ld k, v1"), "<input>:1:4: Can't load into `k`");
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("chip8-asm-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.s"), "call sub\ninclude \"sub.s\"\n").unwrap();
+        fs::write(dir.join("sub.s"), "sub:\n  ret\n  bogus\n").unwrap();
+        fs::write(dir.join("loop.s"), "include \"loop.s\"\n").unwrap();
+
+        let err = assemble_file(dir.join("main.s")).unwrap_err();
+        assert_eq!(err.file, dir.join("sub.s").display().to_string());
+        assert_eq!((err.line, err.column), (3, 3));
+        fs::write(dir.join("sub.s"), "sub:\n  ret\n").unwrap();
+        assert_eq!(
+            assemble_file(dir.join("main.s")).unwrap(),
+            vec![0x22, 0x02, 0x00, 0xEE]
+        );
+        let err = assemble_file(dir.join("loop.s")).unwrap_err();
+        assert!(err.message.ends_with("includes itself"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,25 @@
+use anyhow::Result;
+use chip8::assemble_file;
+use std::{fs, path::PathBuf};
+use structopt::*;
+
+#[derive(Debug, StructOpt)]
+/// Assemble Chip8 assembly source into a ROM
+struct Opt {
+    /// Path to the source file
+    #[structopt(parse(from_os_str))]
+    source: PathBuf,
+    /// Where to write the ROM - defaults to the source path with a .ch8 extension
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    let Opt { source, output } = Opt::from_args();
+
+    let rom = assemble_file(&source)?;
+    let output = output.unwrap_or_else(|| source.with_extension("ch8"));
+    fs::write(&output, &rom)?;
+    println!("Wrote {} bytes to {}", rom.len(), output.display());
+    Ok(())
+}
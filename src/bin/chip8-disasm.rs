@@ -0,0 +1,83 @@
+use anyhow::{anyhow, Result};
+use chip8::{disassemble_with_entries, AccessMap, Context, HeadlessContext, Machine, Quirks, ROMS};
+use std::{fs, path::PathBuf};
+use structopt::*;
+
+#[derive(Debug, StructOpt)]
+/// Disassemble a Chip8 ROM to assembly source
+struct Opt {
+    /// Path to a rom file on disk
+    #[structopt(parse(from_os_str), required_unless = "rom-name")]
+    rom_path: Option<PathBuf>,
+    /// The name of a bundled rom to disassemble instead, lower-case
+    #[structopt(short, long)]
+    rom_name: Option<String>,
+    /// Address the rom is loaded at, where execution starts
+    #[structopt(short, long, default_value = "0x200", parse(try_from_str = parse_addr))]
+    base: u16,
+    /// Write the listing here instead of stdout
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+    /// Run the rom headless for this many milliseconds first, so code only reached through
+    /// computed jumps gets found too, and report self-modifying code and uninitialised reads
+    #[structopt(long)]
+    run: Option<u64>,
+    /// Quirks preset to run under with --run: default, vip, chip48, schip, or xochip
+    #[structopt(short, long, default_value = "default")]
+    quirks: Quirks,
+}
+
+/// Accept decimal or 0x-prefixed hex
+fn parse_addr(s: &str) -> Result<u16> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| anyhow!("Invalid address {}", s))
+}
+
+fn main() -> Result<()> {
+    let opt = Opt::from_args();
+
+    let rom = if let Some(path) = &opt.rom_path {
+        fs::read(path)?
+    } else {
+        let name = opt.rom_name.unwrap_or_default();
+        ROMS.get(&name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Game {} not included", name))?
+    };
+    let entries = match opt.run {
+        Some(millis) => run(&rom, millis, opt.quirks)?,
+        None => Vec::new(),
+    };
+    let listing = disassemble_with_entries(&rom, opt.base, &entries).to_string();
+    match &opt.output {
+        Some(path) => fs::write(path, listing)?,
+        None => print!("{}", listing),
+    }
+    Ok(())
+}
+
+/// Run `rom` for `millis` of emulated time, returning every address an instruction ran from.
+/// A ROM that crashes still gives whatever it ran before it did.
+fn run(rom: &[u8], millis: u64, quirks: Quirks) -> Result<Vec<u16>> {
+    let context = HeadlessContext::new(1);
+    let mut machine = Machine::with_quirks(Box::new(context.clone()), quirks);
+    machine.set_access_map(AccessMap::new());
+    machine.load_rom(rom)?;
+    while !machine.has_exited() && context.now() < millis {
+        if let Err(e) = machine.run_due() {
+            eprintln!("Stopped running: {}", e);
+            break;
+        }
+        // Nothing looks at the screen, so don't let frames pile up
+        context.take_frames();
+        context.sleep(1);
+    }
+    let map = machine.take_access_map().unwrap_or_default();
+    for issue in map.issues() {
+        eprintln!("{}", issue);
+    }
+    Ok(map.executed().collect())
+}
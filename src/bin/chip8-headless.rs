@@ -0,0 +1,161 @@
+use anyhow::{anyhow, Result};
+use chip8::{
+    parse_address_range, save_screenshot, Context, GifRecorder, HeadlessContext, Machine,
+    MachineCalls, Movie, OpcodeClass, Palette, Profiler, Quirks, ReplayContext, TraceFilter,
+    TraceFormat, Tracer, ROMS,
+};
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    ops::RangeInclusive,
+    path::PathBuf,
+};
+use structopt::*;
+
+/// Busiest addresses and subroutines listed in the profile report
+const REPORT_LINES: usize = 10;
+
+#[derive(Debug, StructOpt)]
+/// Run a Chip8 ROM without a window, capturing what it draws
+struct Opt {
+    /// Path to a rom file on disk
+    #[structopt(parse(from_os_str), required_unless = "rom-name")]
+    rom_path: Option<PathBuf>,
+    /// The name of a bundled rom to run instead, lower-case
+    #[structopt(short, long)]
+    rom_name: Option<String>,
+    /// Milliseconds of emulated time to run for
+    #[structopt(short, long, default_value = "5000")]
+    millis: u64,
+    /// Quirks preset to run under: default, vip, chip48, schip, or xochip
+    #[structopt(short, long, default_value = "default")]
+    quirks: Quirks,
+    /// What 0NNN machine code calls do: error, ignore, or vip to emulate known COSMAC VIP routines
+    #[structopt(long, default_value = "error")]
+    machine_calls: MachineCalls,
+    /// Instructions per second
+    #[structopt(short, long, default_value = "500")]
+    speed: u64,
+    /// Seed for the random number generator
+    #[structopt(long, default_value = "1")]
+    seed: u64,
+    /// Play back a movie recorded with `native --record` instead, using its quirks, speed and seed
+    #[structopt(long, parse(from_os_str))]
+    replay: Option<PathBuf>,
+    /// Save the last frame here, as .png or .pbm
+    #[structopt(long, parse(from_os_str))]
+    screenshot: Option<PathBuf>,
+    /// Record every frame to an animated GIF here
+    #[structopt(long, parse(from_os_str))]
+    gif: Option<PathBuf>,
+    /// Image pixels per CHIP-8 pixel
+    #[structopt(long, default_value = "4")]
+    scale: u32,
+    /// Colours to capture in: classic, amber, green, lcd, or comma-separated #RRGGBB, background first
+    #[structopt(long, default_value = "classic")]
+    palette: Palette,
+    /// Write every instruction run to this file, or - for stderr
+    #[structopt(long, parse(from_os_str))]
+    trace: Option<PathBuf>,
+    /// Trace as text or json
+    #[structopt(long, default_value = "text")]
+    trace_format: TraceFormat,
+    /// Only trace instructions at these addresses, like 0x200-0x2FF
+    #[structopt(long, parse(try_from_str = parse_address_range))]
+    trace_pc: Option<RangeInclusive<u16>>,
+    /// Only trace these kinds of instruction: flow, skip, register, memory, display, input, timer, sound
+    #[structopt(long, use_delimiter = true)]
+    trace_class: Vec<OpcodeClass>,
+    /// Count how often each address runs and where the time goes, and print a report at the end
+    #[structopt(long)]
+    profile: bool,
+    /// Save a heatmap of how often each address in the first 4K ran, as .png or .ppm - implies --profile
+    #[structopt(long, parse(from_os_str))]
+    heatmap: Option<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    let mut opt = Opt::from_args();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let rom = if let Some(path) = &opt.rom_path {
+        fs::read(path)?
+    } else {
+        let name = opt.rom_name.clone().unwrap_or_default();
+        ROMS.get(&name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Game {} not included", name))?
+    };
+
+    let context = HeadlessContext::new(opt.seed);
+    let (mut machine, frames) = match &opt.replay {
+        Some(path) => {
+            let movie = Movie::from_bytes(&fs::read(path)?)?;
+            movie.check_rom(&rom)?;
+            let frames = movie.frames.len() as u64;
+            let quirks = movie.quirks;
+            let speed = movie.speed;
+            let replay = ReplayContext::new(Box::new(context.clone()), movie);
+            let mut machine = Machine::with_quirks(Box::new(replay), quirks);
+            machine.set_speed(speed);
+            (machine, Some(frames))
+        }
+        None => {
+            let mut machine = Machine::with_quirks(Box::new(context.clone()), opt.quirks);
+            machine.set_speed(opt.speed);
+            (machine, None)
+        }
+    };
+    machine.set_machine_calls(std::mem::take(&mut opt.machine_calls));
+    machine.load_rom(&rom)?;
+    if opt.profile || opt.heatmap.is_some() {
+        machine.set_profiler(Profiler::new());
+    }
+    if let Some(path) = &opt.trace {
+        let filter = TraceFilter {
+            pcs: opt.trace_pc.clone(),
+            classes: opt.trace_class.clone(),
+        };
+        machine.set_tracer(Tracer::create(path, opt.trace_format)?.with_filter(filter));
+    }
+
+    let mut gif = match &opt.gif {
+        Some(path) => Some(GifRecorder::new(
+            BufWriter::new(File::create(path)?),
+            opt.scale,
+            opt.palette,
+        )),
+        None => None,
+    };
+    // A movie runs for as long as it was recorded, anything else for the time asked
+    let done = |machine: &Machine, now: u64| match frames {
+        Some(frames) => machine.frame_count() >= frames,
+        None => now >= opt.millis,
+    };
+    loop {
+        machine.run_due()?;
+        for frame in context.take_frames() {
+            if let Some(gif) = &mut gif {
+                gif.add_frame(&frame, context.now())?;
+            }
+        }
+        if machine.has_exited() || done(&machine, context.now()) {
+            break;
+        }
+        context.sleep(1);
+    }
+
+    if let Some(gif) = gif {
+        gif.finish(context.now())?;
+    }
+    if let Some(path) = &opt.screenshot {
+        save_screenshot(machine.screen(), path, opt.scale, &opt.palette)?;
+    }
+    if let Some(profiler) = machine.take_profiler() {
+        print!("{}", profiler.report(REPORT_LINES));
+        if let Some(path) = &opt.heatmap {
+            profiler.save_heatmap(path, opt.scale)?;
+        }
+    }
+    Ok(())
+}
@@ -1,24 +1,344 @@
-use anyhow::Result;
-use chip8::{Machine, SdlContext};
+use anyhow::{anyhow, Result};
+use chip8::{
+    parse_address_range, Context, DebugCommand, Debugger, Effect, KeyConfig, Machine, MachineCalls,
+    Movie, OpcodeClass, Palette, Profiler, Quirks, RecordingContext, RenderOptions, ReplayContext,
+    SdlContext, StopReason, TraceFilter, TraceFormat, Tracer, DEBUG_HELP, ROMS,
+};
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
 use structopt::*;
 
+/// Busiest addresses and subroutines listed in the profile report
+const REPORT_LINES: usize = 10;
+
+/// Image pixels per address in the heatmap
+const HEATMAP_SCALE: u32 = 8;
+
 #[derive(Debug, StructOpt)]
 struct Opt {
-    // /// Activate debug mode
-    // #[structopt(short, long)]
-    // debug: bool
+    /// Start paused in the interactive debugger
+    #[structopt(short, long)]
+    debug: bool,
     /// The name of the rom to load, lower-case
     #[structopt(short, long, default_value = "test_opcode")]
     rom_name: String,
+    /// Path to a rom file on disk - takes precedence over --rom-name
+    #[structopt(short = "p", long, parse(from_os_str))]
+    rom_path: Option<PathBuf>,
+    /// Quirks preset to run under: default, vip, chip48, schip, or xochip
+    #[structopt(short, long, default_value = "default")]
+    quirks: Quirks,
+    /// What 0NNN machine code calls do: error, ignore, or vip to emulate known COSMAC VIP routines
+    #[structopt(long, default_value = "error")]
+    machine_calls: MachineCalls,
+    /// Instructions per second - Minus and Equals change it while running
+    #[structopt(short, long, default_value = "500")]
+    speed: u64,
+    /// Seconds of history Backspace can rewind through - 0 turns it off
+    #[structopt(long, default_value = "10")]
+    rewind_seconds: u64,
+    /// Directory to keep quicksaves in - F1-F4 pick a slot, F5 saves, F9 loads
+    #[structopt(long, parse(from_os_str), default_value = "saves")]
+    save_dir: PathBuf,
+    /// Record the keypad and random seed to a movie file, for replaying the session exactly
+    #[structopt(long, parse(from_os_str))]
+    record: Option<PathBuf>,
+    /// Play back a movie file recorded with --record, using its quirks and speed
+    #[structopt(long, parse(from_os_str), conflicts_with = "record")]
+    replay: Option<PathBuf>,
+    /// Colours to draw in: classic, amber, green, lcd, or comma-separated #RRGGBB, background first
+    #[structopt(long, default_value = "classic")]
+    palette: Palette,
+    /// Brightness a pixel keeps each frame after going dark, to hide flicker - 0 turns it off
+    #[structopt(long, default_value = "0.5")]
+    persistence: f32,
+    /// Lines between pixels: none, scanlines, or grid
+    #[structopt(long, default_value = "none")]
+    effect: Effect,
+    /// TOML file of key and controller bindings, with optional overrides per ROM
+    #[structopt(long, parse(from_os_str))]
+    keymap: Option<PathBuf>,
+    /// Write every instruction run to this file, or - for stderr
+    #[structopt(long, parse(from_os_str))]
+    trace: Option<PathBuf>,
+    /// Trace as text or json
+    #[structopt(long, default_value = "text")]
+    trace_format: TraceFormat,
+    /// Only trace instructions at these addresses, like 0x200-0x2FF
+    #[structopt(long, parse(try_from_str = parse_address_range))]
+    trace_pc: Option<RangeInclusive<u16>>,
+    /// Only trace these kinds of instruction: flow, skip, register, memory, display, input, timer, sound
+    #[structopt(long, use_delimiter = true)]
+    trace_class: Vec<OpcodeClass>,
+    /// Count how often each address runs and where the time goes, and print a report at the end
+    #[structopt(long)]
+    profile: bool,
+    /// Save a heatmap of how often each address in the first 4K ran, as .png or .ppm - implies --profile
+    #[structopt(long, parse(from_os_str))]
+    heatmap: Option<PathBuf>,
 }
 
 fn main() -> Result<()> {
-    let opt = Opt::from_args();
+    let mut opt = Opt::from_args();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
     // Init context
-    let context = SdlContext::new(15);
-    let mut machine = Machine::new(context);
-    machine.load_game(&opt.rom_name)?;
-    machine.run();
-    Ok(())
+    let options = RenderOptions {
+        palette: opt.palette,
+        persistence: opt.persistence,
+        effect: opt.effect,
+    };
+    let mut sdl = SdlContext::new(15, opt.save_dir, options);
+    if let Some(path) = &opt.keymap {
+        // Overrides go by the bundled name, or the file name without its extension
+        let rom = match &opt.rom_path {
+            Some(path) => path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            None => opt.rom_name.clone(),
+        };
+        sdl.set_keymap(KeyConfig::load(path)?.keymap(&rom));
+    }
+    let mut context: Box<dyn Context> = sdl;
+    let mut quirks = opt.quirks;
+    let mut speed = opt.speed;
+    let mut recording = None;
+    if let Some(path) = &opt.replay {
+        let movie = Movie::from_bytes(&fs::read(path)?)?;
+        movie.check_rom(&rom_bytes(opt.rom_path.as_deref(), &opt.rom_name)?)?;
+        quirks = movie.quirks;
+        speed = movie.speed;
+        context = Box::new(ReplayContext::new(context, movie));
+    } else if opt.record.is_some() {
+        let rom = rom_bytes(opt.rom_path.as_deref(), &opt.rom_name)?;
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+        let recorder = RecordingContext::new(context, Movie::new(&rom, quirks, speed, seed));
+        recording = Some(recorder.movie());
+        context = Box::new(recorder);
+    }
+    let mut machine = Machine::with_quirks(context, quirks);
+    machine.set_speed(speed);
+    machine.set_rewind_seconds(opt.rewind_seconds);
+    machine.set_machine_calls(std::mem::take(&mut opt.machine_calls));
+    if let Some(path) = &opt.rom_path {
+        machine.load_rom_file(path)?;
+    } else {
+        machine.load_game(&opt.rom_name)?;
+    }
+    if let Some(path) = &opt.trace {
+        let filter = TraceFilter {
+            pcs: opt.trace_pc.clone(),
+            classes: opt.trace_class.clone(),
+        };
+        machine.set_tracer(Tracer::create(path, opt.trace_format)?.with_filter(filter));
+    }
+    if opt.profile || opt.heatmap.is_some() {
+        machine.set_profiler(Profiler::new());
+    }
+    // Save the movie and report on the profile even if the ROM hit an error
+    let result = if opt.debug {
+        debug(&mut machine)
+    } else {
+        machine.run().map_err(Into::into)
+    };
+    if let (Some(path), Some(movie)) = (&opt.record, recording) {
+        fs::write(path, movie.read().unwrap().to_bytes())?;
+        println!("Saved movie to {}", path.display());
+    }
+    if let Some(profiler) = machine.take_profiler() {
+        print!("{}", profiler.report(REPORT_LINES));
+        if let Some(path) = &opt.heatmap {
+            profiler.save_heatmap(path, HEATMAP_SCALE)?;
+            println!("Saved heatmap to {}", path.display());
+        }
+    }
+    result
+}
+
+/// The ROM to run, read from disk or the bundled set
+fn rom_bytes(path: Option<&Path>, name: &str) -> Result<Vec<u8>> {
+    match path {
+        Some(path) => Ok(fs::read(path)?),
+        None => ROMS
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Game {} not included", name)),
+    }
+}
+
+/// Read debugger commands from stdin until told to quit
+fn debug(machine: &mut Machine) -> Result<()> {
+    use DebugCommand::*;
+    let mut debugger = Debugger::new();
+    println!("{}\n", DEBUG_HELP);
+    print_listing(machine, &debugger);
+
+    let stdin = io::stdin();
+    loop {
+        print!("(chip8) ");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let command = match line.parse::<DebugCommand>() {
+            Ok(command) => command,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            }
+        };
+        let stop = match command {
+            Step(n) => Some(debugger.step(machine, n)),
+            StepOver => Some(debugger.step_over(machine)),
+            StepOut => Some(debugger.step_out(machine)),
+            Continue => Some(debugger.resume(machine)),
+            Break(addr) => {
+                debugger.add_breakpoint(addr);
+                None
+            }
+            DeleteBreak(addr) => {
+                if !debugger.remove_breakpoint(addr) {
+                    println!("No breakpoint at {:#05X}", addr);
+                }
+                None
+            }
+            Watch(addr, kind) => {
+                debugger.add_watchpoint(addr, kind);
+                None
+            }
+            DeleteWatch(addr) => {
+                if !debugger.remove_watchpoint(addr) {
+                    println!("No watchpoint at {:#05X}", addr);
+                }
+                None
+            }
+            When(condition) => {
+                debugger.add_condition(condition);
+                None
+            }
+            DeleteCondition(index) => {
+                if debugger.remove_condition(index).is_none() {
+                    println!("No condition {}", index);
+                }
+                None
+            }
+            Info => {
+                print_info(&debugger);
+                None
+            }
+            Registers => {
+                print_registers(machine);
+                None
+            }
+            List => {
+                print_listing(machine, &debugger);
+                None
+            }
+            Memory(addr, len) => {
+                print_memory(machine, addr, len);
+                None
+            }
+            Help => {
+                println!("{}", DEBUG_HELP);
+                None
+            }
+            Quit => return Ok(()),
+        };
+        if let Some(stop) = stop {
+            if stop == StopReason::Quit {
+                println!("Quitting...");
+                return Ok(());
+            }
+            if stop != StopReason::Done {
+                println!("{}", stop);
+            }
+            print_listing(machine, &debugger);
+        }
+        // Keep the window up to date with each stop
+        if machine.draw_flag {
+            machine.draw_graphics();
+        }
+    }
+}
+
+/// Show the instructions either side of pc, marking pc and breakpoints
+fn print_listing(machine: &Machine, debugger: &Debugger) {
+    let breakpoints: Vec<u16> = debugger.breakpoints().collect();
+    for offset in -3i32..=4 {
+        let addr = machine.pc as i32 + offset * 2;
+        if addr < 0 || addr > u16::MAX as i32 - 1 {
+            continue;
+        }
+        let addr = addr as u16;
+        let marker = if addr == machine.pc { "=>" } else { "  " };
+        let breakpoint = if breakpoints.contains(&addr) {
+            "*"
+        } else {
+            " "
+        };
+        let word = u16::from_be_bytes([
+            machine.memory()[addr as usize],
+            machine.memory()[addr as usize + 1],
+        ]);
+        match machine.opcode_at(addr) {
+            Ok(opcode) => println!(
+                "{}{} {:#06X}: {:04X}  {}",
+                marker, breakpoint, addr, word, opcode
+            ),
+            Err(_) => println!("{}{} {:#06X}: {:04X}  ???", marker, breakpoint, addr, word),
+        }
+    }
+}
+
+/// Show registers, the call stack and timers
+fn print_registers(machine: &Machine) {
+    for row in machine.registers().chunks(8).enumerate() {
+        let (row, values) = row;
+        let cells: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| format!("V{:X}={:02X}", row * 8 + i, v))
+            .collect();
+        println!("{}", cells.join(" "));
+    }
+    println!(
+        "I={:04X} PC={:04X} SP={} DT={:02X} ST={:02X}",
+        machine.idx, machine.pc, machine.sp, machine.delay_timer, machine.sound_timer
+    );
+    let stack: Vec<String> = machine
+        .call_stack()
+        .iter()
+        .map(|addr| format!("{:#05X}", addr))
+        .collect();
+    println!("Stack: [{}]", stack.join(", "));
+}
+
+/// Show `len` bytes of memory from `addr`, sixteen to a line
+fn print_memory(machine: &Machine, addr: u16, len: u16) {
+    let start = addr as usize;
+    let end = (start + len as usize).min(machine.memory().len());
+    for (i, line) in machine.memory()[start..end].chunks(16).enumerate() {
+        let bytes: Vec<String> = line.iter().map(|b| format!("{:02X}", b)).collect();
+        println!("{:#06X}: {}", start + i * 16, bytes.join(" "));
+    }
+}
+
+/// List breakpoints, watchpoints and conditions
+fn print_info(debugger: &Debugger) {
+    for addr in debugger.breakpoints() {
+        println!("Breakpoint at {:#05X}", addr);
+    }
+    for (addr, kind) in debugger.watchpoints() {
+        println!("Watchpoint ({:?}) at {:#06X}", kind, addr);
+    }
+    for (i, condition) in debugger.conditions().iter().enumerate() {
+        println!("Condition {}: {}", i, condition);
+    }
 }
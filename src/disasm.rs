@@ -0,0 +1,327 @@
+//! Turn ROM bytes back into assembly source.
+//!
+//! Instructions and data share the same address space, so the disassembler follows control flow from the
+//! start of the ROM to find out which bytes actually run.  Anything it never reaches is data.
+
+use crate::Opcode;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    fmt,
+};
+
+/// One item in the listing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+    /// An instruction reached by following control flow.
+    /// `long` is the address operand of an XO-CHIP `ld i, long`, which takes up the two bytes after the opcode.
+    Code {
+        addr: u16,
+        opcode: Opcode,
+        long: Option<u16>,
+    },
+    /// Bytes nothing jumps to.  Sprites are data regions loaded with `ld i`.
+    Data {
+        addr: u16,
+        bytes: Vec<u8>,
+        sprite: bool,
+    },
+}
+
+impl Line {
+    /// Where this line starts in memory.
+    pub fn addr(&self) -> u16 {
+        match self {
+            Line::Code { addr, .. } | Line::Data { addr, .. } => *addr,
+        }
+    }
+}
+
+/// A disassembled ROM.  `Display` writes it out as assembly source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disassembly {
+    /// Address the first byte was loaded at.
+    pub base_addr: u16,
+    /// Every line in address order, covering the whole ROM.
+    pub lines: Vec<Line>,
+    /// Addresses that get a label, because something jumps to or points at them.
+    pub labels: BTreeSet<u16>,
+}
+
+/// Disassemble `rom` as if it were loaded at `base_addr`, which is where execution starts.
+/// ```
+/// # use chip8::disassemble;
+/// # use pretty_assertions::assert_eq;
+/// let listing = disassemble(&[0x61, 0x20, 0x12, 0x00], 0x200).to_string();
+/// assert!(listing.contains("L0x200:"));
+/// assert!(listing.contains("ld v1, 0x20"));
+/// assert!(listing.contains("jp L0x200"));
+/// ```
+pub fn disassemble(rom: &[u8], base_addr: u16) -> Disassembly {
+    disassemble_with_entries(rom, base_addr, &[])
+}
+
+/// Disassemble `rom`, also following control flow from each of `entries`.
+/// Addresses seen running, like from `AccessMap::executed`, find code that's only reached through
+/// computed jumps.
+/// ```
+/// # use chip8::disassemble_with_entries;
+/// // jp v0, 0x202 - which goes to 0x204 with V0 at 2; (data); ld v1, 0x20
+/// let rom = [0xB2, 0x02, 0xFF, 0xFF, 0x61, 0x20];
+/// assert!(!chip8::disassemble(&rom, 0x200).to_string().contains("ld v1, 0x20"));
+/// assert!(disassemble_with_entries(&rom, 0x200, &[0x204]).to_string().contains("ld v1, 0x20"));
+/// ```
+pub fn disassemble_with_entries(rom: &[u8], base_addr: u16, entries: &[u16]) -> Disassembly {
+    let end = base_addr as u32 + rom.len() as u32;
+    let in_rom = |addr: u16| addr >= base_addr && (addr as u32) < end;
+    let word_at = |addr: u16| -> Option<u16> {
+        if in_rom(addr) && addr as u32 + 1 < end {
+            let i = (addr - base_addr) as usize;
+            Some(u16::from_be_bytes([rom[i], rom[i + 1]]))
+        } else {
+            None
+        }
+    };
+
+    // Address of each instruction found, with its length in bytes
+    let mut code: BTreeMap<u16, u16> = BTreeMap::new();
+    let mut targets = BTreeSet::new();
+    let mut sprites = BTreeSet::new();
+    let mut pending = vec![base_addr];
+    pending.extend_from_slice(entries);
+    while let Some(start) = pending.pop() {
+        let mut addr = start;
+        while !code.contains_key(&addr) {
+            let opcode = match word_at(addr).map(Opcode::try_from) {
+                Some(Ok(opcode)) => opcode,
+                _ => break,
+            };
+            let len = if opcode == Opcode::LongSetIdx {
+                match word_at(addr.wrapping_add(2)) {
+                    Some(target) => {
+                        sprites.insert(target);
+                        4
+                    }
+                    None => break,
+                }
+            } else {
+                2
+            };
+            code.insert(addr, len);
+            let next = addr.wrapping_add(len);
+
+            use Opcode::*;
+            match opcode {
+                Jump(target) | JumpTo(target) => {
+                    // Where a computed jump lands depends on V0, so the base address is only a guess
+                    targets.insert(target);
+                    pending.push(target);
+                    break;
+                }
+                Call(target) => {
+                    targets.insert(target);
+                    pending.push(target);
+                }
+                Return | Exit => break,
+                SkipIfEqVal(..)
+                | SkipIfNotEqVal(..)
+                | SkipIfMatchReg(..)
+                | SkipIfMismatchReg(..)
+                | SkipIfPressed(_)
+                | SkipIfNotPressed(_) => {
+                    let skip = if word_at(next) == Some(0xF000) { 4 } else { 2 };
+                    pending.push(next.wrapping_add(skip));
+                }
+                SetIdx(target) => {
+                    sprites.insert(target);
+                }
+                _ => {}
+            }
+            addr = next;
+        }
+    }
+
+    // Lay out the listing, anything between instructions is data
+    let mut lines = Vec::new();
+    // Count in u32 so a ROM running right up to 0xFFFF doesn't overflow
+    let mut next = base_addr as u32;
+    while next < end {
+        let addr = next as u16;
+        if let Some(&len) = code.get(&addr) {
+            let opcode = Opcode::try_from(word_at(addr).unwrap()).unwrap();
+            let long = if len == 4 { word_at(addr + 2) } else { None };
+            lines.push(Line::Code { addr, opcode, long });
+            next += len as u32;
+            continue;
+        }
+        // Break data at the next instruction or anything that would want a label
+        let mut stop = next + 1;
+        while stop < end
+            && !code.contains_key(&(stop as u16))
+            && !targets.contains(&(stop as u16))
+            && !sprites.contains(&(stop as u16))
+        {
+            stop += 1;
+        }
+        let start = (addr - base_addr) as usize;
+        let stop_idx = (stop - base_addr as u32) as usize;
+        lines.push(Line::Data {
+            addr,
+            bytes: rom[start..stop_idx].to_vec(),
+            sprite: sprites.contains(&addr),
+        });
+        next = stop;
+    }
+
+    // Only addresses at the start of a line can carry a label
+    let starts: BTreeSet<u16> = lines.iter().map(Line::addr).collect();
+    let labels = targets
+        .union(&sprites)
+        .filter(|a| starts.contains(a))
+        .copied()
+        .collect();
+
+    Disassembly {
+        base_addr,
+        lines,
+        labels,
+    }
+}
+
+impl Disassembly {
+    /// Name for an address - its label if it has one, otherwise plain hex.
+    fn name(&self, addr: u16) -> String {
+        if self.labels.contains(&addr) {
+            format!("L{:#05X}", addr)
+        } else {
+            format!("{:#05X}", addr)
+        }
+    }
+
+    /// An instruction's mnemonic, with labels swapped in for addresses.
+    fn mnemonic(&self, opcode: Opcode, long: Option<u16>) -> String {
+        use Opcode::*;
+        match opcode {
+            Jump(addr) => format!("jp {}", self.name(addr)),
+            Call(addr) => format!("call {}", self.name(addr)),
+            JumpTo(addr) => format!("jp v0, {}", self.name(addr)),
+            SetIdx(addr) => format!("ld i, {}", self.name(addr)),
+            LongSetIdx => format!("ld i, long {}", self.name(long.unwrap_or_default())),
+            _ => opcode.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "org {:#05X}", self.base_addr)?;
+        for line in &self.lines {
+            if self.labels.contains(&line.addr()) {
+                writeln!(f, "\nL{:#05X}:", line.addr())?;
+            }
+            match line {
+                Line::Code { addr, opcode, long } => {
+                    writeln!(
+                        f,
+                        "    {:<24}; {:#05X}",
+                        self.mnemonic(*opcode, *long),
+                        addr
+                    )?;
+                }
+                // One row per line, so the shape shows
+                Line::Data { bytes, sprite, .. } if *sprite => {
+                    for byte in bytes {
+                        let pixels: String = (0..8)
+                            .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+                            .collect();
+                        let db = format!("db {:#04X}", byte);
+                        writeln!(f, "    {:<24}; {}", db, pixels)?;
+                    }
+                }
+                Line::Data { bytes, .. } => {
+                    for row in bytes.chunks(8) {
+                        let row: Vec<String> = row.iter().map(|b| format!("{:#04X}", b)).collect();
+                        writeln!(f, "    db {}", row.join(", "))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ROMS;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_unreached_bytes_are_data() {
+        // ld i, sprite; drw v0, v0, 2; jp self; then a sprite that happens to decode as "ld v0, 0x3C"
+        let rom = [0xA2, 0x06, 0xD0, 0x02, 0x12, 0x04, 0x60, 0x3C];
+        let listing = disassemble(&rom, 0x200);
+        assert_eq!(listing.lines.len(), 4);
+        assert_eq!(
+            listing.lines[3],
+            Line::Data {
+                addr: 0x206,
+                bytes: vec![0x60, 0x3C],
+                sprite: true
+            }
+        );
+        let text = listing.to_string();
+        assert!(text.contains("ld i, L0x206"));
+        assert!(text.contains("db 0x3C                 ; ..####.."));
+    }
+
+    #[test]
+    fn test_skips_follow_both_paths() {
+        // se v0, 0; jp 0x208; ret; (data); ld v1, 1
+        let rom = [0x30, 0x00, 0x12, 0x08, 0x00, 0xEE, 0xFF, 0xFF, 0x61, 0x01];
+        let listing = disassemble(&rom, 0x200);
+        let code: Vec<u16> = listing
+            .lines
+            .iter()
+            .filter(|l| matches!(l, Line::Code { .. }))
+            .map(Line::addr)
+            .collect();
+        assert_eq!(code, vec![0x200, 0x202, 0x204, 0x208]);
+        assert_eq!(
+            listing.labels.iter().copied().collect::<Vec<_>>(),
+            vec![0x208]
+        );
+    }
+
+    #[test]
+    fn test_long_set_idx() {
+        let rom = [0xF0, 0x00, 0x02, 0x06, 0x00, 0xFD, 0xAA];
+        let listing = disassemble(&rom, 0x200);
+        assert_eq!(
+            listing.lines[0],
+            Line::Code {
+                addr: 0x200,
+                opcode: Opcode::LongSetIdx,
+                long: Some(0x206)
+            }
+        );
+        assert!(listing.to_string().contains("ld i, long L0x206"));
+    }
+
+    #[test]
+    fn test_bundled_roms_fully_covered() {
+        for (name, rom) in ROMS.iter() {
+            let listing = disassemble(rom, 0x200);
+            let mut addr = 0x200;
+            for line in &listing.lines {
+                assert_eq!(line.addr(), addr, "{}", name);
+                addr += match line {
+                    Line::Code { long: Some(_), .. } => 4,
+                    Line::Code { .. } => 2,
+                    Line::Data { bytes, .. } => bytes.len() as u16,
+                };
+            }
+            assert_eq!(addr as usize, 0x200 + rom.len(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! Screenshots and recordings of the screen.
+//!
+//! PBM needs nothing extra, PNG and animated GIF need the `capture` feature.
+
+use super::{machine::Screen, palette::Palette};
+use anyhow::{anyhow, Result};
+#[cfg(feature = "capture")]
+use std::io::Write;
+use std::{fs, path::Path};
+
+/// Shortest time a GIF frame should stay up, in milliseconds.
+/// Browsers stretch anything shorter to a tenth of a second, so quicker changes are merged together.
+#[cfg(feature = "capture")]
+const MIN_GIF_FRAME_MILLIS: u64 = 20;
+
+/// Pixel values of `screen` resized to `width` by `height`, row by row.
+/// Any other resolution is scaled to fit, so a recording can go on across a resolution switch.
+fn resample(screen: &Screen, width: u32, height: u32) -> Vec<u8> {
+    let mut ret = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            ret.push(screen.get(x * screen.width() / width, y * screen.height() / height));
+        }
+    }
+    ret
+}
+
+/// Plain PBM, `scale` image pixels to a screen pixel.  Every lit pixel is black, whatever its planes.
+/// ```
+/// # use chip8::{encode_pbm, Screen};
+/// # use pretty_assertions::assert_eq;
+/// let mut screen = Screen::new(2, 1);
+/// screen.toggle(1, 0, 1);
+/// assert_eq!(encode_pbm(&screen, 1), "P1\n2 1\n0 1\n");
+/// ```
+pub fn encode_pbm(screen: &Screen, scale: u32) -> String {
+    let (width, height) = (screen.width() * scale, screen.height() * scale);
+    let mut ret = format!("P1\n{} {}\n", width, height);
+    for row in resample(screen, width, height).chunks(width as usize) {
+        let row: Vec<&str> = row
+            .iter()
+            .map(|&p| if p != 0 { "1" } else { "0" })
+            .collect();
+        ret.push_str(&row.join(" "));
+        ret.push('\n');
+    }
+    ret
+}
+
+/// PNG in `palette`'s colours, `scale` image pixels to a screen pixel.
+#[cfg(feature = "capture")]
+pub fn encode_png(screen: &Screen, scale: u32, palette: &Palette) -> Result<Vec<u8>> {
+    let (width, height) = (screen.width() * scale, screen.height() * scale);
+    let mut ret = Vec::new();
+    let mut encoder = png::Encoder::new(&mut ret, width, height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette.to_bytes());
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&resample(screen, width, height))?;
+    writer.finish()?;
+    Ok(ret)
+}
+
+/// Write a screenshot to `path`, picking PBM or PNG from the extension.
+#[cfg_attr(not(feature = "capture"), allow(unused_variables))]
+pub fn save_screenshot<P: AsRef<Path>>(
+    screen: &Screen,
+    path: P,
+    scale: u32,
+    palette: &Palette,
+) -> Result<()> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase);
+    let bytes = match extension.as_deref() {
+        Some("pbm") => encode_pbm(screen, scale).into_bytes(),
+        #[cfg(feature = "capture")]
+        Some("png") => encode_png(screen, scale, palette)?,
+        _ => {
+            return Err(anyhow!(
+                "Can't save a screenshot as {} - use .pbm{}",
+                path.display(),
+                if cfg!(feature = "capture") {
+                    " or .png"
+                } else {
+                    ""
+                }
+            ));
+        }
+    };
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+/// Records frames into an animated GIF as they're drawn.
+/// The size is fixed by the first frame - later frames at another resolution are scaled to match.
+/// ```
+/// # use chip8::{GifRecorder, Palette, Screen};
+/// let mut gif = Vec::new();
+/// let mut recorder = GifRecorder::new(&mut gif, 2, Palette::default());
+/// let mut screen = Screen::default();
+/// recorder.add_frame(&screen, 0).unwrap();
+/// screen.toggle(0, 0, 1);
+/// recorder.add_frame(&screen, 100).unwrap();
+/// recorder.finish(200).unwrap();
+/// assert!(gif.starts_with(b"GIF89a"));
+/// ```
+#[cfg(feature = "capture")]
+pub struct GifRecorder<W: Write> {
+    output: Option<W>,
+    encoder: Option<gif::Encoder<W>>,
+    scale: u32,
+    palette: Palette,
+    /// Size of every frame, set by the first
+    size: Option<(u32, u32)>,
+    /// Most recent frame, held back until the next one says how long it was up for
+    pending: Option<(Vec<u8>, u64)>,
+}
+
+#[cfg(feature = "capture")]
+impl<W: Write> GifRecorder<W> {
+    /// Record into `output`, `scale` image pixels to a screen pixel.
+    pub fn new(output: W, scale: u32, palette: Palette) -> Self {
+        Self {
+            output: Some(output),
+            encoder: None,
+            scale: scale.max(1),
+            palette,
+            size: None,
+            pending: None,
+        }
+    }
+
+    /// Add a frame drawn at `now_millis`.  Repeats of the last frame just make it last longer.
+    pub fn add_frame(&mut self, screen: &Screen, now_millis: u64) -> Result<()> {
+        let (width, height) = match self.size {
+            Some(size) => size,
+            None => {
+                let size = (screen.width() * self.scale, screen.height() * self.scale);
+                let output = self.output.take().unwrap();
+                let mut encoder = gif::Encoder::new(
+                    output,
+                    size.0 as u16,
+                    size.1 as u16,
+                    &self.palette.to_bytes(),
+                )?;
+                encoder.set_repeat(gif::Repeat::Infinite)?;
+                self.encoder = Some(encoder);
+                self.size = Some(size);
+                size
+            }
+        };
+        let pixels = resample(screen, width, height);
+        match self.pending.take() {
+            Some((previous, since)) if previous == pixels => {
+                self.pending = Some((previous, since));
+            }
+            // Too soon to show - swap it in but keep the time it went up
+            Some((_, since)) if now_millis.saturating_sub(since) < MIN_GIF_FRAME_MILLIS => {
+                self.pending = Some((pixels, since));
+            }
+            Some((previous, since)) => {
+                self.write_frame(&previous, now_millis.saturating_sub(since))?;
+                self.pending = Some((pixels, now_millis));
+            }
+            None => self.pending = Some((pixels, now_millis)),
+        }
+        Ok(())
+    }
+
+    /// Write out the last frame, shown until `now_millis`, and finish the file.
+    pub fn finish(mut self, now_millis: u64) -> Result<()> {
+        if let Some((pixels, since)) = self.pending.take() {
+            self.write_frame(&pixels, now_millis.saturating_sub(since))?;
+        }
+        if let Some(encoder) = self.encoder.take() {
+            encoder.into_inner()?.flush()?;
+        }
+        Ok(())
+    }
+
+    fn write_frame(&mut self, pixels: &[u8], millis: u64) -> Result<()> {
+        let (width, height) = self.size.unwrap();
+        let mut frame = gif::Frame::from_indexed_pixels(width as u16, height as u16, pixels, None);
+        // GIF delays are in hundredths of a second
+        frame.delay = ((millis + 5) / 10).clamp(2, u16::MAX as u64) as u16;
+        self.encoder.as_mut().unwrap().write_frame(&frame)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::machine::{HIRES_PIXEL_COLS, HIRES_PIXEL_ROWS};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_resample_across_resolutions() {
+        let mut hires = Screen::hires();
+        hires.toggle(2, 2, 1);
+        hires.toggle(3, 3, 1);
+        // Halving keeps the top left pixel of each 2x2 block
+        let lores = resample(&hires, HIRES_PIXEL_COLS / 2, HIRES_PIXEL_ROWS / 2);
+        assert_eq!(lores[HIRES_PIXEL_COLS as usize / 2 + 1], 1);
+        assert_eq!(lores.iter().filter(|&&p| p != 0).count(), 1);
+    }
+
+    #[test]
+    fn test_pbm_scales() {
+        let mut screen = Screen::new(2, 1);
+        screen.toggle(0, 0, 1);
+        assert_eq!(encode_pbm(&screen, 2), "P1\n4 2\n1 1 0 0\n1 1 0 0\n");
+    }
+
+    #[cfg(feature = "capture")]
+    #[test]
+    fn test_png_uses_palette() {
+        let mut screen = Screen::default();
+        screen.toggle(0, 0, 1);
+        let palette: Palette = "#102030,#405060".parse().unwrap();
+        let png = encode_png(&screen, 3, &palette).unwrap();
+        let decoder = png::Decoder::new(&png[..]);
+        let reader = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!((info.width, info.height), (64 * 3, 32 * 3));
+        assert_eq!(
+            &info.palette.as_ref().unwrap()[..6],
+            &[0x10, 0x20, 0x30, 0x40, 0x50, 0x60]
+        );
+    }
+
+    #[test]
+    fn test_unknown_screenshot_format() {
+        let error = save_screenshot(&Screen::default(), "shot.bmp", 1, &Palette::default())
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("Can't save a screenshot as shot.bmp"));
+    }
+}
@@ -0,0 +1,270 @@
+//! A context with no window, speaker or keyboard, for running ROMs in tests, CI and servers.
+
+use super::*;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, RwLock},
+};
+
+/// A scripted change to the keypad, applied once the virtual clock reaches `at_millis`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// Virtual time to apply the event at, in milliseconds since the context was created.
+    pub at_millis: u64,
+    /// Keypad key 0x0-0xF.
+    pub key: u8,
+    /// Whether the key goes down or up.
+    pub pressed: bool,
+}
+
+impl KeyEvent {
+    /// Push `key` down at `at_millis`.
+    pub fn press(at_millis: u64, key: u8) -> Self {
+        Self {
+            at_millis,
+            key,
+            pressed: true,
+        }
+    }
+
+    /// Let `key` up at `at_millis`.
+    pub fn release(at_millis: u64, key: u8) -> Self {
+        Self {
+            at_millis,
+            key,
+            pressed: false,
+        }
+    }
+}
+
+/// Starting state for `next_random` from a seed.
+pub(super) fn seed_random(seed: u64) -> u64 {
+    // xorshift gets stuck on zero
+    if seed == 0 {
+        0x9E37_79B9_7F4A_7C15
+    } else {
+        seed
+    }
+}
+
+/// Next byte from a xorshift64* generator - plenty for games.
+pub(super) fn next_random(rng: &mut u64) -> u8 {
+    let mut x = *rng;
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    *rng = x;
+    (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+}
+
+/// Everything a `HeadlessContext` records or replays.
+#[derive(Debug)]
+struct HeadlessState {
+    /// Virtual time in milliseconds, only moved along by `sleep`
+    clock: u64,
+    /// xorshift64* state, never zero
+    rng: u64,
+    /// Pending key events, ordered by time
+    script: VecDeque<KeyEvent>,
+    /// Current keypad state
+    keys: [bool; NUM_KEYS],
+    /// Every frame drawn so far, oldest first
+    frames: VecDeque<Screen>,
+    /// Drop the oldest frames past this many
+    frame_limit: Option<usize>,
+    /// Virtual start and stop time of each tone, no stop yet while it's sounding
+    tones: Vec<(u64, Option<u64>)>,
+    /// Report a quit once the clock gets here
+    quit_at: Option<u64>,
+    /// Pending frontend commands
+    commands: VecDeque<Command>,
+    /// Encoded save states by name, only kept in memory
+    states: HashMap<String, Vec<u8>>,
+}
+
+impl HeadlessState {
+    /// Apply every scripted key event that's come due on the clock.
+    fn apply_due_keys(&mut self) {
+        while let Some(&event) = self.script.front() {
+            if event.at_millis > self.clock {
+                break;
+            }
+            self.script.pop_front();
+            self.keys[event.key as usize] = event.pressed;
+        }
+    }
+}
+
+/// Runs a machine without any real I/O.
+/// Frames are recorded instead of displayed, key input is scripted ahead of time, random numbers come
+/// from a seeded generator and sleeping just moves a virtual clock along, so runs are repeatable and fast.
+/// Save states are kept in memory.
+///
+/// Clones share the same state, so keep one to inspect after handing the other to a `Machine`.
+/// ```
+/// # use chip8::{HeadlessContext, KeyEvent, Machine};
+/// let context = HeadlessContext::new(0xC8);
+/// context.push_key_event(KeyEvent::press(100, 0x5));
+/// context.push_key_event(KeyEvent::release(200, 0x5));
+/// context.quit_after(1000);
+///
+/// let mut machine = Machine::new(Box::new(context.clone()));
+/// machine.load_game("pong").unwrap();
+/// machine.run().unwrap();
+///
+/// assert_eq!(context.now(), 1000);
+/// assert!(context.last_frame().is_some());
+/// ```
+#[derive(Debug, Clone)]
+pub struct HeadlessContext {
+    state: Arc<RwLock<HeadlessState>>,
+}
+
+impl HeadlessContext {
+    /// A fresh context at virtual time zero.  The same `seed` always produces the same random bytes.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: Arc::new(RwLock::new(HeadlessState {
+                clock: 0,
+                rng: seed_random(seed),
+                script: VecDeque::new(),
+                keys: [false; NUM_KEYS],
+                frames: VecDeque::new(),
+                frame_limit: None,
+                tones: Vec::new(),
+                quit_at: None,
+                commands: VecDeque::new(),
+                states: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Schedule a key press or release.  Events for keys past 0xF are ignored.
+    pub fn push_key_event(&self, event: KeyEvent) {
+        if event.key as usize >= NUM_KEYS {
+            log::warn!(
+                "Ignoring event for key {:#X}, which isn't on the keypad",
+                event.key
+            );
+            return;
+        }
+        let mut state = self.state.write().unwrap();
+        // Keep the script in order, events at the same time apply in the order they were pushed
+        let position = state
+            .script
+            .iter()
+            .position(|e| e.at_millis > event.at_millis)
+            .unwrap_or_else(|| state.script.len());
+        state.script.insert(position, event);
+    }
+
+    /// Queue up a `Command` for the machine to pick up on its next step.
+    pub fn push_command(&self, command: Command) {
+        self.state.write().unwrap().commands.push_back(command);
+    }
+
+    /// Ask the machine to quit once the virtual clock reaches `millis`.
+    pub fn quit_after(&self, millis: u64) {
+        self.state.write().unwrap().quit_at = Some(millis);
+    }
+
+    /// Only keep the most recent `limit` frames, to bound memory on long runs.
+    pub fn set_frame_limit(&self, limit: usize) {
+        let mut state = self.state.write().unwrap();
+        state.frame_limit = Some(limit);
+        while state.frames.len() > limit {
+            state.frames.pop_front();
+        }
+    }
+
+    /// Current virtual time in milliseconds.
+    pub fn now(&self) -> u64 {
+        self.state.read().unwrap().clock
+    }
+
+    /// Every recorded frame, oldest first.
+    pub fn frames(&self) -> Vec<Screen> {
+        self.state.read().unwrap().frames.iter().cloned().collect()
+    }
+
+    /// The most recently drawn frame, if anything has been drawn.
+    pub fn last_frame(&self) -> Option<Screen> {
+        self.state.read().unwrap().frames.back().cloned()
+    }
+
+    /// Remove and return every recorded frame, oldest first.
+    pub fn take_frames(&self) -> Vec<Screen> {
+        self.state.write().unwrap().frames.drain(..).collect()
+    }
+
+    /// Virtual start and stop time of every tone so far.  The last has no stop if it's still sounding.
+    pub fn tones(&self) -> Vec<(u64, Option<u64>)> {
+        self.state.read().unwrap().tones.clone()
+    }
+}
+
+impl Context for HeadlessContext {
+    fn init(&mut self) {}
+    fn start_tone(&mut self, _audio: &AudioPattern) {
+        let mut state = self.state.write().unwrap();
+        let now = state.clock;
+        // A new pattern mid-tone carries on the same tone
+        if !matches!(state.tones.last(), Some((_, None))) {
+            state.tones.push((now, None));
+        }
+    }
+    fn stop_tone(&mut self) {
+        let mut state = self.state.write().unwrap();
+        let now = state.clock;
+        if let Some((_, stop @ None)) = state.tones.last_mut() {
+            *stop = Some(now);
+        }
+    }
+    fn listen_for_input(&mut self) -> bool {
+        let mut state = self.state.write().unwrap();
+        state.apply_due_keys();
+        matches!(state.quit_at, Some(t) if state.clock >= t)
+    }
+    fn draw_graphics(&mut self, screen: &Screen) {
+        let mut state = self.state.write().unwrap();
+        state.frames.push_back(screen.clone());
+        if let Some(limit) = state.frame_limit {
+            while state.frames.len() > limit {
+                state.frames.pop_front();
+            }
+        }
+    }
+    fn get_key_state(&self) -> [bool; NUM_KEYS] {
+        let mut state = self.state.write().unwrap();
+        state.apply_due_keys();
+        state.keys
+    }
+    fn random_byte(&self) -> u8 {
+        next_random(&mut self.state.write().unwrap().rng)
+    }
+    // Scripted keys go down and up as the clock passes them, whether or not anything polls for input
+    fn sleep(&self, millis: u64) {
+        let mut state = self.state.write().unwrap();
+        state.clock += millis;
+        state.apply_due_keys();
+    }
+    fn now(&self) -> u64 {
+        let mut state = self.state.write().unwrap();
+        state.apply_due_keys();
+        state.clock
+    }
+    fn start_frame(&mut self) {
+        self.state.write().unwrap().apply_due_keys();
+    }
+    fn next_command(&mut self) -> Option<Command> {
+        self.state.write().unwrap().commands.pop_front()
+    }
+    fn store_state(&mut self, name: &str, bytes: &[u8]) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        state.states.insert(name.to_string(), bytes.to_vec());
+        Ok(())
+    }
+    fn fetch_state(&mut self, name: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.state.read().unwrap().states.get(name).cloned())
+    }
+}
@@ -1,40 +1,90 @@
 //! This is the interface for a machine to interact with the outside
 use super::machine::*;
+use anyhow::{anyhow, Result};
 
+mod headless;
+mod movie;
 #[cfg(feature = "sdl")]
 mod sdl;
 
 #[cfg(feature = "wasm")]
 pub mod wasm;
 
+pub use headless::{HeadlessContext, KeyEvent};
+pub use movie::{Movie, RecordingContext, ReplayContext};
 #[cfg(feature = "sdl")]
 pub use sdl::SdlContext;
 
 #[cfg(test)]
 pub use test::TestContext;
 
+/// Requests from the user to the emulator itself, rather than to the running program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Snapshot the machine into the numbered slot.
+    SaveState(u8),
+    /// Restore the machine from the numbered slot.
+    LoadState(u8),
+    /// Stop running the program, or start again.
+    TogglePause,
+    /// Run one frame and pause.
+    FrameAdvance,
+    /// Fast-forward at `TURBO_FACTOR` times the set speed, or go back to normal.
+    SetTurbo(bool),
+    /// Run about a quarter faster.
+    SpeedUp,
+    /// Run about a fifth slower.
+    SpeedDown,
+    /// Run this many instructions per second.
+    SetSpeed(u64),
+    /// Step backwards through recent frames, or stop and carry on from there.
+    SetRewind(bool),
+}
+
 /// A Context allows the Machine to interact with a real output screen, speaker, and keyboard.
 /// It also handles random number generation.
+/// Implement this to drive the machine from something other than the bundled SDL, WebAssembly and headless contexts.
 pub trait Context {
     /// Call once to initalize systems and prepare to loop
     fn init(&mut self);
-    /// Produce a beep sound
-    fn beep(&self);
+    /// Start sounding the buzzer, looping the program's audio pattern until `stop_tone`.
+    /// Called again without a stop in between if the program changes the pattern mid-tone.
+    /// Plain CHIP-8 programs never touch the pattern, so they all get the default tone.
+    fn start_tone(&mut self, audio: &AudioPattern);
+    /// Silence the buzzer.
+    fn stop_tone(&mut self);
     /// Gather input for the tick, return true if user requested a quit
     fn listen_for_input(&mut self) -> bool;
-    /// Draw the current stored screen state out to the real screen
-    fn draw_graphics(&mut self, screen: Screen);
+    /// Draw the current stored screen state out to the real screen.
+    /// The resolution can change between calls when a program switches between low and high resolution.
+    fn draw_graphics(&mut self, screen: &Screen);
     /// Retreive the current real-world key state
     fn get_key_state(&self) -> [bool; NUM_KEYS];
     /// Get a random byte
     fn random_byte(&self) -> u8;
     /// Sleep for a number of milliseconds
     fn sleep(&self, millis: u64);
+    /// Milliseconds since some fixed point, from a clock that never goes backwards.  Paces the CPU.
+    fn now(&self) -> u64;
+    /// Called on every 60Hz tick of emulated time, just before the machine reads the keypad again.
+    fn start_frame(&mut self) {}
+    /// Take the next pending `Command`, if the user asked for anything.  Polled once per step.
+    fn next_command(&mut self) -> Option<Command> {
+        None
+    }
+    /// Persist an encoded save state under `name`, replacing any previous one.
+    fn store_state(&mut self, _name: &str, _bytes: &[u8]) -> Result<()> {
+        Err(anyhow!("Save states are not supported here"))
+    }
+    /// Retrieve the encoded save state stored under `name`, if there is one.
+    fn fetch_state(&mut self, _name: &str) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use std::time::Duration;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
     use super::*;
 
@@ -44,17 +94,18 @@ mod test {
 
     impl TestContext {
         pub fn new() -> Box<Self> {
-            Box::new(Self::default())
+            Box::new(Self)
         }
     }
 
     impl Context for TestContext {
         fn init(&mut self) {}
-        fn beep(&self) {}
+        fn start_tone(&mut self, _audio: &AudioPattern) {}
+        fn stop_tone(&mut self) {}
         fn listen_for_input(&mut self) -> bool {
             false
         }
-        fn draw_graphics(&mut self, _screen: Screen) {}
+        fn draw_graphics(&mut self, _screen: &Screen) {}
         fn get_key_state(&self) -> [bool; NUM_KEYS] {
             [false; NUM_KEYS]
         }
@@ -64,5 +115,11 @@ mod test {
         fn sleep(&self, millis: u64) {
             std::thread::sleep(Duration::from_millis(millis));
         }
+        fn now(&self) -> u64 {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64
+        }
     }
 }
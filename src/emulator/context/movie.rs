@@ -0,0 +1,376 @@
+//! Input recordings, and contexts that make and replay them.
+//!
+//! The keypad is only allowed to change at the start of each 60Hz frame, and random bytes come from a seeded
+//! generator, so replaying the same movie against the same ROM, quirks and speed runs exactly the same
+//! instructions with exactly the same inputs.
+
+use super::super::quirks::{IndexIncrement, Quirks};
+use super::headless::{next_random, seed_random};
+use super::*;
+use std::{
+    cell::Cell,
+    sync::{Arc, RwLock},
+};
+
+/// Identifies an encoded movie.
+const MOVIE_MAGIC: &[u8; 4] = b"C8MV";
+/// Bump whenever the encoding changes.
+pub const MOVIE_VERSION: u16 = 1;
+/// Bytes before the first frame.
+const HEADER_LEN: usize = 36;
+
+/// A recorded session - what it ran, how, and what was pressed on every frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    /// `Movie::hash_rom` of the ROM it was recorded against.
+    pub rom_hash: u64,
+    pub quirks: Quirks,
+    /// Instructions per second, which fixes how many instructions run each frame.
+    pub speed: u64,
+    /// Seed for every random byte the program asks for.
+    pub seed: u64,
+    /// Keypad state for each frame in order, bit N set while key N is down.
+    pub frames: Vec<u16>,
+}
+
+impl Movie {
+    /// An empty movie, ready to record a run of `rom`.
+    pub fn new(rom: &[u8], quirks: Quirks, speed: u64, seed: u64) -> Self {
+        Self {
+            rom_hash: Self::hash_rom(rom),
+            quirks,
+            speed,
+            seed,
+            frames: Vec::new(),
+        }
+    }
+
+    /// 64-bit FNV-1a hash of a ROM, to tell whether a movie belongs to it.
+    pub fn hash_rom(rom: &[u8]) -> u64 {
+        rom.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+        })
+    }
+
+    /// Make sure this movie was recorded against `rom` - any other ROM would go out of sync.
+    pub fn check_rom(&self, rom: &[u8]) -> Result<()> {
+        let hash = Self::hash_rom(rom);
+        if hash != self.rom_hash {
+            return Err(anyhow!(
+                "Movie was recorded against ROM {:016X}, not {:016X}",
+                self.rom_hash,
+                hash
+            ));
+        }
+        Ok(())
+    }
+
+    /// Encode in the binary movie format.  All numbers are little-endian.
+    ///
+    /// | Field     | Encoding                                                          |
+    /// |-----------|-------------------------------------------------------------------|
+    /// | magic     | `C8MV`                                                            |
+    /// | version   | u16                                                               |
+    /// | rom_hash  | u64                                                               |
+    /// | quirks    | u8 flags - shift, jump, overflow, clip from bit 0 - then u8 index |
+    /// | speed     | u64                                                               |
+    /// | seed      | u64                                                               |
+    /// | frames    | u32 count, then a u16 keypad bitmask each                         |
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(HEADER_LEN + self.frames.len() * 2);
+        ret.extend_from_slice(MOVIE_MAGIC);
+        ret.extend_from_slice(&MOVIE_VERSION.to_le_bytes());
+        ret.extend_from_slice(&self.rom_hash.to_le_bytes());
+        let quirks = &self.quirks;
+        ret.push(
+            quirks.shift_uses_vy as u8
+                | (quirks.jump_uses_vx as u8) << 1
+                | (quirks.idx_overflow_sets_vf as u8) << 2
+                | (quirks.clip_sprites as u8) << 3,
+        );
+        ret.push(match quirks.load_store_idx {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::ByX => 1,
+            IndexIncrement::ByXPlusOne => 2,
+        });
+        ret.extend_from_slice(&self.speed.to_le_bytes());
+        ret.extend_from_slice(&self.seed.to_le_bytes());
+        ret.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        for keys in &self.frames {
+            ret.extend_from_slice(&keys.to_le_bytes());
+        }
+        ret
+    }
+
+    /// Decode the binary format written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < HEADER_LEN {
+            return Err(anyhow!("Movie is truncated"));
+        }
+        if &bytes[0..4] != MOVIE_MAGIC {
+            return Err(anyhow!("Not a movie"));
+        }
+        let u64_at = |i: usize| {
+            let mut word = [0; 8];
+            word.copy_from_slice(&bytes[i..i + 8]);
+            u64::from_le_bytes(word)
+        };
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != MOVIE_VERSION {
+            return Err(anyhow!("Unsupported movie version {}", version));
+        }
+        let flags = bytes[14];
+        let load_store_idx = match bytes[15] {
+            0 => IndexIncrement::Unchanged,
+            1 => IndexIncrement::ByX,
+            2 => IndexIncrement::ByXPlusOne,
+            n => return Err(anyhow!("Unknown index increment quirk {}", n)),
+        };
+        let quirks = Quirks {
+            shift_uses_vy: flags & 0b0001 != 0,
+            load_store_idx,
+            jump_uses_vx: flags & 0b0010 != 0,
+            idx_overflow_sets_vf: flags & 0b0100 != 0,
+            clip_sprites: flags & 0b1000 != 0,
+        };
+        let count = u32::from_le_bytes([bytes[32], bytes[33], bytes[34], bytes[35]]) as usize;
+        let body = &bytes[HEADER_LEN..];
+        if body.len() != count * 2 {
+            return Err(anyhow!(
+                "Movie should have {} frames, but has {} bytes of them",
+                count,
+                body.len()
+            ));
+        }
+        Ok(Self {
+            rom_hash: u64_at(6),
+            quirks,
+            speed: u64_at(16),
+            seed: u64_at(24),
+            frames: body
+                .chunks(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect(),
+        })
+    }
+}
+
+/// Pack a keypad state into a bitmask.
+fn keys_to_mask(keys: &[bool; NUM_KEYS]) -> u16 {
+    keys.iter()
+        .enumerate()
+        .fold(0, |mask, (i, &down)| mask | (down as u16) << i)
+}
+
+/// Unpack a keypad bitmask.
+fn mask_to_keys(mask: u16) -> [bool; NUM_KEYS] {
+    let mut keys = [false; NUM_KEYS];
+    for (i, key) in keys.iter_mut().enumerate() {
+        *key = mask & (1 << i) != 0;
+    }
+    keys
+}
+
+/// Commands that change how many instructions run per frame, or jump the machine somewhere else entirely,
+/// would send a replay out of sync.
+fn keeps_sync(command: Command) -> bool {
+    matches!(
+        command,
+        Command::SaveState(_) | Command::TogglePause | Command::FrameAdvance | Command::SetTurbo(_)
+    )
+}
+
+/// Wraps another context and records a `Movie` of the session.
+/// The keypad is read from the inner context once per frame and held there, and random bytes come from
+/// the movie's seed.  Commands that would break sync, like loading a state, are ignored.
+///
+/// Start recording on a fresh machine, before anything runs.
+/// ```
+/// # use chip8::{HeadlessContext, KeyEvent, Machine, Movie, RecordingContext, ReplayContext, Quirks, ROMS};
+/// # use pretty_assertions::assert_eq;
+/// let rom = ROMS.get("pong").unwrap();
+/// let live = HeadlessContext::new(1);
+/// live.push_key_event(KeyEvent::press(100, 0x1));
+/// live.push_key_event(KeyEvent::release(400, 0x1));
+/// live.quit_after(1000);
+/// let recorder = RecordingContext::new(Box::new(live.clone()), Movie::new(rom, Quirks::default(), 500, 0xC8));
+/// let recording = recorder.movie();
+/// let mut machine = Machine::new(Box::new(recorder));
+/// machine.load_rom(rom).unwrap();
+/// machine.run().unwrap();
+///
+/// // Play it back somewhere else, with nothing scripted
+/// let movie = recording.read().unwrap().clone();
+/// movie.check_rom(rom).unwrap();
+/// let replay = HeadlessContext::new(2);
+/// let mut machine = Machine::with_quirks(Box::new(ReplayContext::new(Box::new(replay.clone()), movie.clone())), movie.quirks);
+/// machine.set_speed(movie.speed);
+/// machine.load_rom(rom).unwrap();
+/// machine.run().unwrap();
+/// assert_eq!(replay.last_frame(), live.last_frame());
+/// ```
+pub struct RecordingContext {
+    inner: Box<dyn Context>,
+    movie: Arc<RwLock<Movie>>,
+    /// Keypad as of the start of this frame
+    keys: [bool; NUM_KEYS],
+    rng: Cell<u64>,
+}
+
+impl RecordingContext {
+    /// Record into `movie`, which should have no frames yet.
+    pub fn new(inner: Box<dyn Context>, movie: Movie) -> Self {
+        let rng = Cell::new(seed_random(movie.seed));
+        Self {
+            inner,
+            movie: Arc::new(RwLock::new(movie)),
+            keys: [false; NUM_KEYS],
+            rng,
+        }
+    }
+
+    /// The movie so far.  Keep hold of this to save it once the machine is done.
+    pub fn movie(&self) -> Arc<RwLock<Movie>> {
+        Arc::clone(&self.movie)
+    }
+}
+
+impl Context for RecordingContext {
+    fn init(&mut self) {
+        self.inner.init();
+    }
+    fn start_tone(&mut self, audio: &AudioPattern) {
+        self.inner.start_tone(audio);
+    }
+    fn stop_tone(&mut self) {
+        self.inner.stop_tone();
+    }
+    fn listen_for_input(&mut self) -> bool {
+        self.inner.listen_for_input()
+    }
+    fn draw_graphics(&mut self, screen: &Screen) {
+        self.inner.draw_graphics(screen);
+    }
+    fn get_key_state(&self) -> [bool; NUM_KEYS] {
+        self.keys
+    }
+    fn random_byte(&self) -> u8 {
+        let mut rng = self.rng.get();
+        let ret = next_random(&mut rng);
+        self.rng.set(rng);
+        ret
+    }
+    fn sleep(&self, millis: u64) {
+        self.inner.sleep(millis);
+    }
+    fn now(&self) -> u64 {
+        self.inner.now()
+    }
+    fn start_frame(&mut self) {
+        self.inner.start_frame();
+        self.keys = self.inner.get_key_state();
+        self.movie
+            .write()
+            .unwrap()
+            .frames
+            .push(keys_to_mask(&self.keys));
+    }
+    fn next_command(&mut self) -> Option<Command> {
+        while let Some(command) = self.inner.next_command() {
+            if keeps_sync(command) {
+                return Some(command);
+            }
+            eprintln!("Ignoring {:?} while recording a movie", command);
+        }
+        None
+    }
+    fn store_state(&mut self, name: &str, bytes: &[u8]) -> Result<()> {
+        self.inner.store_state(name, bytes)
+    }
+    fn fetch_state(&mut self, name: &str) -> Result<Option<Vec<u8>>> {
+        self.inner.fetch_state(name)
+    }
+}
+
+/// Wraps another context and plays a `Movie` back through it.
+/// Only drawing, sound and the clock go to the inner context - the keypad and random bytes come from the movie.
+/// Reports a quit once every recorded frame has played.
+///
+/// Build the machine with the movie's quirks and speed, and load the ROM it was recorded against.
+pub struct ReplayContext {
+    inner: Box<dyn Context>,
+    movie: Movie,
+    /// Frames started so far
+    frame: usize,
+    keys: [bool; NUM_KEYS],
+    rng: Cell<u64>,
+}
+
+impl ReplayContext {
+    /// Play back `movie`.
+    pub fn new(inner: Box<dyn Context>, movie: Movie) -> Self {
+        let rng = Cell::new(seed_random(movie.seed));
+        Self {
+            inner,
+            movie,
+            frame: 0,
+            keys: [false; NUM_KEYS],
+            rng,
+        }
+    }
+}
+
+impl Context for ReplayContext {
+    fn init(&mut self) {
+        self.inner.init();
+    }
+    fn start_tone(&mut self, audio: &AudioPattern) {
+        self.inner.start_tone(audio);
+    }
+    fn stop_tone(&mut self) {
+        self.inner.stop_tone();
+    }
+    fn listen_for_input(&mut self) -> bool {
+        let quit = self.inner.listen_for_input();
+        quit || self.frame >= self.movie.frames.len()
+    }
+    fn draw_graphics(&mut self, screen: &Screen) {
+        self.inner.draw_graphics(screen);
+    }
+    fn get_key_state(&self) -> [bool; NUM_KEYS] {
+        self.keys
+    }
+    fn random_byte(&self) -> u8 {
+        let mut rng = self.rng.get();
+        let ret = next_random(&mut rng);
+        self.rng.set(rng);
+        ret
+    }
+    fn sleep(&self, millis: u64) {
+        self.inner.sleep(millis);
+    }
+    fn now(&self) -> u64 {
+        self.inner.now()
+    }
+    fn start_frame(&mut self) {
+        self.inner.start_frame();
+        let mask = self.movie.frames.get(self.frame).copied().unwrap_or(0);
+        self.keys = mask_to_keys(mask);
+        self.frame += 1;
+    }
+    fn next_command(&mut self) -> Option<Command> {
+        while let Some(command) = self.inner.next_command() {
+            if keeps_sync(command) {
+                return Some(command);
+            }
+            eprintln!("Ignoring {:?} while replaying a movie", command);
+        }
+        None
+    }
+    fn store_state(&mut self, name: &str, bytes: &[u8]) -> Result<()> {
+        self.inner.store_state(name, bytes)
+    }
+    fn fetch_state(&mut self, name: &str) -> Result<Option<Vec<u8>>> {
+        self.inner.fetch_state(name)
+    }
+}
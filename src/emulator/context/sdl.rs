@@ -1,107 +1,292 @@
+use super::super::{
+    capture::{save_screenshot, GifRecorder},
+    keymap::KeyMap,
+    render::{RenderOptions, Renderer},
+};
 use super::*;
 use sdl2::{
     self,
-    audio::{AudioCallback, AudioSpecDesired},
+    audio::{AudioCallback, AudioDevice, AudioSpecDesired},
+    controller::GameController,
     event::Event,
     keyboard::Keycode,
-    pixels::Color,
+    pixels::{Color, PixelFormatEnum},
     rect::Rect,
-    render::WindowCanvas,
-    AudioSubsystem, EventPump,
+    render::{Texture, TextureCreator, WindowCanvas},
+    video::WindowContext,
+    EventPump, GameControllerSubsystem,
+};
+use std::{
+    collections::{HashSet, VecDeque},
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use std::time::Duration;
-
-/// Helper to converd an SDL Keycode to a normalized keypad value to store
-fn keycode_to_keypad(keycode: Option<Keycode>) -> Option<u8> {
-    // First, make sure it's not a None
-    keycode?;
-
-    use Keycode::*;
-    let c = match keycode.unwrap() {
-        Num1 => '1',
-        Num2 => '2',
-        Num3 => '3',
-        Num4 => '4',
-        Q => 'Q',
-        W => 'W',
-        E => 'E',
-        R => 'R',
-        A => 'A',
-        S => 'S',
-        D => 'D',
-        F => 'F',
-        Z => 'Z',
-        X => 'X',
-        C => 'C',
-        V => 'V',
-        _ => '.', // this will turn into an error, end then a None
-    };
-    keyboard_to_keypad(c).ok()
+
+/// Image pixels per CHIP-8 pixel in screenshots and recordings.
+const CAPTURE_SCALE: u32 = 4;
+
+/// SDL's name for RGBA bytes in memory, which depends on the byte order
+#[cfg(target_endian = "little")]
+const RGBA_FORMAT: PixelFormatEnum = PixelFormatEnum::ABGR8888;
+#[cfg(target_endian = "big")]
+const RGBA_FORMAT: PixelFormatEnum = PixelFormatEnum::RGBA8888;
+
+/// How far a stick has to be pushed before it counts as held in that direction, out of 32767.
+const AXIS_THRESHOLD: i16 = 16_384;
+
+/// Helper to pick a quicksave slot with F1-F4
+fn keycode_to_save_slot(keycode: Option<Keycode>) -> Option<u8> {
+    match keycode? {
+        Keycode::F1 => Some(1),
+        Keycode::F2 => Some(2),
+        Keycode::F3 => Some(3),
+        Keycode::F4 => Some(4),
+        _ => None,
+    }
+}
+
+/// Helper for the emulator controls: Space pauses, Period advances a frame, Minus and Equals change speed
+fn keycode_to_command(keycode: Option<Keycode>) -> Option<Command> {
+    match keycode? {
+        Keycode::Space => Some(Command::TogglePause),
+        Keycode::Period => Some(Command::FrameAdvance),
+        Keycode::Minus => Some(Command::SpeedDown),
+        Keycode::Equals => Some(Command::SpeedUp),
+        _ => None,
+    }
 }
 
 /// Sdl2 context
 pub struct SdlContext {
-    audio: AudioSubsystem,
+    /// The buzzer, open the whole time and paused while it's quiet
+    audio: AudioDevice<PatternWave>,
     canvas: WindowCanvas,
+    /// Makes textures for the canvas, which they can't outlive
+    texture_creator: &'static TextureCreator<WindowContext>,
+    /// The screen, streamed in on every draw.  Remade when the screen or window changes size.
+    texture: Option<Texture<'static>>,
+    /// RGBA bytes for the texture, kept between draws to save reallocating
+    pixels: Vec<u8>,
     event_pump: EventPump,
     key_state: Keys,
+    /// Which keys and buttons press which keypad keys
+    keymap: KeyMap,
+    controller_subsystem: GameControllerSubsystem,
+    /// Every controller plugged in, kept open to get their events
+    controllers: Vec<GameController>,
+    /// Stick directions currently held past the threshold, like `leftx-`
+    held_axes: HashSet<String>,
+    /// Directory quicksaves are written to
+    save_dir: PathBuf,
+    /// Quicksave slot used by F5/F9
+    save_slot: u8,
+    /// Commands from hotkeys, waiting for the machine to pick up
+    commands: VecDeque<Command>,
+    /// When the context was created, the zero point for `now`
+    start: Instant,
+    /// Last screen drawn, for screenshots and to keep fading
+    screen: Screen,
+    /// Turns the screen into colours
+    renderer: Renderer,
+    /// GIF being recorded with F11, and where it's going
+    recording: Option<(GifRecorder<BufWriter<File>>, String)>,
 }
 
 impl SdlContext {
-    pub fn new(scale_factor: u8) -> Box<Self> {
+    /// Open a window `scale_factor` times the size of the CHIP-8 screen, drawn as `options` says.
+    /// Quicksaves go in `save_dir`.
+    pub fn new(scale_factor: u8, save_dir: PathBuf, options: RenderOptions) -> Box<Self> {
         let scale_factor = scale_factor as u32;
         let window_width = PIXEL_COLS * scale_factor;
         let window_height = PIXEL_ROWS * scale_factor;
 
         let context = sdl2::init().unwrap();
         let video_subsystem = context.video().unwrap();
-        let audio = context.audio().unwrap();
+        let desired_spec = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),
+            samples: None,
+        };
+        let audio = context
+            .audio()
+            .unwrap()
+            .open_playback(None, &desired_spec, |spec| PatternWave {
+                pattern: AudioPattern::default(),
+                phase: 0.0,
+                rate: spec.freq as f32,
+                volume: 0.25,
+            })
+            .unwrap();
         let window = video_subsystem
             .window("CHIP 8 - SDL2 Renderer", window_width, window_height)
             .position_centered()
             .build()
             .unwrap();
-        let mut canvas = window
+        let canvas = window
             .into_canvas()
             .build()
             .map_err(|e| e.to_string())
             .unwrap();
         let event_pump = context.event_pump().unwrap();
-
-        let scale_factor = scale_factor as f32;
-        canvas.set_scale(scale_factor, scale_factor).unwrap();
+        let controller_subsystem = context.game_controller().unwrap();
+        // There's one context for the life of the program, so its texture creator can live as long
+        let texture_creator = Box::leak(Box::new(canvas.texture_creator()));
 
         let ret = Self {
             audio,
             canvas,
+            texture_creator,
+            texture: None,
+            pixels: Vec::new(),
             event_pump,
             key_state: Keys::new(),
+            keymap: KeyMap::default(),
+            controller_subsystem,
+            controllers: Vec::new(),
+            held_axes: HashSet::new(),
+            save_dir,
+            save_slot: 1,
+            commands: VecDeque::new(),
+            start: Instant::now(),
+            screen: Screen::default(),
+            renderer: Renderer::new(options),
+            recording: None,
         };
 
         Box::new(ret)
     }
+
+    /// Use `keymap` for the keypad instead of the default layout.
+    pub fn set_keymap(&mut self, keymap: KeyMap) {
+        self.keymap = keymap;
+    }
+
+    /// Save what's on screen as a PNG in the working directory.
+    fn screenshot(&self) {
+        let path = format!("screenshot-{}.png", unix_millis());
+        let palette = self.renderer.options().palette;
+        match save_screenshot(&self.screen, &path, CAPTURE_SCALE, &palette) {
+            Ok(()) => println!("Saved screenshot {}", path),
+            Err(e) => eprintln!("Could not save screenshot {}: {}", path, e),
+        }
+    }
+
+    /// Start recording a GIF in the working directory, or finish the one going.
+    fn toggle_recording(&mut self) {
+        let now = self.now();
+        if let Some((recorder, path)) = self.recording.take() {
+            match recorder.finish(now) {
+                Ok(()) => println!("Saved recording {}", path),
+                Err(e) => eprintln!("Could not save recording {}: {}", path, e),
+            }
+            return;
+        }
+        let path = format!("recording-{}.gif", unix_millis());
+        let started = File::create(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|file| {
+                let palette = self.renderer.options().palette;
+                let mut recorder = GifRecorder::new(BufWriter::new(file), CAPTURE_SCALE, palette);
+                // Start from what's showing rather than waiting for the next draw
+                recorder.add_frame(&self.screen, now)?;
+                Ok(recorder)
+            });
+        match started {
+            Ok(recorder) => {
+                println!("Recording {}", path);
+                self.recording = Some((recorder, path));
+            }
+            Err(e) => eprintln!("Could not record {}: {}", path, e),
+        }
+    }
+
+    /// Draw the last screen, fading out whatever has gone dark since the last time.
+    fn render(&mut self) {
+        let now = self.now();
+        self.renderer.update(&self.screen, now);
+        let (width, height) = self.canvas.output_size().unwrap();
+        let (size, (left, top)) = self.renderer.layout(width, height);
+        let (width, height) = (self.renderer.width() * size, self.renderer.height() * size);
+        self.renderer.write_rgba(size, &mut self.pixels);
+
+        let stale = match &self.texture {
+            Some(texture) => {
+                let query = texture.query();
+                (query.width, query.height) != (width, height)
+            }
+            None => true,
+        };
+        if stale {
+            self.texture = Some(
+                self.texture_creator
+                    .create_texture_streaming(RGBA_FORMAT, width, height)
+                    .unwrap(),
+            );
+        }
+        let texture = self.texture.as_mut().unwrap();
+        texture
+            .update(None, &self.pixels, width as usize * 4)
+            .unwrap();
+
+        // Anything around the screen is the colour between pixels
+        let gap = self.renderer.gap_colour();
+        self.canvas
+            .set_draw_color(Color::RGB(gap[0], gap[1], gap[2]));
+        self.canvas.clear();
+        self.canvas
+            .copy(
+                texture,
+                None,
+                Rect::new(left as i32, top as i32, width, height),
+            )
+            .unwrap();
+        self.canvas.present();
+    }
+}
+
+impl Drop for SdlContext {
+    fn drop(&mut self) {
+        // Don't lose a recording by quitting in the middle of it
+        if self.recording.is_some() {
+            self.toggle_recording();
+        }
+    }
 }
 
-// This is literally the example from https://docs.rs/sdl2/0.34.3/sdl2/audio/index.html
-// Used for the system beep
-struct SquareWave {
-    phase_inc: f32,
+/// Milliseconds since the Unix epoch, to give captures unique names
+fn unix_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+// Adapted from the square wave example at https://docs.rs/sdl2/0.34.3/sdl2/audio/index.html
+// Used for the buzzer, loops over the 1-bit audio pattern instead
+struct PatternWave {
+    pattern: AudioPattern,
+    /// Position in the pattern, in pattern samples
     phase: f32,
+    /// Output samples per second
+    rate: f32,
     volume: f32,
 }
 
-impl AudioCallback for SquareWave {
+impl AudioCallback for PatternWave {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
+        // Generate a wave from the pattern bits, following any change in pitch
+        let phase_inc = self.pattern.sample_rate() / self.rate;
         for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
+            *x = if self.pattern.sample(self.phase as usize) {
                 self.volume
             } else {
                 -self.volume
             };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+            self.phase = (self.phase + phase_inc) % AUDIO_PATTERN_SAMPLES as f32;
         }
     }
 }
@@ -111,68 +296,149 @@ impl Context for SdlContext {
         self.canvas.clear();
         self.canvas.present();
     }
-    fn beep(&self) {
-        let desired_spec = AudioSpecDesired {
-            freq: Some(44100),
-            channels: Some(1),
-            samples: None,
-        };
-        let device = self
-            .audio
-            .open_playback(None, &desired_spec, |spec| SquareWave {
-                phase_inc: 440.0 / spec.freq as f32,
-                phase: 0.0,
-                volume: 0.25,
-            })
-            .unwrap();
-        device.resume();
-        self.sleep(25);
+    fn start_tone(&mut self, audio: &AudioPattern) {
+        // Swap the new pattern in without a gap, the phase carries on where it was
+        self.audio.lock().pattern = *audio;
+        self.audio.resume();
+    }
+    fn stop_tone(&mut self) {
+        self.audio.pause();
     }
     fn listen_for_input(&mut self) -> bool {
-        for event in self.event_pump.poll_iter() {
+        // Collected first, so handling them can borrow the rest of the context
+        let events: Vec<_> = self.event_pump.poll_iter().collect();
+        for event in events {
             match event {
                 Event::Quit { .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => return true,
-                Event::KeyDown { keycode, .. } => {
-                    if let Some(key) = keycode_to_keypad(keycode) {
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => self.commands.push_back(Command::SaveState(self.save_slot)),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => self.commands.push_back(Command::LoadState(self.save_slot)),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    repeat: false,
+                    ..
+                } => self.screenshot(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    repeat: false,
+                    ..
+                } => self.toggle_recording(),
+                // Fast-forward while Tab is held
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    repeat: false,
+                    ..
+                } => self.commands.push_back(Command::SetTurbo(true)),
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => self.commands.push_back(Command::SetTurbo(false)),
+                // Step back in time while Backspace is held
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    repeat: false,
+                    ..
+                } => self.commands.push_back(Command::SetRewind(true)),
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => self.commands.push_back(Command::SetRewind(false)),
+                Event::KeyDown {
+                    keycode,
+                    scancode,
+                    repeat,
+                    ..
+                } => {
+                    let keymap = &self.keymap;
+                    if let Some(key) = scancode.and_then(|s| keymap.keyboard(s.name())) {
+                        self.key_state.key_down(key);
+                    } else if let Some(slot) = keycode_to_save_slot(keycode) {
+                        self.save_slot = slot;
+                        println!("Quicksave slot {}", slot);
+                    } else if let Some(command) = keycode_to_command(keycode) {
+                        if !repeat {
+                            self.commands.push_back(command);
+                        }
+                    }
+                }
+                Event::KeyUp { scancode, .. } => {
+                    let keymap = &self.keymap;
+                    if let Some(key) = scancode.and_then(|s| keymap.keyboard(s.name())) {
+                        self.key_state.key_up(key);
+                    }
+                }
+                // SDL sends this for every controller already plugged in at startup too
+                Event::ControllerDeviceAdded { which, .. } => {
+                    match self.controller_subsystem.open(which) {
+                        Ok(controller) => {
+                            println!("Controller connected: {}", controller.name());
+                            self.controllers.push(controller);
+                        }
+                        Err(e) => eprintln!("Could not open controller {}: {}", which, e),
+                    }
+                }
+                Event::ControllerButtonDown { button, .. } => {
+                    if let Some(key) = self.keymap.button(&button.string()) {
                         self.key_state.key_down(key);
                     }
                 }
-                Event::KeyUp { keycode, .. } => {
-                    if let Some(key) = keycode_to_keypad(keycode) {
+                Event::ControllerButtonUp { button, .. } => {
+                    if let Some(key) = self.keymap.button(&button.string()) {
                         self.key_state.key_up(key);
                     }
                 }
+                Event::ControllerAxisMotion { axis, value, .. } => {
+                    for &(direction, held) in &[
+                        ("-", value < -AXIS_THRESHOLD),
+                        ("+", value > AXIS_THRESHOLD),
+                    ] {
+                        let name = format!("{}{}", axis.string(), direction);
+                        // Only act when a direction starts or stops being held, so small wobbles
+                        // don't let go of a key held some other way
+                        let changed = if held {
+                            self.held_axes.insert(name.clone())
+                        } else {
+                            self.held_axes.remove(&name)
+                        };
+                        match (changed, self.keymap.button(&name)) {
+                            (true, Some(key)) if held => self.key_state.key_down(key),
+                            (true, Some(key)) => self.key_state.key_up(key),
+                            _ => {}
+                        }
+                    }
+                }
                 _ => {}
             }
         }
         false
     }
 
-    fn draw_graphics(&mut self, screen: Screen) {
-        // For each pixel in the screen, draw a filled rectangle
-
-        // First, clear the canvas to black.
-        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
-        self.canvas.clear();
-        // Set to white to draw pixels.
-        self.canvas.set_draw_color(Color::RGB(255, 255, 255));
-
-        for y in 0..PIXEL_ROWS {
-            for x in 0..PIXEL_COLS {
-                // Draw a point if it exists scaled up from the source screen
-                if screen[(x + (y * PIXEL_COLS)) as usize] == 1 {
-                    self.canvas
-                        .fill_rect(Rect::new(x as i32, y as i32, 1, 1))
-                        .unwrap();
-                }
+    fn draw_graphics(&mut self, screen: &Screen) {
+        self.screen = screen.clone();
+        let now = self.now();
+        if let Some((recorder, path)) = &mut self.recording {
+            if let Err(e) = recorder.add_frame(screen, now) {
+                eprintln!("Stopped recording {}: {}", path, e);
+                self.recording = None;
             }
         }
+        self.render();
+    }
 
-        self.canvas.present();
+    fn start_frame(&mut self) {
+        // Keep anything that went dark fading out, even if nothing new is drawn
+        if self.renderer.is_fading() {
+            self.render();
+        }
     }
 
     fn get_key_state(&self) -> [bool; NUM_KEYS] {
@@ -186,4 +452,26 @@ impl Context for SdlContext {
     fn sleep(&self, millis: u64) {
         std::thread::sleep(Duration::from_millis(millis));
     }
+
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    fn next_command(&mut self) -> Option<Command> {
+        self.commands.pop_front()
+    }
+
+    fn store_state(&mut self, name: &str, bytes: &[u8]) -> Result<()> {
+        fs::create_dir_all(&self.save_dir)?;
+        fs::write(self.save_dir.join(format!("{}.c8s", name)), bytes)?;
+        Ok(())
+    }
+
+    fn fetch_state(&mut self, name: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.save_dir.join(format!("{}.c8s", name))) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
 }
@@ -0,0 +1,71 @@
+//! The buzzer, through WebAudio.
+
+use super::*;
+use web_sys::{AudioBufferSourceNode, AudioContext, AudioScheduledSourceNode, GainNode};
+
+/// Loudness of the buzzer, out of 1
+const VOLUME: f32 = 0.25;
+
+/// Loops the program's audio pattern for as long as the sound timer runs.
+#[derive(Debug)]
+pub struct Tone {
+    context: AudioContext,
+    gain: GainNode,
+    /// The pattern playing now, if anything is
+    source: Option<AudioBufferSourceNode>,
+}
+
+impl Tone {
+    pub fn new() -> Result<Self> {
+        let context = AudioContext::new()?;
+        let gain = context.create_gain()?;
+        gain.gain().set_value(VOLUME);
+        gain.connect_with_audio_node(&context.destination())?;
+        Ok(Self {
+            context,
+            gain,
+            source: None,
+        })
+    }
+
+    /// Start looping `audio`, replacing whatever was playing.
+    pub fn start(&mut self, audio: &AudioPattern) -> Result<()> {
+        self.stop()?;
+        // Browsers keep audio suspended until the page has been interacted with
+        let _ = self.context.resume()?;
+
+        // Resample one loop of the pattern to the output rate, so any pitch works
+        let rate = self.context.sample_rate();
+        let length = ((AUDIO_PATTERN_SAMPLES as f32 * rate / audio.sample_rate()) as u32).max(1);
+        let step = audio.sample_rate() / rate;
+        let samples: Vec<f32> = (0..length)
+            .map(|i| {
+                if audio.sample((i as f32 * step) as usize) {
+                    1.0
+                } else {
+                    -1.0
+                }
+            })
+            .collect();
+        let buffer = self.context.create_buffer(1, length, rate)?;
+        buffer.copy_to_channel(&samples, 0)?;
+
+        let source = self.context.create_buffer_source()?;
+        source.set_buffer(Some(&buffer));
+        source.set_loop(true);
+        source.connect_with_audio_node(&self.gain)?;
+        source.start()?;
+        self.source = Some(source);
+        Ok(())
+    }
+
+    /// Stop whatever is playing.
+    pub fn stop(&mut self) -> Result<()> {
+        if let Some(source) = self.source.take() {
+            // The buffer source's own stop is deprecated in favour of the base node's
+            AsRef::<AudioScheduledSourceNode>::as_ref(&source).stop()?;
+            source.disconnect()?;
+        }
+        Ok(())
+    }
+}
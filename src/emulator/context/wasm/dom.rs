@@ -2,8 +2,8 @@
 
 use super::*;
 use console_error_panic_hook::set_once;
-use wasm_bindgen::JsCast;
-use web_sys::{CanvasRenderingContext2d, Document, Element, Window};
+use wasm_bindgen::{Clamped, JsCast};
+use web_sys::{CanvasRenderingContext2d, Document, HtmlInputElement, ImageData, Storage, Window};
 
 /// Fake a "sleep" function in a hacky dumb way using the Date object.
 pub fn sleep(millis: u64) {
@@ -14,25 +14,6 @@ pub fn sleep(millis: u64) {
     }
 }
 
-/// beep background
-pub fn beep() -> Result<()> {
-    let document = get_document();
-    let div = document.body().unwrap().dyn_into::<Element>()?;
-    let class_list = div.class_list();
-    class_list.add_1("beep")?;
-
-    let remove = Closure::wrap(Box::new(move || {
-        class_list.remove_1("beep").unwrap();
-    }) as Box<dyn Fn()>);
-    window().set_timeout_with_callback_and_timeout_and_arguments_0(
-        remove.as_ref().unchecked_ref(),
-        300,
-    )?;
-    remove.forget();
-
-    Ok(())
-}
-
 /// Helper to grab the document object
 pub fn get_document() -> Document {
     window()
@@ -47,29 +28,29 @@ pub fn request_animation_frame(f: &Closure<dyn FnMut()>) {
         .expect("should register `requestAnimationFrame` OK");
 }
 
-/// draw screen
+/// draw the screen as the renderer sees it, scaled up to fill the canvas.
+/// `pixels` holds the RGBA bytes between draws, to save reallocating.
 pub fn update_canvas(
     context: &CanvasRenderingContext2d,
-    width: f64,
-    height: f64,
-    screen: Screen,
+    width: u32,
+    height: u32,
+    renderer: &Renderer,
+    pixels: &mut Vec<u8>,
 ) -> Result<()> {
-    // draw
-
-    context.set_fill_style(&JsValue::from_str("black"));
-    context.fill_rect(0.0, 0.0, width, height);
-
-    // For pixel in screen
-    context.set_fill_style(&JsValue::from_str("white"));
-    for y in 0..PIXEL_ROWS {
-        for x in 0..PIXEL_COLS {
-            // Draw a point if it exists
-            if screen[(x + (y * PIXEL_COLS)) as usize] == 1 {
-                context.fill_rect(x as f64, y as f64, 1.0, 1.0);
-            }
-        }
-    }
-    Ok(())
+    // The pixel size changes with the resolution
+    let (size, (left, top)) = renderer.layout(width, height);
+    renderer.write_rgba(size, pixels);
+    let image = ImageData::new_with_u8_clamped_array_and_sh(
+        Clamped(pixels.as_slice()),
+        renderer.width() * size,
+        renderer.height() * size,
+    )?;
+
+    // Anything around the screen is the colour between pixels
+    let [r, g, b] = renderer.gap_colour();
+    context.set_fill_style_str(&format!("rgb({},{},{})", r, g, b));
+    context.fill_rect(0.0, 0.0, width as f64, height as f64);
+    context.put_image_data(&image, left as f64, top as f64)
 }
 
 // Helpers to build the page
@@ -97,10 +78,21 @@ fn attach_game_listener(document: &Document) -> Result<()> {
 fn attach_keydown_listener(document: &Document) -> Result<()> {
     let callback = Closure::wrap(Box::new(move |evt: web_sys::Event| {
         let evt = evt.dyn_into::<web_sys::KeyboardEvent>().unwrap();
-        let c = std::char::from_u32(evt.key_code()).unwrap();
-        if let Ok(ch) = keyboard_to_keypad(c) {
-            KEYS.key_down(ch);
-        } else if c == 'G' {
+        let name = key_name_from_code(&evt.code());
+        // A key binding being changed takes the next key instead of the game
+        let rebinding = REBINDING.write().unwrap().take();
+        if let Some(key) = rebinding {
+            if name != "Escape" {
+                KEYMAP.write().unwrap().bind_key(key, &name);
+                if let Err(e) = store_keymap() {
+                    error!("Could not save key bindings: {:?}", e);
+                }
+            }
+            update_binding_labels().unwrap();
+            evt.prevent_default();
+        } else if let Some(key) = KEYMAP.read().unwrap().keyboard(&name) {
+            KEYS.key_down(key);
+        } else if name == "G" {
             // trigger restart
             *TRIGGER_RESTART.write().unwrap() = true;
         }
@@ -112,13 +104,223 @@ fn attach_keydown_listener(document: &Document) -> Result<()> {
     Ok(())
 }
 
+/// Button listeners, which leave a command for the context to pick up
+fn attach_command_listeners(document: &Document) -> Result<()> {
+    for &(id, command) in &[
+        ("save-state", Command::SaveState(1)),
+        ("load-state", Command::LoadState(1)),
+        ("pause", Command::TogglePause),
+        ("frame-advance", Command::FrameAdvance),
+    ] {
+        let callback = Closure::wrap(Box::new(move |_evt: web_sys::Event| {
+            PENDING_COMMANDS.write().unwrap().push_back(command);
+        }) as Box<dyn FnMut(_)>);
+
+        document
+            .get_element_by_id(id)
+            .unwrap()
+            .dyn_into::<HtmlElement>()?
+            .set_onclick(Some(callback.as_ref().unchecked_ref()));
+
+        callback.forget();
+    }
+    Ok(())
+}
+
+/// Rewind button listeners, stepping back in time while it's held down
+fn attach_rewind_listeners(document: &Document) -> Result<()> {
+    let button = document
+        .get_element_by_id("rewind")
+        .unwrap()
+        .dyn_into::<HtmlElement>()?;
+    let hold = |rewinding| {
+        Closure::wrap(Box::new(move |_evt: web_sys::Event| {
+            PENDING_COMMANDS
+                .write()
+                .unwrap()
+                .push_back(Command::SetRewind(rewinding));
+        }) as Box<dyn FnMut(_)>)
+    };
+    let press = hold(true);
+    let release = hold(false);
+    button.set_onmousedown(Some(press.as_ref().unchecked_ref()));
+    button.set_onmouseup(Some(release.as_ref().unchecked_ref()));
+    // Dragging off the button counts as letting go
+    button.set_onmouseleave(Some(release.as_ref().unchecked_ref()));
+    press.forget();
+    release.forget();
+    Ok(())
+}
+
+/// Turbo checkbox and speed input listeners
+fn attach_speed_listeners(document: &Document) -> Result<()> {
+    let turbo = document
+        .get_element_by_id("turbo")
+        .unwrap()
+        .dyn_into::<HtmlInputElement>()?;
+    let checkbox = turbo.clone();
+    let callback = Closure::wrap(Box::new(move |_evt: web_sys::Event| {
+        PENDING_COMMANDS
+            .write()
+            .unwrap()
+            .push_back(Command::SetTurbo(checkbox.checked()));
+    }) as Box<dyn FnMut(_)>);
+    turbo.set_onchange(Some(callback.as_ref().unchecked_ref()));
+    callback.forget();
+
+    let speed = document
+        .get_element_by_id("speed")
+        .unwrap()
+        .dyn_into::<HtmlInputElement>()?;
+    let input = speed.clone();
+    let callback = Closure::wrap(Box::new(move |_evt: web_sys::Event| {
+        if let Ok(speed) = input.value().parse() {
+            PENDING_COMMANDS
+                .write()
+                .unwrap()
+                .push_back(Command::SetSpeed(speed));
+        }
+    }) as Box<dyn FnMut(_)>);
+    speed.set_onchange(Some(callback.as_ref().unchecked_ref()));
+    callback.forget();
+    Ok(())
+}
+
+/// Palette, effect and persistence listeners, which change the options the next draw uses
+fn attach_display_listeners(document: &Document) -> Result<()> {
+    let palette = document
+        .get_element_by_id("palette")
+        .unwrap()
+        .dyn_into::<web_sys::HtmlSelectElement>()?;
+    let select = palette.clone();
+    let callback = Closure::wrap(Box::new(move |_evt: web_sys::Event| {
+        if let Ok(palette) = select.value().parse() {
+            RENDER_OPTIONS.write().unwrap().palette = palette;
+        }
+        blur_all().unwrap();
+    }) as Box<dyn FnMut(_)>);
+    palette.set_onchange(Some(callback.as_ref().unchecked_ref()));
+    callback.forget();
+
+    let effect = document
+        .get_element_by_id("effect")
+        .unwrap()
+        .dyn_into::<web_sys::HtmlSelectElement>()?;
+    let select = effect.clone();
+    let callback = Closure::wrap(Box::new(move |_evt: web_sys::Event| {
+        if let Ok(effect) = select.value().parse::<Effect>() {
+            RENDER_OPTIONS.write().unwrap().effect = effect;
+        }
+        blur_all().unwrap();
+    }) as Box<dyn FnMut(_)>);
+    effect.set_onchange(Some(callback.as_ref().unchecked_ref()));
+    callback.forget();
+
+    let persistence = document
+        .get_element_by_id("persistence")
+        .unwrap()
+        .dyn_into::<HtmlInputElement>()?;
+    let input = persistence.clone();
+    let callback = Closure::wrap(Box::new(move |_evt: web_sys::Event| {
+        if let Ok(persistence) = input.value().parse() {
+            RENDER_OPTIONS.write().unwrap().persistence = persistence;
+        }
+    }) as Box<dyn FnMut(_)>);
+    persistence.set_oninput(Some(callback.as_ref().unchecked_ref()));
+    callback.forget();
+    Ok(())
+}
+
+/// localStorage key for the key bindings, stored as TOML
+const KEYMAP_KEY: &str = "chip8-keymap";
+
+/// Pick up key bindings saved by an earlier visit
+fn load_keymap() -> Result<()> {
+    if let Some(stored) = local_storage()?.get_item(KEYMAP_KEY)? {
+        match KeyMap::from_toml(&stored) {
+            Ok(keymap) => *KEYMAP.write().unwrap() = keymap,
+            Err(e) => {
+                error!("Ignoring saved key bindings: {}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Save the key bindings for the next visit
+fn store_keymap() -> Result<()> {
+    let toml = KEYMAP
+        .read()
+        .unwrap()
+        .to_toml()
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    local_storage()?.set_item(KEYMAP_KEY, &toml)
+}
+
+/// Show what each keypad key is bound to, or that it's waiting for a new key
+fn update_binding_labels() -> Result<()> {
+    let document = get_document();
+    let keymap = KEYMAP.read().unwrap();
+    let rebinding = *REBINDING.read().unwrap();
+    for key in 0..NUM_KEYS as u8 {
+        let label = if rebinding == Some(key) {
+            format!("{:X}: press a key", key)
+        } else {
+            format!("{:X}: {}", key, keymap.keys_for(key).join(" "))
+        };
+        document
+            .get_element_by_id(&binding_id(key))
+            .unwrap()
+            .set_text_content(Some(&label));
+    }
+    Ok(())
+}
+
+/// Rebinding buttons, one per keypad key, plus one to go back to the defaults
+fn attach_binding_listeners(document: &Document) -> Result<()> {
+    for key in 0..NUM_KEYS as u8 {
+        let callback = Closure::wrap(Box::new(move |_evt: web_sys::Event| {
+            *REBINDING.write().unwrap() = Some(key);
+            update_binding_labels().unwrap();
+        }) as Box<dyn FnMut(_)>);
+        document
+            .get_element_by_id(&binding_id(key))
+            .unwrap()
+            .dyn_into::<HtmlElement>()?
+            .set_onclick(Some(callback.as_ref().unchecked_ref()));
+        callback.forget();
+    }
+
+    let callback = Closure::wrap(Box::new(move |_evt: web_sys::Event| {
+        *KEYMAP.write().unwrap() = KeyMap::default();
+        *REBINDING.write().unwrap() = None;
+        if let Err(e) = store_keymap() {
+            error!("Could not save key bindings: {:?}", e);
+        }
+        update_binding_labels().unwrap();
+        blur_all().unwrap();
+    }) as Box<dyn FnMut(_)>);
+    document
+        .get_element_by_id("reset-bindings")
+        .unwrap()
+        .dyn_into::<HtmlElement>()?
+        .set_onclick(Some(callback.as_ref().unchecked_ref()));
+    callback.forget();
+
+    load_keymap()?;
+    update_binding_labels()
+}
+
 /// Keyup event listener
 fn attach_keyup_listener(document: &Document) -> Result<()> {
     let callback = Closure::wrap(Box::new(move |evt: web_sys::Event| {
         let evt = evt.dyn_into::<web_sys::KeyboardEvent>().unwrap();
-        let c = std::char::from_u32(evt.key_code()).unwrap();
-        if let Ok(ch) = keyboard_to_keypad(c) {
-            KEYS.key_up(ch);
+        if let Some(key) = KEYMAP
+            .read()
+            .unwrap()
+            .keyboard(&key_name_from_code(&evt.code()))
+        {
+            KEYS.key_up(key);
         }
     }) as Box<dyn FnMut(_)>);
 
@@ -146,6 +348,13 @@ fn window() -> Window {
     web_sys::window().expect("no global `window` exists")
 }
 
+/// Browser storage that survives reloads, used for save states
+pub fn local_storage() -> Result<Storage> {
+    window()
+        .local_storage()?
+        .ok_or_else(|| JsValue::from_str("localStorage is unavailable"))
+}
+
 /// Mount the DOM necessary to host the app
 pub fn mount() {
     set_once(); // console_error_panic_hook
@@ -155,4 +364,9 @@ pub fn mount() {
     attach_game_listener(&document).unwrap();
     attach_keydown_listener(&document).unwrap();
     attach_keyup_listener(&document).unwrap();
+    attach_command_listeners(&document).unwrap();
+    attach_speed_listeners(&document).unwrap();
+    attach_rewind_listeners(&document).unwrap();
+    attach_display_listeners(&document).unwrap();
+    attach_binding_listeners(&document).unwrap();
 }
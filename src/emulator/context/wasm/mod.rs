@@ -1,21 +1,23 @@
 //! This module builds the containing webpage and mounts the machine to a canvas element.
 use std::{
     cell::RefCell,
+    collections::VecDeque,
     rc::Rc,
     sync::{Arc, RwLock},
 };
 
 use super::*;
-use crate::ROMS;
+use crate::{key_name_from_code, Effect, KeyMap, RenderOptions, Renderer, PALETTES, ROMS};
 
 use lazy_static::lazy_static;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{Document, Element, HtmlElement};
 
-mod dom;
 #[macro_use]
 mod macros;
+mod audio;
+mod dom;
 mod wasm_context;
 
 use dom::*;
@@ -23,7 +25,7 @@ use wasm_context::WasmContext;
 
 pub type Result<T> = std::result::Result<T, JsValue>;
 
-const INSTRUCTIONS: &str = "Select your preferred ROM, and use the keys as shown.  Use the 'G' key to restart the current ROM.
+const INSTRUCTIONS: &str = "Select your preferred ROM, and use the keys as shown, or change them under Key bindings.  Use the 'G' key to restart the current ROM.
 
   CHIP8   =>  Keyboard
 
@@ -38,29 +40,16 @@ lazy_static! {
     static ref KEYS: Keys = Keys::new(); // TODO I think the Arc should jus tbe here, dont make Machine worry about it
     static ref CURRENT_GAME: Arc<RwLock<String>> = Arc::new(RwLock::new("test_opcode".to_string()));
     static ref TRIGGER_RESTART: Arc<RwLock<bool>> = Arc::new(RwLock::new(false));
-}
-
-/// Render a string for the console
-#[allow(dead_code)]
-fn debug_render(screen: Screen) {
-    let mut ret = String::new();
-    for y in 0..PIXEL_ROWS {
-        for x in 0..PIXEL_COLS {
-            if screen[((y * PIXEL_COLS) + x) as usize] == 0 {
-                ret.push('0');
-            } else {
-                ret.push(' ');
-            }
-        }
-        ret.push('\n');
-    }
-    ret.push('\n');
-    log!("{}", ret);
+    static ref PENDING_COMMANDS: Arc<RwLock<VecDeque<Command>>> = Arc::new(RwLock::new(VecDeque::new()));
+    static ref RENDER_OPTIONS: Arc<RwLock<RenderOptions>> = Arc::new(RwLock::new(RenderOptions::default()));
+    static ref KEYMAP: Arc<RwLock<KeyMap>> = Arc::new(RwLock::new(KeyMap::default()));
+    /// Keypad key waiting for a new keyboard key to be pressed for it
+    static ref REBINDING: Arc<RwLock<Option<u8>>> = Arc::new(RwLock::new(None));
 }
 
 fn mount_app(document: &Document, body: &HtmlElement) -> Result<()> {
     append_text_element_attrs!(document, body, "h1", "CHIP-8",);
-    mount_controls(&document, &body)?;
+    mount_controls(document, body)?;
     append_text_element_attrs!(
         document,
         body,
@@ -91,12 +80,108 @@ fn mount_controls(document: &Document, parent: &HtmlElement) -> Result<()> {
         select.append_child(&new_option)?;
     }
     parent.append_child(&select)?;
+    append_text_element_attrs!(document, parent, "button", "Save", ("id", "save-state"));
+    append_text_element_attrs!(document, parent, "button", "Restore", ("id", "load-state"));
+    append_text_element_attrs!(document, parent, "button", "Pause", ("id", "pause"));
+    append_text_element_attrs!(document, parent, "button", "Frame", ("id", "frame-advance"));
+    append_text_element_attrs!(document, parent, "button", "Rewind", ("id", "rewind"));
+    append_text_element_attrs!(document, parent, "label", "Turbo", ("for", "turbo"));
+    append_element_attrs!(
+        document,
+        parent,
+        "input",
+        ("id", "turbo"),
+        ("type", "checkbox")
+    );
+    append_text_element_attrs!(document, parent, "label", "Speed:", ("for", "speed"));
+    append_element_attrs!(
+        document,
+        parent,
+        "input",
+        ("id", "speed"),
+        ("type", "number"),
+        ("min", "1"),
+        ("step", "50"),
+        ("value", &CYCLES_PER_SECOND.to_string())
+    );
+    mount_display_controls(document, parent)?;
 
     let div = create_element_attrs!(document, "div", ("id", "chip8canvas"));
     // canvas
-    mount_canvas(&document, &div)?;
+    mount_canvas(document, &div)?;
     parent.append_child(&div)?;
     append_text_element_attrs!(document, parent, "pre", INSTRUCTIONS,);
+    mount_bindings(document, parent)?;
+    Ok(())
+}
+
+/// Id of the button that rebinds keypad `key`
+fn binding_id(key: u8) -> String {
+    format!("bind-{:x}", key)
+}
+
+/// Key bindings screen - click a keypad key, then press the keyboard key to use for it
+fn mount_bindings(document: &Document, parent: &HtmlElement) -> Result<()> {
+    let details = create_element_attrs!(document, "details", ("id", "bindings"));
+    append_text_element_attrs!(document, details, "summary", "Key bindings",);
+    // Laid out like the keypad itself
+    for row in &[
+        [0x1, 0x2, 0x3, 0xC],
+        [0x4, 0x5, 0x6, 0xD],
+        [0x7, 0x8, 0x9, 0xE],
+        [0xA, 0x0, 0xB, 0xF],
+    ] {
+        let div = create_element_attrs!(document, "div",);
+        for &key in row {
+            append_element_attrs!(document, div, "button", ("id", &binding_id(key)));
+        }
+        details.append_child(&div)?;
+    }
+    append_text_element_attrs!(
+        document,
+        details,
+        "button",
+        "Reset to defaults",
+        ("id", "reset-bindings")
+    );
+    parent.append_child(&details)?;
+    Ok(())
+}
+
+/// Palette, effect and persistence pickers
+fn mount_display_controls(document: &Document, parent: &HtmlElement) -> Result<()> {
+    append_text_element_attrs!(document, parent, "label", "Palette:", ("for", "palette"));
+    let select = create_element_attrs!(document, "select", ("id", "palette"));
+    for &(name, _) in PALETTES {
+        let option = web_sys::HtmlOptionElement::new_with_text_and_value(name, name)?;
+        select.append_child(&option)?;
+    }
+    parent.append_child(&select)?;
+    append_text_element_attrs!(document, parent, "label", "Effect:", ("for", "effect"));
+    let select = create_element_attrs!(document, "select", ("id", "effect"));
+    for name in &["none", "scanlines", "grid"] {
+        let option = web_sys::HtmlOptionElement::new_with_text_and_value(name, name)?;
+        select.append_child(&option)?;
+    }
+    parent.append_child(&select)?;
+    append_text_element_attrs!(
+        document,
+        parent,
+        "label",
+        "Persistence:",
+        ("for", "persistence")
+    );
+    append_element_attrs!(
+        document,
+        parent,
+        "input",
+        ("id", "persistence"),
+        ("type", "range"),
+        ("min", "0"),
+        ("max", "0.9"),
+        ("step", "0.1"),
+        ("value", &RenderOptions::default().persistence.to_string())
+    );
     Ok(())
 }
 
@@ -131,13 +216,8 @@ pub fn run() {
     let f = Rc::new(RefCell::new(None));
     let g = Rc::clone(&f);
 
-    // Counters to force redraw after a certian number of identical frames, even if draw_flag isnt set
-    // Otherwise, completed programs will simply freeze the page.
-    let max_timeout = 20;
-    let mut current_timeout = 0;
-
-    // Counter to force sleep sometimes
-    let mut cycle_counter = 0;
+    // Set when the ROM hits an error, the machine stays halted until a game is (re)loaded
+    let mut crashed = false;
 
     // Store the callback in g (and consequently, f)
     *g.borrow_mut() = Some(Closure::wrap(Box::new(move || {
@@ -148,41 +228,33 @@ pub fn run() {
             if name != selected_game || *TRIGGER_RESTART.read().unwrap() {
                 *TRIGGER_RESTART.write().unwrap() = false;
                 let bytes = machine
-                    .load_game(&selected_game)
+                    .load_game(selected_game)
                     .expect("Could not load new rom");
                 log!("Loaded {}: {} bytes.", selected_game, bytes);
+                crashed = false;
                 // Defocus everything to clear way for keyboard input
                 blur_all().unwrap();
             }
         }
 
-        //log!("{}", KEYS.to_string());
-
-        // Then, execute cycles until draw_flag gets set or a certain number of cycles have passed.
-        // This is basically machine::step() but in wasm callback form
-
-        loop {
-            // TODO listen for a quit signal or something?
-
-            machine.cycle().unwrap();
-
-            machine.update_keys();
-
-            if machine.draw_flag {
-                machine.draw_graphics();
-                current_timeout = 0;
-                break;
-            }
-            if current_timeout >= max_timeout {
-                current_timeout = 0;
-                break;
+        // Carry out anything the controls asked for.  Restoring a state gets a crashed machine going again.
+        let pending = PENDING_COMMANDS.read().unwrap().clone();
+        if !pending.is_empty() {
+            if pending.iter().any(|c| matches!(c, Command::LoadState(_))) {
+                crashed = false;
             }
-            current_timeout += 1;
+            machine.poll_commands();
+            blur_all().unwrap();
+        }
+
+        //log!("{}", KEYS.to_string());
 
-            cycle_counter += 1;
-            if cycle_counter >= CYCLES_PER_SLEEP {
-                cycle_counter = 0;
-                machine.sleep(MILLIS_PER_SLEEP.floor() as u64);
+        // Then, catch up on whatever instructions have come due since the last frame.
+        // The scheduler paces them off the clock, so the frame rate doesn't change the game speed.
+        if !crashed {
+            if let Err(e) = machine.run_due() {
+                error!("{} - press 'G' to restart", e);
+                crashed = true;
             }
         }
         // Schedule another redraw
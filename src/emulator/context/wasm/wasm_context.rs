@@ -1,15 +1,26 @@
 //! This struct connects the machine to the wasm context.
 
-use super::*;
+use super::{audio::Tone, *};
 use js_sys::Math::{floor, random};
 use web_sys::CanvasRenderingContext2d;
+/// localStorage key for a save state, save states are stored hex-encoded
+fn storage_key(name: &str) -> String {
+    format!("chip8-state-{}", name)
+}
+
 /// The WebAssembly interface
 #[derive(Debug)]
 pub struct WasmContext {
     ctx: Option<CanvasRenderingContext2d>,
     width: u32,
     height: u32,
-    scale_factor: u32,
+    /// Last screen drawn, to keep fading
+    screen: Screen,
+    renderer: Renderer,
+    /// RGBA bytes for the canvas, kept between draws to save reallocating
+    pixels: Vec<u8>,
+    /// The buzzer, if the browser has WebAudio
+    tone: Option<Tone>,
 }
 
 impl WasmContext {
@@ -18,9 +29,27 @@ impl WasmContext {
             ctx: None,
             width: PIXEL_COLS * scale_factor,
             height: PIXEL_ROWS * scale_factor,
-            scale_factor,
+            screen: Screen::default(),
+            renderer: Renderer::new(*RENDER_OPTIONS.read().unwrap()),
+            pixels: Vec::new(),
+            tone: None,
         })
     }
+
+    /// Draw the last screen in the current options, fading out whatever has gone dark since the last time.
+    fn render(&mut self) {
+        self.renderer.set_options(*RENDER_OPTIONS.read().unwrap());
+        let now = self.now();
+        self.renderer.update(&self.screen, now);
+        update_canvas(
+            self.ctx.as_ref().unwrap(),
+            self.width,
+            self.height,
+            &self.renderer,
+            &mut self.pixels,
+        )
+        .unwrap();
+    }
 }
 
 impl Context for WasmContext {
@@ -38,35 +67,45 @@ impl Context for WasmContext {
         canvas.set_width(self.width);
         canvas.set_height(self.height);
         // TODO pass attribute to disable alpha - performance?
+        // The pixel size is worked out on every draw, to follow resolution changes
         let context = canvas
             .get_context("2d")
             .unwrap()
             .unwrap()
             .dyn_into::<web_sys::CanvasRenderingContext2d>()
             .unwrap();
-        context
-            .scale(self.scale_factor as f64, self.scale_factor as f64)
-            .unwrap();
         self.ctx = Some(context);
+        match Tone::new() {
+            Ok(tone) => self.tone = Some(tone),
+            Err(e) => {
+                error!("No sound: {:?}", e);
+            }
+        }
         log!("Finished init");
     }
-    fn beep(&self) {
-        log!("BEEP");
-        beep().unwrap_or_else(|_| {});
+    fn start_tone(&mut self, audio: &AudioPattern) {
+        if let Some(Err(e)) = self.tone.as_mut().map(|tone| tone.start(audio)) {
+            error!("Could not play tone: {:?}", e);
+        }
+    }
+    fn stop_tone(&mut self) {
+        if let Some(Err(e)) = self.tone.as_mut().map(Tone::stop) {
+            error!("Could not stop tone: {:?}", e);
+        }
     }
     fn listen_for_input(&mut self) -> bool {
         // This is handled differently in wasm, there's no quit, just restart
         false
     }
-    fn draw_graphics(&mut self, screen: Screen) {
-        //debug_render(screen);
-        update_canvas(
-            self.ctx.as_ref().unwrap(),
-            self.width as f64,
-            self.height as f64,
-            screen,
-        )
-        .unwrap();
+    fn draw_graphics(&mut self, screen: &Screen) {
+        self.screen = screen.clone();
+        self.render();
+    }
+    fn start_frame(&mut self) {
+        // Keep anything that went dark fading out, even if nothing new is drawn
+        if self.renderer.is_fading() {
+            self.render();
+        }
     }
     fn get_key_state(&self) -> [bool; NUM_KEYS] {
         KEYS.inner()
@@ -77,4 +116,24 @@ impl Context for WasmContext {
     fn sleep(&self, millis: u64) {
         sleep(millis);
     }
+    fn now(&self) -> u64 {
+        js_sys::Date::now() as u64
+    }
+    fn next_command(&mut self) -> Option<Command> {
+        PENDING_COMMANDS.write().unwrap().pop_front()
+    }
+    fn store_state(&mut self, name: &str, bytes: &[u8]) -> anyhow::Result<()> {
+        local_storage()
+            .and_then(|storage| storage.set_item(&storage_key(name), &hex::encode(bytes)))
+            .map_err(|e| anyhow!("{:?}", e))
+    }
+    fn fetch_state(&mut self, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let stored = local_storage()
+            .and_then(|storage| storage.get_item(&storage_key(name)))
+            .map_err(|e| anyhow!("{:?}", e))?;
+        match stored {
+            Some(encoded) => Ok(Some(hex::decode(encoded)?)),
+            None => Ok(None),
+        }
+    }
 }
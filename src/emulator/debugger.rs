@@ -0,0 +1,472 @@
+//! Breakpoints, watchpoints and stepping, driving a `Machine` one instruction at a time.
+
+use super::{
+    error::Chip8Error,
+    machine::{Access, Machine},
+    opcode::Opcode,
+};
+use anyhow::{anyhow, Result};
+use std::{collections::BTreeSet, convert::TryFrom, fmt, str::FromStr};
+
+/// How many instructions a continue, step over or step out runs before giving up, a few seconds flat out.
+pub const DEFAULT_RUN_LIMIT: usize = 100_000_000;
+
+/// How a register condition compares the register with its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn holds(self, lhs: u8, rhs: u8) -> bool {
+        use Comparison::*;
+        match self {
+            Eq => lhs == rhs,
+            Ne => lhs != rhs,
+            Lt => lhs < rhs,
+            Le => lhs <= rhs,
+            Gt => lhs > rhs,
+            Ge => lhs >= rhs,
+        }
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        use Comparison::*;
+        match s {
+            "==" => Ok(Eq),
+            "!=" => Ok(Ne),
+            "<" => Ok(Lt),
+            "<=" => Ok(Le),
+            ">" => Ok(Gt),
+            ">=" => Ok(Ge),
+            _ => Err(anyhow!("Unknown comparison {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Comparison::*;
+        let s = match self {
+            Eq => "==",
+            Ne => "!=",
+            Lt => "<",
+            Le => "<=",
+            Gt => ">",
+            Ge => ">=",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Stop when a register starts satisfying a comparison, e.g. `V3 == 0x10`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub register: u8,
+    pub comparison: Comparison,
+    pub value: u8,
+}
+
+impl Condition {
+    /// Check the condition against the machine's current registers.
+    pub fn holds(&self, machine: &Machine) -> bool {
+        self.comparison
+            .holds(machine.registers()[self.register as usize], self.value)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "V{:X} {} {:#04X}",
+            self.register, self.comparison, self.value
+        )
+    }
+}
+
+/// Which memory accesses a watchpoint stops on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(self, access: Access) -> bool {
+        !matches!(
+            (self, access),
+            (WatchKind::Read, Access::Write) | (WatchKind::Write, Access::Read)
+        )
+    }
+}
+
+/// Why the debugger handed control back.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    /// Ran the requested number of instructions, or finished a step over/out.
+    Done,
+    /// About to execute the instruction at a breakpoint.
+    Breakpoint(u16),
+    /// The next instruction touches a watched memory cell.
+    Watchpoint { addr: u16, access: Access },
+    /// A register condition just became true.
+    Condition(Condition),
+    /// The program executed 00FD.
+    Exited,
+    /// The program hit an error, the machine is left at the failing instruction.
+    Error(Chip8Error),
+    /// The user closed the window or otherwise asked to quit.
+    Quit,
+    /// Ran this many instructions without anything else stopping it.
+    Limit(usize),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use StopReason::*;
+        match self {
+            Done => write!(f, "Stopped"),
+            Breakpoint(addr) => write!(f, "Breakpoint at {:#05X}", addr),
+            Watchpoint { addr, access } => write!(f, "Watchpoint: {:?} at {:#06X}", access, addr),
+            Condition(condition) => write!(f, "Condition {}", condition),
+            Exited => write!(f, "Program exited"),
+            Error(e) => write!(f, "Error: {}", e),
+            Quit => write!(f, "Quit"),
+            Limit(count) => write!(f, "Gave up after {} instructions", count),
+        }
+    }
+}
+
+/// Keeps track of breakpoints, watchpoints and register conditions, and runs a machine until one of them is hit.
+/// Every run executes at least one instruction before checking anything, so resuming from a stop always makes progress.
+/// Runs service the machine's frontend once a frame, so input still arrives and the window can be closed.
+#[derive(Debug, Clone)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<(u16, WatchKind)>,
+    conditions: Vec<Condition>,
+    run_limit: usize,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self {
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            conditions: Vec::new(),
+            run_limit: DEFAULT_RUN_LIMIT,
+        }
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give up on a continue, step over or step out after this many instructions.
+    pub fn set_run_limit(&mut self, limit: usize) {
+        self.run_limit = limit;
+    }
+
+    /// Stop before executing the instruction at `addr`.
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Remove a breakpoint, returning whether there was one.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    /// All breakpoints in address order.
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Stop before any instruction that accesses `addr` in the given way.
+    pub fn add_watchpoint(&mut self, addr: u16, kind: WatchKind) {
+        self.remove_watchpoint(addr);
+        self.watchpoints.push((addr, kind));
+    }
+
+    /// Remove a watchpoint, returning whether there was one.
+    pub fn remove_watchpoint(&mut self, addr: u16) -> bool {
+        let before = self.watchpoints.len();
+        self.watchpoints.retain(|&(a, _)| a != addr);
+        self.watchpoints.len() != before
+    }
+
+    /// All watchpoints in the order they were added.
+    pub fn watchpoints(&self) -> &[(u16, WatchKind)] {
+        &self.watchpoints
+    }
+
+    /// Stop whenever `condition` goes from false to true.
+    pub fn add_condition(&mut self, condition: Condition) {
+        self.conditions.push(condition);
+    }
+
+    /// Remove the condition at `index` in `conditions()`.
+    pub fn remove_condition(&mut self, index: usize) -> Option<Condition> {
+        if index < self.conditions.len() {
+            Some(self.conditions.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// All register conditions in the order they were added.
+    pub fn conditions(&self) -> &[Condition] {
+        &self.conditions
+    }
+
+    /// Execute up to `count` instructions.
+    pub fn step(&self, machine: &mut Machine, count: usize) -> StopReason {
+        self.run_until(machine, Some(count), |_| false)
+    }
+
+    /// Execute until something stops the machine.
+    pub fn resume(&self, machine: &mut Machine) -> StopReason {
+        self.run_until(machine, None, |_| false)
+    }
+
+    /// Execute one instruction, running a whole subroutine if it's a call.
+    pub fn step_over(&self, machine: &mut Machine) -> StopReason {
+        match machine.current_opcode() {
+            Ok(Opcode::Call(_)) => {
+                let (return_pc, depth) = (machine.pc.wrapping_add(2), machine.sp);
+                self.run_until(machine, None, |m| m.pc == return_pc && m.sp == depth)
+            }
+            _ => self.step(machine, 1),
+        }
+    }
+
+    /// Execute until the current subroutine returns.
+    pub fn step_out(&self, machine: &mut Machine) -> StopReason {
+        let depth = machine.sp;
+        if depth == 0 {
+            return StopReason::Error(Chip8Error::StackUnderflow);
+        }
+        self.run_until(machine, None, |m| m.sp < depth)
+    }
+
+    /// Run up to `limit` instructions, stopping early once `done` returns true after an instruction,
+    /// or a breakpoint, watchpoint or condition is hit before one.
+    /// Without a limit it gives up after the run limit, and it stops if the user quits between frames.
+    fn run_until<F>(&self, machine: &mut Machine, limit: Option<usize>, done: F) -> StopReason
+    where
+        F: Fn(&Machine) -> bool,
+    {
+        let mut held: Vec<bool> = self.conditions.iter().map(|c| c.holds(machine)).collect();
+        let mut executed = 0;
+        let mut frame = machine.frame_count();
+        loop {
+            match limit {
+                Some(l) if executed >= l => return StopReason::Done,
+                None if executed >= self.run_limit => return StopReason::Limit(executed),
+                _ => {}
+            }
+            if machine.frame_count() != frame {
+                frame = machine.frame_count();
+                if machine.pump_events() {
+                    return StopReason::Quit;
+                }
+            }
+            if machine.has_exited() {
+                return StopReason::Exited;
+            }
+            if executed > 0 {
+                if let Some(reason) = self.check(machine, &mut held) {
+                    return reason;
+                }
+            }
+            if let Err(e) = machine.cycle() {
+                return StopReason::Error(e);
+            }
+            executed += 1;
+            if done(machine) {
+                return StopReason::Done;
+            }
+        }
+    }
+
+    /// Look for anything that should stop the machine before the next instruction.
+    fn check(&self, machine: &Machine, held: &mut [bool]) -> Option<StopReason> {
+        if self.breakpoints.contains(&machine.pc) {
+            return Some(StopReason::Breakpoint(machine.pc));
+        }
+        if !self.watchpoints.is_empty() {
+            // A bad instruction will be reported when it runs
+            for access in machine.pending_accesses().unwrap_or_default() {
+                if self
+                    .watchpoints
+                    .iter()
+                    .any(|&(addr, kind)| addr == access.addr && kind.matches(access.access))
+                {
+                    return Some(StopReason::Watchpoint {
+                        addr: access.addr,
+                        access: access.access,
+                    });
+                }
+            }
+        }
+        let mut ret = None;
+        for (condition, was_held) in self.conditions.iter().zip(held.iter_mut()) {
+            let holds = condition.holds(machine);
+            if holds && !*was_held && ret.is_none() {
+                ret = Some(StopReason::Condition(*condition));
+            }
+            *was_held = holds;
+        }
+        ret
+    }
+}
+
+/// A single debugger REPL command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugCommand {
+    /// `s [N]` - execute N instructions, default 1.
+    Step(usize),
+    /// `n` - step over a call.
+    StepOver,
+    /// `o` - step out of the current subroutine.
+    StepOut,
+    /// `c` - continue until something stops the machine.
+    Continue,
+    /// `b ADDR` - set a breakpoint.
+    Break(u16),
+    /// `db ADDR` - delete a breakpoint.
+    DeleteBreak(u16),
+    /// `w ADDR [r|w|rw]` - watch a memory cell, default `rw`.
+    Watch(u16, WatchKind),
+    /// `dw ADDR` - delete a watchpoint.
+    DeleteWatch(u16),
+    /// `if VX OP NN` - stop when the comparison becomes true, OP is one of `== != < <= > >=`.
+    When(Condition),
+    /// `dc N` - delete the Nth condition as listed by `i`.
+    DeleteCondition(usize),
+    /// `i` - list breakpoints, watchpoints and conditions.
+    Info,
+    /// `r` - show registers, stack and timers.
+    Registers,
+    /// `l` - show instructions around pc.
+    List,
+    /// `m ADDR [LEN]` - dump memory, default 16 bytes.
+    Memory(u16, u16),
+    /// `h` - show the available commands.
+    Help,
+    /// `q` - quit.
+    Quit,
+}
+
+/// Parse a number as hex with a `0x` prefix, or decimal otherwise.
+fn parse_number<T>(s: &str) -> Result<T>
+where
+    T: FromStr + TryFrom<u32>,
+{
+    let value = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse::<u32>(),
+    }
+    .map_err(|_| anyhow!("Not a number: {}", s))?;
+    T::try_from(value).map_err(|_| anyhow!("Out of range: {}", s))
+}
+
+impl FromStr for DebugCommand {
+    type Err = anyhow::Error;
+    /// Parse a line of REPL input.  Numbers are decimal, or hex with a `0x` prefix.
+    /// ```
+    /// # use chip8::{Comparison, Condition, DebugCommand, WatchKind};
+    /// # use pretty_assertions::assert_eq;
+    /// assert_eq!("s".parse::<DebugCommand>().unwrap(), DebugCommand::Step(1));
+    /// assert_eq!("s 10".parse::<DebugCommand>().unwrap(), DebugCommand::Step(10));
+    /// assert_eq!("b 0x2A0".parse::<DebugCommand>().unwrap(), DebugCommand::Break(0x2A0));
+    /// assert_eq!("w 0x300 w".parse::<DebugCommand>().unwrap(), DebugCommand::Watch(0x300, WatchKind::Write));
+    /// assert_eq!(
+    ///     "if V3 >= 0x10".parse::<DebugCommand>().unwrap(),
+    ///     DebugCommand::When(Condition { register: 3, comparison: Comparison::Ge, value: 0x10 })
+    /// );
+    /// assert!("b".parse::<DebugCommand>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self> {
+        use DebugCommand::*;
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let arg = |i: usize| {
+            words
+                .get(i)
+                .copied()
+                .ok_or_else(|| anyhow!("Missing argument, try h for help"))
+        };
+        match words.first().copied().unwrap_or("s") {
+            "s" | "step" => Ok(Step(words.get(1).map_or(Ok(1), |n| parse_number(n))?)),
+            "n" | "next" => Ok(StepOver),
+            "o" | "out" => Ok(StepOut),
+            "c" | "continue" => Ok(Continue),
+            "b" | "break" => Ok(Break(parse_number(arg(1)?)?)),
+            "db" => Ok(DeleteBreak(parse_number(arg(1)?)?)),
+            "w" | "watch" => {
+                let kind = match words.get(2).copied().unwrap_or("rw") {
+                    "r" => WatchKind::Read,
+                    "w" => WatchKind::Write,
+                    "rw" => WatchKind::ReadWrite,
+                    other => return Err(anyhow!("Unknown watch kind {}, use r, w or rw", other)),
+                };
+                Ok(Watch(parse_number(arg(1)?)?, kind))
+            }
+            "dw" => Ok(DeleteWatch(parse_number(arg(1)?)?)),
+            "if" => {
+                let register = arg(1)?;
+                let register = register
+                    .strip_prefix('V')
+                    .or_else(|| register.strip_prefix('v'))
+                    .and_then(|r| u8::from_str_radix(r, 16).ok())
+                    .filter(|&r| r < 16)
+                    .ok_or_else(|| anyhow!("Not a register: {}", register))?;
+                Ok(When(Condition {
+                    register,
+                    comparison: arg(2)?.parse()?,
+                    value: parse_number(arg(3)?)?,
+                }))
+            }
+            "dc" => Ok(DeleteCondition(parse_number(arg(1)?)?)),
+            "i" | "info" => Ok(Info),
+            "r" | "regs" => Ok(Registers),
+            "l" | "list" => Ok(List),
+            "m" | "mem" => Ok(Memory(
+                parse_number(arg(1)?)?,
+                words.get(2).map_or(Ok(16), |n| parse_number(n))?,
+            )),
+            "h" | "help" | "?" => Ok(Help),
+            "q" | "quit" => Ok(Quit),
+            other => Err(anyhow!("Unknown command {}, try h for help", other)),
+        }
+    }
+}
+
+/// Summary of the REPL commands, for `h`.
+pub const DEBUG_HELP: &str = "Commands - numbers are decimal, or hex with a 0x prefix:
+  s [N]            step N instructions, default 1 - an empty line steps once
+  n                step over a call
+  o                step out of the current subroutine
+  c                continue until a breakpoint, watchpoint or condition, or the window closes
+  b ADDR / db ADDR set or delete a breakpoint
+  w ADDR [r|w|rw]  watch memory reads and/or writes, default rw
+  dw ADDR          delete a watchpoint
+  if VX OP NN      stop when the comparison becomes true, OP is == != < <= > >=
+  dc N             delete the Nth condition
+  i                list breakpoints, watchpoints and conditions
+  r                show registers, stack and timers
+  l                show instructions around pc
+  m ADDR [LEN]     dump memory, default 16 bytes
+  q                quit";
@@ -0,0 +1,40 @@
+//! The ways a running program can fail.
+
+use super::opcode::RawOpcode;
+use std::{error::Error, fmt};
+
+/// Everything that can go wrong while the machine is executing a ROM.
+/// None of these are fatal to the host - the caller decides whether to halt, reset, or load another game.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Chip8Error {
+    /// 2NNN was executed with every call stack slot already in use.
+    StackOverflow,
+    /// 00EE was executed with an empty call stack.
+    StackUnderflow,
+    /// The two bytes at `pc` don't match anything in the opcode table.
+    InvalidOpcode { pc: u16, raw: RawOpcode },
+    /// An instruction tried to read or write past the end of memory.
+    /// `addr` is wider than the address space so it can say by how much.
+    MemoryOutOfBounds { addr: u32 },
+    /// 0NNN tried to call a native RCA 1802 routine at `addr`.
+    UnsupportedMachineCall { addr: u16 },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Chip8Error::*;
+        match self {
+            StackOverflow => write!(f, "Stack overflow"),
+            StackUnderflow => write!(f, "Stack underflow"),
+            InvalidOpcode { pc, raw } => {
+                write!(f, "Invalid opcode {} at {:#05X}", raw, pc)
+            }
+            MemoryOutOfBounds { addr } => write!(f, "Memory address {:#06X} out of bounds", addr),
+            UnsupportedMachineCall { addr } => {
+                write!(f, "Unsupported machine code call to {:#05X}", addr)
+            }
+        }
+    }
+}
+
+impl Error for Chip8Error {}
@@ -0,0 +1,309 @@
+//! Which keyboard keys and controller buttons press which keypad keys.
+//!
+//! Keyboard keys are named the way SDL names scancodes - `Q`, `1`, `Left`, `Keypad 5` - so a binding
+//! follows the key's position whatever the keyboard layout.  Controller inputs are SDL game controller
+//! button names like `a` or `dpup`, or an axis and direction like `leftx-` or `righty+`.
+//!
+//! With the `keymap` feature, bindings can be read from TOML, with overrides for particular ROMs:
+//! ```toml
+//! [keys]
+//! Q = 0x4
+//! Up = 0x5
+//!
+//! [buttons]
+//! a = 0x6
+//!
+//! [roms.pong.keys]
+//! Up = 0x1
+//! Down = 0x4
+//! ```
+
+use super::machine::NUM_KEYS;
+#[cfg(feature = "keymap")]
+use anyhow::Result;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+#[cfg(feature = "keymap")]
+use std::{fs, path::Path};
+
+/// The usual layout, with the keypad's four rows on the left of the keyboard:
+/// ```txt
+/// Keypad       Keyboard
+/// |1|2|3|C| => |1|2|3|4|
+/// |4|5|6|D| => |Q|W|E|R|
+/// |7|8|9|E| => |A|S|D|F|
+/// |A|0|B|F| => |Z|X|C|V|
+/// ```
+const DEFAULT_KEYS: [(&str, u8); NUM_KEYS] = [
+    ("1", 0x1),
+    ("2", 0x2),
+    ("3", 0x3),
+    ("4", 0xC),
+    ("Q", 0x4),
+    ("W", 0x5),
+    ("E", 0x6),
+    ("R", 0xD),
+    ("A", 0x7),
+    ("S", 0x8),
+    ("D", 0x9),
+    ("F", 0xE),
+    ("Z", 0xA),
+    ("X", 0x0),
+    ("C", 0xB),
+    ("V", 0xF),
+];
+
+/// Controller defaults, with the d-pad and left stick where W, A, S and D are on the keyboard.
+const DEFAULT_BUTTONS: [(&str, u8); 10] = [
+    ("dpup", 0x5),
+    ("dpleft", 0x7),
+    ("dpdown", 0x8),
+    ("dpright", 0x9),
+    ("lefty-", 0x5),
+    ("leftx-", 0x7),
+    ("lefty+", 0x8),
+    ("leftx+", 0x9),
+    ("a", 0x6),
+    ("b", 0x4),
+];
+
+/// Input bindings for the keypad.
+/// ```
+/// # use chip8::KeyMap;
+/// # use pretty_assertions::assert_eq;
+/// let mut keymap = KeyMap::default();
+/// assert_eq!(keymap.keyboard("q"), Some(0x4));
+/// keymap.bind_key(0x4, "Up");
+/// assert_eq!(keymap.keyboard("Up"), Some(0x4));
+/// assert_eq!(keymap.keyboard("Q"), None);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct KeyMap {
+    /// Keyboard scancode names, and the keypad key each presses
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub keys: BTreeMap<String, u8>,
+    /// Controller button and axis names, and the keypad key each presses
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub buttons: BTreeMap<String, u8>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        let bindings = |defaults: &[(&str, u8)]| {
+            defaults
+                .iter()
+                .map(|&(name, key)| (name.to_string(), key))
+                .collect()
+        };
+        Self {
+            keys: bindings(&DEFAULT_KEYS),
+            buttons: bindings(&DEFAULT_BUTTONS),
+        }
+    }
+}
+
+impl KeyMap {
+    /// Keypad key pressed by the keyboard key `name`, ignoring case.
+    pub fn keyboard(&self, name: &str) -> Option<u8> {
+        lookup(&self.keys, name)
+    }
+
+    /// Keypad key pressed by the controller button or axis direction `name`, ignoring case.
+    pub fn button(&self, name: &str) -> Option<u8> {
+        lookup(&self.buttons, name)
+    }
+
+    /// Make the keyboard key `name` the one and only key for keypad `key`.
+    pub fn bind_key(&mut self, key: u8, name: &str) {
+        self.keys
+            .retain(|bound, &mut k| k != key && !bound.eq_ignore_ascii_case(name));
+        self.keys.insert(name.to_string(), key);
+    }
+
+    /// Keyboard keys bound to keypad `key`.
+    pub fn keys_for(&self, key: u8) -> Vec<&str> {
+        self.keys
+            .iter()
+            .filter(|&(_, &k)| k == key)
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /// Add everything bound in `other`, replacing any binding for the same input here.
+    pub fn merge(&mut self, other: &KeyMap) {
+        for (name, &key) in &other.keys {
+            self.keys
+                .retain(|bound, _| !bound.eq_ignore_ascii_case(name));
+            self.keys.insert(name.clone(), key);
+        }
+        for (name, &key) in &other.buttons {
+            self.buttons
+                .retain(|bound, _| !bound.eq_ignore_ascii_case(name));
+            self.buttons.insert(name.clone(), key);
+        }
+    }
+
+    /// Read bindings back from `to_toml`.
+    #[cfg(feature = "keymap")]
+    pub fn from_toml(s: &str) -> Result<Self> {
+        Ok(toml::from_str(s)?)
+    }
+
+    /// Write bindings as TOML.
+    #[cfg(feature = "keymap")]
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+}
+
+/// Look up a binding without caring about case
+fn lookup(bindings: &BTreeMap<String, u8>, name: &str) -> Option<u8> {
+    bindings
+        .iter()
+        .find(|(bound, _)| bound.eq_ignore_ascii_case(name))
+        .map(|(_, &key)| key)
+        .filter(|&key| (key as usize) < NUM_KEYS)
+}
+
+/// A key bindings file: the bindings to use, and overrides for particular ROMs.
+/// Leaving `[keys]` or `[buttons]` out keeps the default layout for them.
+/// ```
+/// # use chip8::KeyConfig;
+/// # use pretty_assertions::assert_eq;
+/// let config = KeyConfig::from_toml("[roms.pong.keys]\nUp = 1\n").unwrap();
+/// assert_eq!(config.keymap("pong").keyboard("Up"), Some(0x1));
+/// assert_eq!(config.keymap("brix").keyboard("Up"), None);
+/// assert_eq!(config.keymap("brix").keyboard("Q"), Some(0x4));
+/// ```
+#[cfg(feature = "keymap")]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyConfig {
+    #[serde(default)]
+    pub keys: Option<BTreeMap<String, u8>>,
+    #[serde(default)]
+    pub buttons: Option<BTreeMap<String, u8>>,
+    /// Bindings added on top for a ROM, by its bundled name or file name without the extension
+    #[serde(default)]
+    pub roms: BTreeMap<String, KeyMap>,
+}
+
+#[cfg(feature = "keymap")]
+impl KeyConfig {
+    pub fn from_toml(s: &str) -> Result<Self> {
+        Ok(toml::from_str(s)?)
+    }
+
+    /// Read a bindings file from disk.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// The bindings to use for the ROM called `rom`.
+    pub fn keymap(&self, rom: &str) -> KeyMap {
+        let mut ret = KeyMap::default();
+        if let Some(keys) = &self.keys {
+            ret.keys = keys.clone();
+        }
+        if let Some(buttons) = &self.buttons {
+            ret.buttons = buttons.clone();
+        }
+        if let Some(overrides) = self.roms.get(rom) {
+            ret.merge(overrides);
+        }
+        ret
+    }
+}
+
+/// SDL's name for the key with the DOM `KeyboardEvent.code` `code`, so the browser can share bindings.
+/// Codes without an obvious SDL name are kept as they are.
+/// ```
+/// # use chip8::key_name_from_code;
+/// # use pretty_assertions::assert_eq;
+/// assert_eq!(key_name_from_code("KeyQ"), "Q");
+/// assert_eq!(key_name_from_code("Digit4"), "4");
+/// assert_eq!(key_name_from_code("ArrowUp"), "Up");
+/// assert_eq!(key_name_from_code("Numpad5"), "Keypad 5");
+/// ```
+pub fn key_name_from_code(code: &str) -> String {
+    if let Some(letter) = code.strip_prefix("Key") {
+        return letter.to_string();
+    }
+    if let Some(digit) = code.strip_prefix("Digit") {
+        return digit.to_string();
+    }
+    if let Some(arrow) = code.strip_prefix("Arrow") {
+        return arrow.to_string();
+    }
+    if let Some(key) = code.strip_prefix("Numpad") {
+        return format!("Keypad {}", key);
+    }
+    match code {
+        "Enter" => "Return",
+        "ShiftLeft" => "Left Shift",
+        "ShiftRight" => "Right Shift",
+        "ControlLeft" => "Left Ctrl",
+        "ControlRight" => "Right Ctrl",
+        "AltLeft" => "Left Alt",
+        "AltRight" => "Right Alt",
+        "Semicolon" => ";",
+        "Comma" => ",",
+        "Slash" => "/",
+        other => other,
+    }
+    .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_default_covers_every_key() {
+        let keymap = KeyMap::default();
+        for key in 0..NUM_KEYS as u8 {
+            assert_eq!(keymap.keys_for(key).len(), 1, "{:X}", key);
+        }
+        assert_eq!(keymap.button("DPUP"), Some(0x5));
+    }
+
+    #[test]
+    fn test_merge_replaces_the_same_input() {
+        let mut keymap = KeyMap::default();
+        let mut overrides = KeyMap {
+            keys: BTreeMap::new(),
+            buttons: BTreeMap::new(),
+        };
+        overrides.keys.insert("q".to_string(), 0x1);
+        keymap.merge(&overrides);
+        assert_eq!(keymap.keyboard("Q"), Some(0x1));
+        assert_eq!(keymap.keys.len(), NUM_KEYS);
+    }
+
+    #[cfg(feature = "keymap")]
+    #[test]
+    fn test_toml_round_trip() {
+        let mut keymap = KeyMap::default();
+        keymap.bind_key(0x0, "Space");
+        assert_eq!(
+            KeyMap::from_toml(&keymap.to_toml().unwrap()).unwrap(),
+            keymap
+        );
+    }
+
+    #[cfg(feature = "keymap")]
+    #[test]
+    fn test_config_replaces_defaults() {
+        let config =
+            KeyConfig::from_toml("[keys]\nUp = 0x5\n\n[roms.brix.buttons]\nx = 0x4\n").unwrap();
+        let keymap = config.keymap("brix");
+        assert_eq!(keymap.keyboard("Up"), Some(0x5));
+        assert_eq!(keymap.keyboard("W"), None);
+        // Buttons weren't given, so the defaults stay and the override goes on top
+        assert_eq!(keymap.button("dpup"), Some(0x5));
+        assert_eq!(keymap.button("x"), Some(0x4));
+        assert!(KeyConfig::from_toml("[keys]\nUp = \"five\"\n").is_err());
+    }
+}
@@ -0,0 +1,207 @@
+//! What every byte of memory has been used for, to catch self-modifying code and reads of memory
+//! nothing ever set up.
+
+use super::*;
+use std::collections::HashSet;
+
+/// Everything that has happened to one byte of memory.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ByteUse {
+    /// Part of the built in fonts
+    pub font: bool,
+    /// Filled from the ROM
+    pub loaded: bool,
+    /// An instruction started here
+    pub executed: bool,
+    /// The second byte of an instruction, or the address after `ld i, long`
+    pub operand: bool,
+    /// Read as data by an instruction
+    pub read: bool,
+    /// Written by an instruction
+    pub written: bool,
+}
+
+/// The main thing a byte has been used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteKind {
+    /// Never loaded or touched
+    Unused,
+    /// Built in font, never run or written
+    Font,
+    /// Run as part of an instruction
+    Code,
+    /// Loaded or read, but never run
+    Data,
+    /// Written while running, but never run
+    Written,
+}
+
+impl ByteUse {
+    /// Whether anything had set the byte up before it was read.
+    pub fn is_initialised(&self) -> bool {
+        self.font || self.loaded || self.written
+    }
+
+    /// Whether the byte has run as part of an instruction.
+    pub fn is_code(&self) -> bool {
+        self.executed || self.operand
+    }
+
+    /// Sum the byte up as a single kind, code first.
+    pub fn kind(&self) -> ByteKind {
+        if self.is_code() {
+            ByteKind::Code
+        } else if self.written {
+            ByteKind::Written
+        } else if self.font {
+            ByteKind::Font
+        } else if self.loaded || self.read {
+            ByteKind::Data
+        } else {
+            ByteKind::Unused
+        }
+    }
+}
+
+/// Something suspicious a program did with memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryIssue {
+    /// The instruction at `pc` wrote to `addr`, which had already run as code
+    SelfModifyingCode { pc: u16, addr: u16 },
+    /// The instruction at `pc` read `addr`, which nothing had loaded or written
+    UninitialisedRead { pc: u16, addr: u16 },
+}
+
+impl fmt::Display for MemoryIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryIssue::SelfModifyingCode { pc, addr } => write!(
+                f,
+                "Instruction at {:#05X} wrote over code at {:#06X}",
+                pc, addr
+            ),
+            MemoryIssue::UninitialisedRead { pc, addr } => write!(
+                f,
+                "Instruction at {:#05X} read uninitialised memory at {:#06X}",
+                pc, addr
+            ),
+        }
+    }
+}
+
+/// Tracks what every byte of memory is used for, once set with `Machine::set_access_map`.
+/// Set it before loading a ROM, so it knows which bytes the ROM filled.
+/// ```
+/// # use chip8::{AccessMap, ByteKind, HeadlessContext, Machine, MemoryIssue};
+/// # use pretty_assertions::assert_eq;
+/// let mut machine = Machine::new(Box::new(HeadlessContext::new(1)));
+/// machine.set_access_map(AccessMap::new());
+/// // ld i, 0x200; ld v0, 0x12; ld [i], v0 - overwriting the first instruction
+/// machine.load_rom(&[0xA2, 0x00, 0x60, 0x12, 0xF0, 0x55]).unwrap();
+/// for _ in 0..3 {
+///     machine.cycle().unwrap();
+/// }
+/// let map = machine.access_map().unwrap();
+/// assert_eq!(map.kind(0x202), ByteKind::Code);
+/// assert_eq!(map.kind(0x000), ByteKind::Font);
+/// assert_eq!(map.issues(), &[MemoryIssue::SelfModifyingCode { pc: 0x204, addr: 0x200 }]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct AccessMap {
+    bytes: Vec<ByteUse>,
+    /// Each problem found, the first time it happened at its address
+    issues: Vec<MemoryIssue>,
+    /// Addresses already reported, and whether it was for a write
+    reported: HashSet<(u16, bool)>,
+}
+
+impl Default for AccessMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AccessMap {
+    /// A fresh map, knowing only where the fonts are.
+    pub fn new() -> Self {
+        let mut bytes = vec![ByteUse::default(); MEM_SIZE];
+        let fonts = (0..FONTSET.len())
+            .chain(BIG_FONTSET_START as usize..BIG_FONTSET_START as usize + BIG_FONTSET.len());
+        for addr in fonts {
+            bytes[addr].font = true;
+        }
+        Self {
+            bytes,
+            issues: Vec::new(),
+            reported: HashSet::new(),
+        }
+    }
+
+    /// Everything that has happened to the byte at `addr`.
+    pub fn get(&self, addr: u16) -> ByteUse {
+        self.bytes[addr as usize]
+    }
+
+    /// What the byte at `addr` has mostly been used for.
+    pub fn kind(&self, addr: u16) -> ByteKind {
+        self.get(addr).kind()
+    }
+
+    /// Problems found so far, in the order they happened.
+    pub fn issues(&self) -> &[MemoryIssue] {
+        &self.issues
+    }
+
+    /// Addresses instructions have started at, lowest first.
+    pub fn executed(&self) -> impl Iterator<Item = u16> + '_ {
+        self.bytes
+            .iter()
+            .enumerate()
+            .filter(|(_, byte)| byte.executed)
+            .map(|(addr, _)| addr as u16)
+    }
+
+    /// Start again for a ROM of `len` bytes loaded at `start`.
+    pub(super) fn load(&mut self, start: u16, len: usize) {
+        *self = Self::new();
+        for byte in &mut self.bytes[start as usize..start as usize + len] {
+            byte.loaded = true;
+        }
+    }
+
+    /// Note that `opcode` ran from `pc`, touching `accesses`.
+    pub(super) fn record(&mut self, pc: u16, opcode: Opcode, accesses: &[MemoryAccess]) {
+        self.bytes[pc as usize].executed = true;
+        self.bytes[pc.wrapping_add(1) as usize].operand = true;
+        for access in accesses {
+            let addr = access.addr;
+            // The address after `ld i, long` is part of the instruction, not data
+            if opcode == Opcode::LongSetIdx {
+                self.bytes[addr as usize].operand = true;
+                continue;
+            }
+            let byte = &mut self.bytes[addr as usize];
+            match access.access {
+                Access::Read => {
+                    byte.read = true;
+                    if !byte.is_initialised() {
+                        self.report(MemoryIssue::UninitialisedRead { pc, addr }, addr, false);
+                    }
+                }
+                Access::Write => {
+                    byte.written = true;
+                    if byte.is_code() {
+                        self.report(MemoryIssue::SelfModifyingCode { pc, addr }, addr, true);
+                    }
+                }
+            }
+        }
+    }
+
+    fn report(&mut self, issue: MemoryIssue, addr: u16, write: bool) {
+        if self.reported.insert((addr, write)) {
+            log::warn!("{}", issue);
+            self.issues.push(issue);
+        }
+    }
+}
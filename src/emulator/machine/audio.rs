@@ -0,0 +1,44 @@
+//! The XO-CHIP audio pattern buffer, which replaces the fixed buzzer tone.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Size of the pattern buffer in bytes - 128 one-bit samples.
+pub const AUDIO_PATTERN_BYTES: usize = 16;
+/// Number of one-bit samples in the pattern buffer.
+pub const AUDIO_PATTERN_SAMPLES: usize = AUDIO_PATTERN_BYTES * 8;
+/// Pitch register value that plays the pattern back at 4000 samples per second.
+pub const DEFAULT_PITCH: u8 = 64;
+
+/// What the buzzer should sound like: a looping 1-bit waveform and how fast to play it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AudioPattern {
+    /// The waveform, most significant bit of the first byte plays first.
+    pub buffer: [u8; AUDIO_PATTERN_BYTES],
+    /// The FX3A pitch register.
+    pub pitch: u8,
+}
+
+impl Default for AudioPattern {
+    /// A 500Hz square wave, close to the tone of the original buzzer.
+    fn default() -> Self {
+        Self {
+            buffer: [0xF0; AUDIO_PATTERN_BYTES],
+            pitch: DEFAULT_PITCH,
+        }
+    }
+}
+
+impl AudioPattern {
+    /// Samples per second to play the pattern at: 4000 * 2^((pitch - 64) / 48).
+    pub fn sample_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - DEFAULT_PITCH as f32) / 48.0)
+    }
+
+    /// Check if the sample at position `i` is high.  Wraps around, since the pattern loops.
+    pub fn sample(&self, i: usize) -> bool {
+        let i = i % AUDIO_PATTERN_SAMPLES;
+        self.buffer[i / 8] & (0x80 >> (i % 8)) != 0
+    }
+}
@@ -0,0 +1,79 @@
+//! Read-only views into the machine, for debuggers and other tools.
+
+use super::*;
+
+/// Whether an instruction reads or writes a memory cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// A single memory cell an instruction is about to touch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MemoryAccess {
+    pub addr: u16,
+    pub access: Access,
+}
+
+impl Machine {
+    /// All sixteen registers, V0 through VF.
+    pub fn registers(&self) -> &[u8; NUM_REGISTERS] {
+        &self.registers
+    }
+
+    /// The whole 64K address space.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Addresses of the calls still waiting to return, oldest first.
+    pub fn call_stack(&self) -> &[u16] {
+        &self.stack[..self.sp]
+    }
+
+    /// The display as the program last left it.
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    /// Decode the instruction stored at `addr`.
+    pub fn opcode_at(&self, addr: u16) -> Result<Opcode, Chip8Error> {
+        let raw = RawOpcode::from(self.memory_get_word(addr)?);
+        Opcode::try_from(raw).map_err(|_| Chip8Error::InvalidOpcode { pc: addr, raw })
+    }
+
+    /// Decode the instruction about to run.
+    pub fn current_opcode(&self) -> Result<Opcode, Chip8Error> {
+        self.opcode_at(self.pc)
+    }
+
+    /// Work out which memory cells the instruction at `pc` will read or write, without running it.
+    /// Instruction fetches aren't included, only the data the instruction itself uses.
+    pub fn pending_accesses(&self) -> Result<Vec<MemoryAccess>, Chip8Error> {
+        use Opcode::*;
+        let (start, len, access) = match self.current_opcode()? {
+            Draw(_, _, n) => {
+                let bytes = if n == 0 { 32 } else { n as u16 };
+                let planes = self.planes.count_ones() as u16;
+                (self.idx, bytes * planes, Access::Read)
+            }
+            BCD(_) => (self.idx, 3, Access::Write),
+            DumpRegisters(x) => (self.idx, x as u16 + 1, Access::Write),
+            FillRegisters(x) => (self.idx, x as u16 + 1, Access::Read),
+            SaveRange(x, y) => (self.idx, (x.max(y) - x.min(y)) as u16 + 1, Access::Write),
+            LoadRange(x, y) => (self.idx, (x.max(y) - x.min(y)) as u16 + 1, Access::Read),
+            LoadAudio => (self.idx, AUDIO_PATTERN_BYTES as u16, Access::Read),
+            LongSetIdx => (self.pc.wrapping_add(2), 2, Access::Read),
+            _ => return Ok(Vec::new()),
+        };
+        (0..len)
+            .map(|i| {
+                Ok(MemoryAccess {
+                    addr: Self::memory_offset(start, i)?,
+                    access,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,111 @@
+//! 0NNN - calls into RCA 1802 machine code, which only the COSMAC VIP could actually run.
+//!
+//! Most ROMs never use them, and the ones that do mostly call a handful of well known routines.
+//! `MachineCalls` picks whether a call stops the machine, gets skipped, or goes to a
+//! `MachineCallHandler` that emulates whatever routine lives at the address.
+
+use super::*;
+use anyhow::Error;
+use std::str::FromStr;
+
+/// Height of the 64x64 screen the two-page hi-res VIP interpreter gives.
+pub const VIP_HIRES_PIXEL_ROWS: u32 = 64;
+
+/// The parts of the machine a native routine can get at.
+pub struct MachineCallState<'a> {
+    pub memory: &'a mut [u8],
+    pub registers: &'a mut [u8; NUM_REGISTERS],
+    pub idx: &'a mut u16,
+    /// On a call this is the 0NNN itself - the machine moves past it once the routine returns
+    pub pc: &'a mut u16,
+    pub screen: &'a mut Screen,
+}
+
+/// Emulates native routines in place of the 1802 code a ROM calls.
+pub trait MachineCallHandler {
+    /// Look over a freshly loaded ROM, for programs that patch the interpreter before they start.
+    fn load(&mut self, _state: MachineCallState) {}
+
+    /// Run the routine at `addr`, returning false if there's no known routine there.
+    fn call(&mut self, addr: u16, state: MachineCallState) -> bool;
+}
+
+/// What happens when a program executes 0NNN.
+#[derive(Default)]
+pub enum MachineCalls {
+    /// Stop with `Chip8Error::UnsupportedMachineCall`
+    #[default]
+    Error,
+    /// Carry on as if it weren't there
+    Ignore,
+    /// Run it with the handler - anything the handler doesn't know is still an error
+    Dispatch(Box<dyn MachineCallHandler>),
+}
+
+impl fmt::Debug for MachineCalls {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MachineCalls::Error => write!(f, "Error"),
+            MachineCalls::Ignore => write!(f, "Ignore"),
+            MachineCalls::Dispatch(_) => write!(f, "Dispatch(..)"),
+        }
+    }
+}
+
+impl FromStr for MachineCalls {
+    type Err = Error;
+
+    /// Look up a policy by name, where `vip` dispatches to `VipRoutines`.
+    /// ```
+    /// # use chip8::MachineCalls;
+    /// assert!(matches!("ignore".parse::<MachineCalls>().unwrap(), MachineCalls::Ignore));
+    /// assert!(matches!("VIP".parse::<MachineCalls>().unwrap(), MachineCalls::Dispatch(_)));
+    /// assert!("1802".parse::<MachineCalls>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "error" => Ok(Self::Error),
+            "ignore" => Ok(Self::Ignore),
+            "vip" => Ok(Self::Dispatch(Box::new(VipRoutines))),
+            _ => Err(anyhow!(
+                "Unknown machine call policy {} - expected error, ignore, or vip",
+                s
+            )),
+        }
+    }
+}
+
+/// The routines classic COSMAC VIP programs are known to call.
+///
+/// Two-page hi-res programs start with `1260`, and carry their own 1802 patch to the interpreter
+/// up to 0x2BF.  They get a 64x64 screen and start at 0x2C0 instead, with `0230` clearing it.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VipRoutines;
+
+impl VipRoutines {
+    /// Where the CHIP-8 part of a hi-res program starts.
+    const HIRES_START: u16 = 0x2C0;
+    /// Clears the 64x64 screen.
+    const HIRES_CLEAR: u16 = 0x230;
+}
+
+impl MachineCallHandler for VipRoutines {
+    fn load(&mut self, state: MachineCallState) {
+        let start = PC_BEGIN as usize;
+        if state.memory[start..start + 2] == [0x12, 0x60] {
+            log::info!("Found a two-page hi-res program, switching to 64x64");
+            *state.screen = Screen::new(PIXEL_COLS, VIP_HIRES_PIXEL_ROWS);
+            *state.pc = Self::HIRES_START;
+        }
+    }
+
+    fn call(&mut self, addr: u16, state: MachineCallState) -> bool {
+        match addr {
+            Self::HIRES_CLEAR => {
+                state.screen.clear_planes(ALL_PLANES);
+                true
+            }
+            _ => false,
+        }
+    }
+}
@@ -1,50 +1,60 @@
 //! The top-level software representation of the Chip8 virtual machine
 
-use super::{super::ROMS, opcode::*, *};
-use anyhow::{anyhow, Result};
+use super::{super::ROMS, error::Chip8Error, opcode::*, quirks::*, *};
+use anyhow::{anyhow, Context as _, Result};
 use std::{
-    fmt,
+    convert::TryFrom,
+    fmt, fs,
+    path::Path,
     sync::{Arc, RwLock},
 };
 
-#[cfg(test)]
-use std::convert::TryFrom;
-
+mod access_map;
+mod audio;
+mod inspect;
+mod machine_call;
+mod rewind;
+mod scheduler;
+mod screen;
+mod state;
 #[cfg(test)]
 mod test;
 
-use context::Context;
+pub use access_map::*;
+pub use audio::*;
+pub use inspect::*;
+pub use machine_call::*;
+pub use rewind::*;
+pub use scheduler::*;
+pub use screen::*;
+pub use state::*;
+
+use context::{Command, Context};
 
 // TODO maybe use FixedBitSet for keys, screen, etc.
 
-/// Total memory available.
-const MEM_SIZE: usize = 4096;
+/// Total memory available - the full 16-bit address space, as in XO-CHIP.
+const MEM_SIZE: usize = 0x10000;
 /// Number of registers avaialable for short-term storage.
 const NUM_REGISTERS: usize = 16;
 /// Keypad size.
 pub const NUM_KEYS: usize = 16;
-/// Screen height.
-pub const PIXEL_ROWS: u32 = 32;
-/// Screen width.
-pub const PIXEL_COLS: u32 = 64;
+/// Number of RPL user flags available to FX75/FX85 - SUPER-CHIP had 8, XO-CHIP has 16.
+const NUM_RPL_FLAGS: usize = 16;
 /// Call stack depth.
 const STACK_SIZE: usize = 16;
 /// Starting memory location for the program to run - earlier cells are machine-reserved.
 const PC_BEGIN: u16 = 0x200;
+/// Largest program that fits between `PC_BEGIN` and the end of memory.
+const MAX_ROM_SIZE: usize = MEM_SIZE - PC_BEGIN as usize;
 
-// Game speed constants
+/// Default CPU speed.
 pub const CYCLES_PER_SECOND: u64 = 500;
-pub const CYCLES_PER_SLEEP: u64 = 10;
-pub const MILLIS_PER_SLEEP: f64 = (CYCLES_PER_SLEEP as f64 / CYCLES_PER_SECOND as f64) * 1000.0;
-
-/// Helper const for the total number of screen pixels.
-const TOTAL_PIXELS: u32 = PIXEL_COLS * PIXEL_ROWS;
-
-/// The pixel array
-pub type Screen = [u8; TOTAL_PIXELS as usize];
-pub const BLANK_SCREEN: Screen = [0; TOTAL_PIXELS as usize];
-
-// TODO constant accessor for Screen??
+/// How many times faster turbo mode runs.
+pub const TURBO_FACTOR: u64 = 4;
+/// How long `run` sleeps between checking the clock.
+#[cfg(not(feature = "wasm"))]
+const MILLIS_PER_SLEEP: u64 = 1;
 
 /// The key state array.
 /// This has to use thread-safe interior mutability to accommodate the Wasm event listener
@@ -72,6 +82,7 @@ impl Keys {
     }
 
     /// Release a key
+    #[cfg_attr(not(any(feature = "sdl", feature = "wasm")), allow(dead_code))]
     pub fn key_up(&self, key: u8) {
         self.state.write().unwrap()[key as usize] = false;
     }
@@ -87,6 +98,7 @@ impl Keys {
     }
 
     // Get the internal state
+    #[cfg_attr(not(any(feature = "sdl", feature = "wasm")), allow(dead_code))]
     pub fn inner(&self) -> [bool; NUM_KEYS] {
         *self.state.read().unwrap()
     }
@@ -108,39 +120,6 @@ impl fmt::Display for Keys {
     }
 }
 
-/// Helper to map a keyboard key to a hex key
-///
-///Keypad                   Keyboard
-///
-///|1|2|3|C| => |1|2|3|4|
-///
-///|4|5|6|D| => |Q|W|E|R|
-///
-///|7|8|9|E| => |A|S|D|F|
-///
-///|A|0|B|F| =>  |Z|X|C|V|
-pub fn keyboard_to_keypad(keyboard: char) -> Result<u8> {
-    match keyboard.to_ascii_uppercase() {
-        '1' => Ok(1),
-        '2' => Ok(2),
-        '3' => Ok(3),
-        '4' => Ok(0xC),
-        'Q' => Ok(4),
-        'W' => Ok(5),
-        'E' => Ok(6),
-        'R' => Ok(0xD),
-        'A' => Ok(7),
-        'S' => Ok(8),
-        'D' => Ok(9),
-        'F' => Ok(0xE),
-        'Z' => Ok(0xA),
-        'X' => Ok(0),
-        'C' => Ok(0xB),
-        'V' => Ok(0xF),
-        _ => Err(anyhow!("Unsupported keyboard key")),
-    }
-}
-
 /// The sprites used to render hex digits:
 /// ```txt
 /// DEC   HEX    BIN         RESULT    DEC   HEX    BIN         RESULT
@@ -169,17 +148,42 @@ const FONTSET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+/// Where the big SUPER-CHIP font starts in memory, right after the small one.
+const BIG_FONTSET_START: u16 = 0x50;
+
+/// The 8x10 sprites used by FX30 to render big hex digits, as drawn by Octo.
+const BIG_FONTSET: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
 /// The top-level software representation of the Chip8 machine
 pub struct Machine {
     /// Trait objecct for interfacing with the outside world.
     context: Box<dyn Context>,
     /// The current opcode
     opcode: Opcode,
-    /// Available memory space - 4K
+    /// Available memory space - 64K
     /// 0x000-0x1FF - Chip 8 interpreter (contains font set in emu)
-    /// 0x050-0x0A0 - Used for the built in 4x5 pixel font set (0-F)
+    /// 0x000-0x04F - Used for the built in 4x5 pixel font set (0-F)
+    /// 0x050-0x0EF - Used for the SUPER-CHIP 8x10 pixel font set (0-F)
     /// 0x200-0xFFF - Program ROM and work RAM
-    memory: [u8; MEM_SIZE],
+    /// 0x1000-0xFFFF - Extra XO-CHIP program ROM and work RAM
+    memory: Vec<u8>,
     /// CPU Registers
     /// There are 15 general purpose registers, V0 through VE.
     /// The 16th register is the "carry" flag
@@ -188,13 +192,15 @@ pub struct Machine {
     pub idx: u16,
     /// Program counter
     pub pc: u16,
-    /// Graphics system - 2048 total pixels, arranged 64x32
+    /// Graphics system - 64x32, or 128x64 in SUPER-CHIP high resolution mode
     screen: Screen,
+    /// Bitmask of the XO-CHIP planes that drawing, clearing and scrolling apply to
+    planes: u8,
     /// Flag to track whether we need to redraw
     pub draw_flag: bool,
     /// Delay timer - 60Hz, counts down if above 0
     pub delay_timer: u8,
-    /// Sound timer - buzzes at 0.  60Hz, counts down if above 0\
+    /// Sound timer - buzzes while above 0.  60Hz, counts down if above 0
     pub sound_timer: u8,
     /// Call stack, stores program counters of each call site
     pub stack: [u16; STACK_SIZE],
@@ -204,6 +210,40 @@ pub struct Machine {
     key: Keys,
     /// The name of the currently loaded game
     pub current_game: Option<String>,
+    /// How to interpret the ambiguous instructions
+    quirks: Quirks,
+    /// SUPER-CHIP RPL user flags, which survive loading a new game
+    rpl_flags: [u8; NUM_RPL_FLAGS],
+    /// Set once the program executes 00FD
+    exited: bool,
+    /// XO-CHIP audio pattern buffer and pitch, played while the sound timer runs
+    audio: AudioPattern,
+    /// Pattern the context is currently sounding, if any
+    tone: Option<AudioPattern>,
+    /// Paces instructions and timer ticks
+    scheduler: Scheduler,
+    /// Number of 60Hz timer ticks so far - the emulated frame count
+    frames: u64,
+    /// Set while the user has paused emulation
+    paused: bool,
+    /// A frame advance waiting to run while paused
+    frame_advance: bool,
+    /// Set while running `TURBO_FACTOR` times faster
+    turbo: bool,
+    /// Recent states, one per frame, to step back through
+    rewind: Rewind,
+    /// Set while the user holds rewind
+    rewinding: bool,
+    /// Instructions' worth of time towards stepping back another frame
+    rewind_progress: u64,
+    /// Writes out each instruction as it runs, if set
+    tracer: Option<Tracer>,
+    /// Counts where the time goes, if set
+    profiler: Option<Profiler>,
+    /// Keeps track of what each byte of memory is used for, if set
+    access_map: Option<AccessMap>,
+    /// What 0NNN does
+    machine_calls: MachineCalls,
 }
 
 impl Machine {
@@ -211,15 +251,21 @@ impl Machine {
 
     /// Initialize memory and registers.
     pub fn new(context: Box<dyn Context>) -> Self {
+        Self::with_quirks(context, Quirks::default())
+    }
+
+    /// Initialize memory and registers, interpreting ambiguous instructions according to `quirks`.
+    pub fn with_quirks(context: Box<dyn Context>, quirks: Quirks) -> Self {
         // Stack, registers, memory, timers, and program counters all have sensible defaults
         let mut ret = Self {
             context,
             opcode: Opcode::default(),
-            memory: [0; MEM_SIZE],
+            memory: vec![0; MEM_SIZE],
             registers: [0; NUM_REGISTERS],
             idx: 0,
             pc: PC_BEGIN,
-            screen: BLANK_SCREEN,
+            screen: Screen::default(),
+            planes: 1,
             draw_flag: true,
             delay_timer: 0xFF,
             sound_timer: 0xFF,
@@ -227,6 +273,23 @@ impl Machine {
             sp: 0,
             key: Keys::new(),
             current_game: None,
+            quirks,
+            rpl_flags: [0; NUM_RPL_FLAGS],
+            exited: false,
+            audio: AudioPattern::default(),
+            tone: None,
+            scheduler: Scheduler::new(CYCLES_PER_SECOND),
+            frames: 0,
+            paused: false,
+            frame_advance: false,
+            turbo: false,
+            rewind: Rewind::new((DEFAULT_REWIND_SECONDS * TIMER_HZ) as usize),
+            rewinding: false,
+            rewind_progress: 0,
+            tracer: None,
+            profiler: None,
+            access_map: None,
+            machine_calls: MachineCalls::default(),
         };
         // The fonts are the same for every game, we can just load once here.
         ret.load_fontset();
@@ -234,82 +297,456 @@ impl Machine {
         ret
     }
 
-    /// Locate a program file by filename and load into memory.
+    /// Locate a compiled-in program by name and load into memory.
     pub fn load_game(&mut self, name: &str) -> Result<usize> {
-        // Clear the memory to make way
-        self.reset();
-
-        // All the games live in the GAMES_DIR, have an uppercase name, and a .ch8 extension
         if let Some(rom) = ROMS.get(name) {
+            let num_bytes = self.load_rom(rom)?;
             self.current_game = Some(name.to_string());
-            // Load in memory starting at location 512 (0x200), which is where the pc pointer starts
-            for (idx, &byte) in rom.iter().enumerate() {
-                self.memory_set(idx as u16 + self.pc, byte);
-            }
-            let num_bytes = rom.len();
-            println!("Loaded {}: {} bytes", name, num_bytes);
+            log::info!("Loaded {}: {} bytes", name, num_bytes);
             Ok(num_bytes)
         } else {
             Err(anyhow!("Game {} not included", name))
         }
     }
 
+    /// Load a program from raw bytes into memory.
+    /// The program must fit between 0x200 and the end of memory.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<usize> {
+        if rom.len() > MAX_ROM_SIZE {
+            return Err(anyhow!(
+                "ROM is {} bytes, but only {} bytes fit in memory",
+                rom.len(),
+                MAX_ROM_SIZE
+            ));
+        }
+
+        // Clear the memory to make way
+        self.reset();
+        self.current_game = None;
+
+        // Load in memory starting at location 512 (0x200), which is where the pc pointer starts
+        let start = PC_BEGIN as usize;
+        self.memory[start..start + rom.len()].copy_from_slice(rom);
+        if let Some(access_map) = &mut self.access_map {
+            access_map.load(PC_BEGIN, rom.len());
+        }
+        if let MachineCalls::Dispatch(handler) = &mut self.machine_calls {
+            handler.load(MachineCallState {
+                memory: &mut self.memory,
+                registers: &mut self.registers,
+                idx: &mut self.idx,
+                pc: &mut self.pc,
+                screen: &mut self.screen,
+            });
+        }
+        Ok(rom.len())
+    }
+
+    /// Read a program file from disk and load into memory.
+    /// The lower-cased file stem is used as the game name.
+    pub fn load_rom_file<P: AsRef<Path>>(&mut self, path: P) -> Result<usize> {
+        let path = path.as_ref();
+        let rom = fs::read(path).with_context(|| format!("Could not read {}", path.display()))?;
+        let num_bytes = self.load_rom(&rom)?;
+        self.current_game = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_lowercase());
+        log::info!("Loaded {}: {} bytes", path.display(), num_bytes);
+        Ok(num_bytes)
+    }
+
+    /// Get the current quirks profile.
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Check if the program has exited with 00FD.  It won't run any further until a game is loaded.
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Switch quirks profile, takes effect from the next instruction.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Snapshot everything needed to resume from this exact point.
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            memory: self.memory.clone(),
+            registers: self.registers,
+            idx: self.idx,
+            pc: self.pc,
+            stack: self.stack,
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            screen: self.screen.clone(),
+            planes: self.planes,
+            keys: self.key.inner(),
+            rpl_flags: self.rpl_flags,
+            audio: self.audio,
+            exited: self.exited,
+            current_game: self.current_game.clone(),
+        }
+    }
+
+    /// Resume from a snapshot.  Leaves the machine untouched if the snapshot doesn't make sense.
+    pub fn load_state(&mut self, state: &SaveState) -> Result<()> {
+        if state.memory.len() != MEM_SIZE {
+            return Err(anyhow!(
+                "Save state has {} bytes of memory, expected {}",
+                state.memory.len(),
+                MEM_SIZE
+            ));
+        }
+        if state.sp > STACK_SIZE {
+            return Err(anyhow!(
+                "Save state stack pointer {} out of range",
+                state.sp
+            ));
+        }
+        let screen = &state.screen;
+        let resolution = (screen.width(), screen.height());
+        if screen.pixels().len() != (screen.width() * screen.height()) as usize
+            || ![
+                (PIXEL_COLS, PIXEL_ROWS),
+                (HIRES_PIXEL_COLS, HIRES_PIXEL_ROWS),
+                (PIXEL_COLS, VIP_HIRES_PIXEL_ROWS),
+            ]
+            .contains(&resolution)
+        {
+            return Err(anyhow!(
+                "Save state screen is {}x{}, which isn't a supported resolution",
+                screen.width(),
+                screen.height()
+            ));
+        }
+        if state.planes > ALL_PLANES {
+            return Err(anyhow!(
+                "Save state selects unknown planes {:#04b}",
+                state.planes
+            ));
+        }
+
+        self.memory.copy_from_slice(&state.memory);
+        self.registers = state.registers;
+        self.idx = state.idx;
+        self.pc = state.pc;
+        self.stack = state.stack;
+        self.sp = state.sp;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.screen = state.screen.clone();
+        self.planes = state.planes;
+        self.set_keys(state.keys);
+        self.rpl_flags = state.rpl_flags;
+        self.audio = state.audio;
+        self.exited = state.exited;
+        self.current_game = state.current_game.clone();
+        self.draw_flag = true;
+        Ok(())
+    }
+
+    /// Carry out every `Command` the context has pending.
+    pub fn poll_commands(&mut self) {
+        while let Some(command) = self.context.next_command() {
+            self.handle_command(command);
+        }
+    }
+
+    /// Carry out a single `Command`, reporting how it went on the console.
+    pub fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::SaveState(slot) => {
+                let name = self.state_name(slot);
+                let bytes = self.save_state().to_bytes();
+                match self.context.store_state(&name, &bytes) {
+                    Ok(()) => println!("Saved state {}", name),
+                    Err(e) => eprintln!("Could not save state {}: {}", name, e),
+                }
+            }
+            Command::LoadState(slot) => {
+                let name = self.state_name(slot);
+                let loaded = self
+                    .context
+                    .fetch_state(&name)
+                    .and_then(|bytes| match bytes {
+                        Some(bytes) => self
+                            .load_state(&SaveState::from_bytes(&bytes)?)
+                            .map(|_| true),
+                        None => Ok(false),
+                    });
+                match loaded {
+                    Ok(true) => println!("Loaded state {}", name),
+                    Ok(false) => println!("No saved state {}", name),
+                    Err(e) => eprintln!("Could not load state {}: {}", name, e),
+                }
+            }
+            Command::TogglePause => {
+                if self.paused {
+                    self.resume();
+                    println!("Resumed");
+                } else {
+                    self.pause();
+                    println!("Paused");
+                }
+            }
+            Command::FrameAdvance => self.advance_frame(),
+            Command::SetTurbo(turbo) => self.set_turbo(turbo),
+            Command::SpeedUp => {
+                let speed = self.speed();
+                self.set_speed((speed * 5 / 4).max(speed + 1));
+                println!("Speed: {} instructions per second", self.speed());
+            }
+            Command::SpeedDown => {
+                self.set_speed(self.speed() * 4 / 5);
+                println!("Speed: {} instructions per second", self.speed());
+            }
+            Command::SetSpeed(speed) => self.set_speed(speed),
+            Command::SetRewind(rewinding) => self.set_rewinding(rewinding),
+        }
+    }
+
     /// Run the machine for non-wasm target.
+    /// Returns when the user quits, or passes up the first error the ROM hits.
     #[cfg(not(feature = "wasm"))]
-    pub fn run(&mut self) {
-        let mut cycle_counter = 0;
+    pub fn run(&mut self) -> Result<(), Chip8Error> {
         loop {
-            cycle_counter += 1;
-            if cycle_counter >= CYCLES_PER_SLEEP {
-                cycle_counter = 0;
-                self.sleep(MILLIS_PER_SLEEP.floor() as u64);
-            }
-            match self.step() {
-                Ok(true) => break,
-                Ok(_) => continue,
-                Err(e) => {
-                    eprintln!("Error: {}", e);
-                    std::process::exit(1);
-                }
+            if self.handle_events() {
+                return Ok(());
             }
+            self.run_due()?;
+            self.sleep(MILLIS_PER_SLEEP);
         }
     }
 
-    /// Perform one step for the non-wasm target
+    /// Perform one step for the non-wasm target - a single instruction, whatever the clock says.
+    /// Returns true once the user quits or the program exits.
     #[cfg(not(feature = "wasm"))]
-    pub fn step(&mut self) -> Result<bool> {
-        // Handle any events, quit if signaled
-        if self.context.listen_for_input() {
-            println!("Quitting...");
+    pub fn step(&mut self) -> Result<bool, Chip8Error> {
+        if self.handle_events() {
             return Ok(true);
         }
-
+        self.update_keys();
         self.cycle()?;
-
-        // If the draw flag is set, update the screen
         if self.draw_flag {
             self.draw_graphics();
         }
-        // Store key press state
-        self.update_keys();
+        self.update_tone();
         Ok(false)
     }
 
+    /// Run every instruction that's come due on the context's clock since the last call, then draw if anything changed.
+    /// Both `run` and the WebAssembly animation frame loop drive the machine through this.
+    /// While paused, the clock still moves but nothing runs except a requested frame advance.
+    /// While rewinding, the same time goes to stepping back through recent frames instead.
+    pub fn run_due(&mut self) -> Result<(), Chip8Error> {
+        self.update_keys();
+        let due = self.scheduler.instructions_due(self.context.now());
+        let due = if self.turbo { due * TURBO_FACTOR } else { due };
+        let frame = self.frames;
+        if self.rewinding {
+            self.rewind_progress += due;
+            let frame_length = (self.speed() / TIMER_HZ).max(1);
+            while self.rewind_progress >= frame_length {
+                self.rewind_progress -= frame_length;
+                if !self.rewind_frame() {
+                    self.rewind_progress = 0;
+                    break;
+                }
+            }
+        } else if !self.paused {
+            for _ in 0..due {
+                if self.exited {
+                    break;
+                }
+                self.cycle()?;
+            }
+        } else if self.frame_advance {
+            self.frame_advance = false;
+            self.run_frame()?;
+        }
+        if self.frames != frame && !self.rewinding {
+            self.rewind.push(&self.save_state());
+        }
+        if self.draw_flag {
+            self.draw_graphics();
+        }
+        self.update_tone();
+        Ok(())
+    }
+
+    /// Run instructions up to the next 60Hz timer tick - one frame of emulated time.
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        let frame = self.frames;
+        while self.frames == frame && !self.exited {
+            self.cycle()?;
+        }
+        Ok(())
+    }
+
+    /// Number of 60Hz frames of emulated time so far.
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    /// Stop running instructions in `run` and `run_due`, until `resume`.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Carry on after `pause`.
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.frame_advance = false;
+    }
+
+    /// Check if emulation is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Run a single frame on the next `run_due` while paused.  Pauses first if not already.
+    pub fn advance_frame(&mut self) {
+        self.paused = true;
+        self.frame_advance = true;
+    }
+
+    /// Run `TURBO_FACTOR` times faster than the set speed, or go back to normal.
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+    }
+
+    /// Check if turbo mode is on.
+    pub fn is_turbo(&self) -> bool {
+        self.turbo
+    }
+
+    /// Step back through recent frames in `run_due` instead of running, or carry on from where it got to.
+    pub fn set_rewinding(&mut self, rewinding: bool) {
+        self.rewinding = rewinding;
+        self.rewind_progress = 0;
+    }
+
+    /// Check if the machine is rewinding.
+    pub fn is_rewinding(&self) -> bool {
+        self.rewinding
+    }
+
+    /// Go back to the most recently recorded frame, forgetting it.
+    /// Returns false if there's no history left.
+    pub fn rewind_frame(&mut self) -> bool {
+        match self.rewind.pop() {
+            Some(state) => self.load_state(&state).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Keep this many seconds of frames to rewind through.  Zero turns rewinding off.
+    pub fn set_rewind_seconds(&mut self, seconds: u64) {
+        self.rewind.set_capacity((seconds * TIMER_HZ) as usize);
+    }
+
+    /// Number of frames there are to rewind through.
+    pub fn rewind_len(&self) -> usize {
+        self.rewind.len()
+    }
+
+    /// Instructions executed per second of emulated time.
+    pub fn speed(&self) -> u64 {
+        self.scheduler.instructions_per_second()
+    }
+
+    /// Change how many instructions run per second.  The timers stay at 60Hz.
+    pub fn set_speed(&mut self, instructions_per_second: u64) {
+        self.scheduler
+            .set_instructions_per_second(instructions_per_second);
+    }
+
     // Pass through key_up and key_down
     pub fn key_down(&mut self, key: u8) {
         self.key.key_down(key);
     }
 
-    /// Emulate a single cycle of the Chip8 CPU.
-    pub fn cycle(&mut self) -> Result<()> {
+    /// Emulate a single cycle of the Chip8 CPU.  Does nothing once the program has exited.
+    /// Each cycle is one instruction's worth of emulated time, so the timers tick whenever 1/60th of a second has passed.
+    pub fn cycle(&mut self) -> Result<(), Chip8Error> {
+        if self.exited {
+            return Ok(());
+        }
         // Grab the current opcode and copy it into this stack frame
         self.update_opcode()?;
-        self.execute();
-        // Decrement timers if needed
-        self.update_timers();
+        let pc = self.pc;
+        let traced = match &self.tracer {
+            Some(tracer) if tracer.wants(pc, self.opcode) => Some(self.registers),
+            _ => None,
+        };
+        let accesses = match self.access_map {
+            Some(_) => self.pending_accesses()?,
+            None => Vec::new(),
+        };
+        self.execute()?;
+        if let Some(access_map) = &mut self.access_map {
+            access_map.record(pc, self.opcode, &accesses);
+        }
+        if let Some(registers) = traced {
+            self.trace(pc, registers);
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(pc, self.opcode, self.pc, &self.memory);
+        }
+        for _ in 0..self.scheduler.instruction_executed() {
+            self.tick_timers();
+        }
         Ok(())
     }
 
+    /// Write out every instruction run from now on, or stop with `take_tracer`.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Stop tracing, handing back the tracer.
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Count every instruction run from now on, or stop with `take_profiler`.
+    pub fn set_profiler(&mut self, profiler: Profiler) {
+        self.profiler = Some(profiler);
+    }
+
+    /// The profile so far, if profiling.
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Stop profiling, handing back the profile.
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    /// Keep track of what each byte of memory is used for from now on.
+    pub fn set_access_map(&mut self, access_map: AccessMap) {
+        self.access_map = Some(access_map);
+    }
+
+    /// What memory has been used for so far, if tracking it.
+    pub fn access_map(&self) -> Option<&AccessMap> {
+        self.access_map.as_ref()
+    }
+
+    /// Stop tracking memory use, handing back the map.
+    pub fn take_access_map(&mut self) -> Option<AccessMap> {
+        self.access_map.take()
+    }
+
+    /// Choose what 0NNN does from now on.  Set it before loading a ROM, so a handler can look it over.
+    pub fn set_machine_calls(&mut self, machine_calls: MachineCalls) {
+        self.machine_calls = machine_calls;
+    }
+
     /// Sleep the machine
     pub fn sleep(&mut self, millis: u64) {
         self.context.sleep(millis)
@@ -317,7 +754,7 @@ impl Machine {
 
     /// Draw the internal graphics out to a real screen
     pub fn draw_graphics(&mut self) {
-        self.context.draw_graphics(self.screen);
+        self.context.draw_graphics(&self.screen);
         self.draw_flag = false;
     }
 
@@ -326,11 +763,93 @@ impl Machine {
         self.set_keys(self.context.get_key_state());
     }
 
+    /// Keep the frontend alive while something other than `run` drives `cycle`, like the debugger.
+    /// Takes in input, redraws if needed and updates the buzzer.  Returns true once the user quits.
+    pub fn pump_events(&mut self) -> bool {
+        if self.context.listen_for_input() {
+            return true;
+        }
+        self.update_keys();
+        if self.draw_flag {
+            self.draw_graphics();
+        }
+        self.update_tone();
+        false
+    }
+
     // PRIVATE/INTERNAL INTERFACE
 
-    /// Emit a beep
-    fn beep(&self) {
-        self.context.beep();
+    /// Gather input and frontend commands.  Returns true if it's time to stop, because the user quit or the program exited.
+    #[cfg(not(feature = "wasm"))]
+    fn handle_events(&mut self) -> bool {
+        // Handle any events, quit if signaled
+        if self.context.listen_for_input() {
+            println!("Quitting...");
+            return true;
+        }
+
+        // Save or restore states before running anything
+        self.poll_commands();
+
+        // The program can also quit on its own
+        if self.exited {
+            println!("Program exited");
+            return true;
+        }
+        false
+    }
+
+    /// Start, stop or change the buzzer to match the sound timer.
+    /// It sounds the whole time the timer runs, except while paused or rewinding, or once the program exits.
+    fn update_tone(&mut self) {
+        let wanted = if self.sound_timer > 0 && !self.paused && !self.rewinding && !self.exited {
+            Some(self.audio)
+        } else {
+            None
+        };
+        if wanted != self.tone {
+            match &wanted {
+                Some(audio) => self.context.start_tone(audio),
+                None => self.context.stop_tone(),
+            }
+            self.tone = wanted;
+        }
+    }
+
+    /// Record the instruction that just ran from `pc`, given the registers from before it.
+    fn trace(&mut self, pc: u16, before: [u8; NUM_REGISTERS]) {
+        let changes = before
+            .iter()
+            .zip(self.registers.iter())
+            .enumerate()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(register, (&before, &after))| RegisterChange {
+                register: register as u8,
+                before,
+                after,
+            })
+            .collect();
+        let record = TraceRecord {
+            frame: self.frames,
+            pc,
+            raw: RawOpcode::from(self.opcode),
+            opcode: self.opcode,
+            changes,
+            idx: self.idx,
+            vf: self.registers[0xF],
+        };
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(&record);
+        }
+    }
+
+    /// Move idx past the registers FX55/FX65 just touched, if the quirks call for it.
+    fn advance_idx_after_load_store(&mut self, x: u8) {
+        self.idx = self.idx.wrapping_add(match self.quirks.load_store_idx {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::ByX => x as u16,
+            IndexIncrement::ByXPlusOne => x as u16 + 1,
+        });
     }
 
     /// Set the carry flag to off
@@ -349,42 +868,94 @@ impl Machine {
         self.register_get(0xF) == 1
     }
 
-    /// Clear screen
+    /// Clear the selected planes of the screen
     fn clear_screen(&mut self) {
-        self.screen = BLANK_SCREEN;
+        self.screen.clear_planes(self.planes);
     }
 
     /// Retrieve the current byte.
-    fn current_byte(&self) -> u8 {
+    #[cfg(test)]
+    fn current_byte(&self) -> Result<u8, Chip8Error> {
         self.memory_get(self.pc)
     }
 
     /// Execute the current opcode
-    fn execute(&mut self) {
+    fn execute(&mut self) -> Result<(), Chip8Error> {
         use Opcode::*;
         let code = self.opcode;
         match code {
-            MachineCall(_addr) => todo!(),
+            MachineCall(addr) => {
+                let handled = match &mut self.machine_calls {
+                    MachineCalls::Error => false,
+                    MachineCalls::Ignore => true,
+                    MachineCalls::Dispatch(handler) => handler.call(
+                        addr,
+                        MachineCallState {
+                            memory: &mut self.memory,
+                            registers: &mut self.registers,
+                            idx: &mut self.idx,
+                            pc: &mut self.pc,
+                            screen: &mut self.screen,
+                        },
+                    ),
+                };
+                if !handled {
+                    return Err(Chip8Error::UnsupportedMachineCall { addr });
+                }
+                self.draw_flag = true;
+                self.next_opcode();
+            }
             ClearScreen => {
                 self.clear_screen();
                 self.draw_flag = true;
                 self.next_opcode();
             }
             Return => {
-                self.pop_callsite();
+                self.pop_callsite()?;
+                self.next_opcode();
+            }
+            ScrollDown(n) => {
+                self.screen.scroll_down(n as u32, self.planes);
+                self.draw_flag = true;
+                self.next_opcode();
+            }
+            ScrollUp(n) => {
+                self.screen.scroll_up(n as u32, self.planes);
+                self.draw_flag = true;
+                self.next_opcode();
+            }
+            ScrollRight => {
+                self.screen.scroll_right(4, self.planes);
+                self.draw_flag = true;
+                self.next_opcode();
+            }
+            ScrollLeft => {
+                self.screen.scroll_left(4, self.planes);
+                self.draw_flag = true;
+                self.next_opcode();
+            }
+            Exit => self.exited = true,
+            LowRes => {
+                self.screen = Screen::default();
+                self.draw_flag = true;
+                self.next_opcode();
+            }
+            HighRes => {
+                self.screen = Screen::hires();
+                self.draw_flag = true;
                 self.next_opcode();
             }
             Jump(addr) => self.pc = addr,
             Call(addr) => {
                 // Store current location on the stack
-                self.push_callsite();
+                self.push_callsite()?;
                 // Jump to new location
                 self.pc = addr;
             }
             SkipIfEqVal(x, y) => {
                 if self.register_get(x) == y {
                     // Extra advance
-                    self.next_opcode();
+                    self.skip_next()?;
                 }
                 // Always advance at least once
                 self.next_opcode();
@@ -392,7 +963,7 @@ impl Machine {
             SkipIfNotEqVal(x, y) => {
                 if self.register_get(x) != y {
                     // Extra advance
-                    self.next_opcode();
+                    self.skip_next()?;
                 }
                 // Always advance at least once
                 self.next_opcode();
@@ -400,11 +971,25 @@ impl Machine {
             SkipIfMatchReg(x, y) => {
                 if self.register_get(x) == self.register_get(y) {
                     // Extra advance
-                    self.next_opcode();
+                    self.skip_next()?;
                 }
                 // Always advance at least once
                 self.next_opcode();
             }
+            SaveRange(x, y) => {
+                for (i, reg) in Self::register_range(x, y).into_iter().enumerate() {
+                    let addr = Self::memory_offset(self.idx, i as u16)?;
+                    self.memory_set(addr, self.register_get(reg))?;
+                }
+                self.next_opcode();
+            }
+            LoadRange(x, y) => {
+                for (i, reg) in Self::register_range(x, y).into_iter().enumerate() {
+                    let addr = Self::memory_offset(self.idx, i as u16)?;
+                    self.register_set(reg, self.memory_get(addr)?);
+                }
+                self.next_opcode();
+            }
             SetRegister(x, y) => {
                 self.register_set(x, y);
                 self.next_opcode();
@@ -460,8 +1045,12 @@ impl Machine {
                 self.register_set(x, reg_x.wrapping_sub(reg_y));
                 self.next_opcode();
             }
-            ShiftRight(x) => {
-                let reg = self.register_get(x);
+            ShiftRight(x, y) => {
+                let reg = if self.quirks.shift_uses_vy {
+                    self.register_get(y)
+                } else {
+                    self.register_get(x)
+                };
                 // Set the carry flag according to LSB
                 self.register_set(0xF, reg & 0x01);
                 self.register_set(x, reg >> 1);
@@ -480,8 +1069,12 @@ impl Machine {
                 self.register_set(x, reg_y.wrapping_sub(reg_x));
                 self.next_opcode();
             }
-            ShiftLeft(x) => {
-                let reg = self.register_get(x);
+            ShiftLeft(x, y) => {
+                let reg = if self.quirks.shift_uses_vy {
+                    self.register_get(y)
+                } else {
+                    self.register_get(x)
+                };
                 // Set the carry flag according to MSB
                 // Shift by seven (number of bits in the byte minus 1), then it's the LSB!
                 self.register_set(0xF, reg >> (8 - 1));
@@ -490,7 +1083,7 @@ impl Machine {
             }
             SkipIfMismatchReg(x, y) => {
                 if self.register_get(x) != self.register_get(y) {
-                    self.next_opcode();
+                    self.skip_next()?;
                 }
                 self.next_opcode();
             }
@@ -498,37 +1091,67 @@ impl Machine {
                 self.idx = addr;
                 self.next_opcode();
             }
-            JumpTo(addr) => self.pc = addr + self.register_get(0) as u16,
+            JumpTo(addr) => {
+                let x = if self.quirks.jump_uses_vx {
+                    (addr >> 8) as u8
+                } else {
+                    0
+                };
+                self.pc = addr + self.register_get(x) as u16
+            }
             Rand(x, mask) => {
                 let r = self.context.random_byte();
                 self.register_set(x, r & mask);
                 self.next_opcode();
             }
-            Draw(x, y, h) => {
-                let reg_x = self.register_get(x);
-                let reg_y = self.register_get(y);
+            Draw(x, y, n) => {
+                let reg_x = self.register_get(x) as u32;
+                let reg_y = self.register_get(y) as u32;
+                // DXY0 is a 16x16 sprite, two bytes per row
+                let (width, height) = if n == 0 { (16, 16) } else { (8, n as u32) };
+                let bytes_per_row = width / 8;
+                let (cols, rows) = (self.screen.width(), self.screen.height());
                 // reset collision detection register - uses carry flag
                 self.carry_off();
-                // Loop over each row
-                for yline in 0..h {
-                    let row = ((reg_y as u32 + yline as u32) % PIXEL_ROWS) as u8;
-                    // Fetch pixel value
-                    let pixel = self.memory_get(self.idx + (yline as u16));
-                    // Loop over each bit in the row
-                    for xline in 0..8 {
-                        let col = ((reg_x as u32 + xline as u32) % PIXEL_COLS) as u8;
-                        // Check if current pixel is set to one, i.e. we need to draw it.
-                        // 0x80 >> xline scans through to the current bit
-                        if pixel & (0x80 >> xline) != 0 {
-                            // Check if the current display pixel is also set to 1
-                            if self.screen_get(col, row) == 1 {
-                                // Collision detected!
-                                self.carry_on();
+                // Each selected plane gets its own sprite, stored one after another from idx
+                let planes = self.planes;
+                let mut sprite_addr = self.idx;
+                for plane in (0..NUM_PLANES).map(|i| 1 << i).filter(|p| planes & p != 0) {
+                    // The starting position always wraps, the rest of the sprite wraps or clips per the quirks
+                    // Loop over each row
+                    for yline in 0..height {
+                        let row = reg_y % rows + yline;
+                        if row >= rows && self.quirks.clip_sprites {
+                            break;
+                        }
+                        let row = row % rows;
+                        // Fetch pixel values, most significant bit is leftmost
+                        let mut pixels = 0u16;
+                        for byte in 0..bytes_per_row {
+                            let addr = Self::memory_offset(
+                                sprite_addr,
+                                (yline * bytes_per_row + byte) as u16,
+                            )?;
+                            pixels = pixels << 8 | self.memory_get(addr)? as u16;
+                        }
+                        // Loop over each bit in the row
+                        for xline in 0..width {
+                            let col = reg_x % cols + xline;
+                            if col >= cols && self.quirks.clip_sprites {
+                                break;
+                            }
+                            let col = col % cols;
+                            // Check if current pixel is set to one, i.e. we need to draw it.
+                            if pixels & (1 << (width - 1 - xline)) != 0 {
+                                // Set the pixel value with XOR, checking if the display pixel was also set
+                                if self.screen.toggle(col, row, plane) {
+                                    // Collision detected!
+                                    self.carry_on();
+                                }
                             }
-                            // Set the pixel value with XOR
-                            self.screen_set(col, row);
                         }
                     }
+                    sprite_addr = sprite_addr.wrapping_add((height * bytes_per_row) as u16);
                 }
                 // We updated the screen, trigger redraw
                 self.draw_flag = true;
@@ -537,14 +1160,31 @@ impl Machine {
             SkipIfPressed(key_reg) => {
                 let key = self.register_get(key_reg);
                 if self.key_pressed(key) {
-                    self.next_opcode();
+                    self.skip_next()?;
                 }
                 self.next_opcode();
             }
             SkipIfNotPressed(key_reg) => {
                 let key = self.register_get(key_reg);
                 if !self.key_pressed(key) {
-                    self.next_opcode();
+                    self.skip_next()?;
+                }
+                self.next_opcode();
+            }
+            LongSetIdx => {
+                self.idx = self.memory_get_word(Self::memory_offset(self.pc, 2)?)?;
+                // Step over the address as well
+                self.next_opcode();
+                self.next_opcode();
+            }
+            SelectPlanes(n) => {
+                self.planes = n & ALL_PLANES;
+                self.next_opcode();
+            }
+            LoadAudio => {
+                for i in 0..AUDIO_PATTERN_BYTES {
+                    let addr = Self::memory_offset(self.idx, i as u16)?;
+                    self.audio.buffer[i] = self.memory_get(addr)?;
                 }
                 self.next_opcode();
             }
@@ -581,53 +1221,78 @@ impl Machine {
             }
             IncrementIdx(x) => {
                 let curr = self.register_get(x) as u16;
-                if self.idx + curr > 0xFFF {
-                    self.carry_on();
-                } else {
-                    self.carry_off();
+                if self.quirks.idx_overflow_sets_vf {
+                    if self.idx as u32 + curr as u32 > 0xFFF {
+                        self.carry_on();
+                    } else {
+                        self.carry_off();
+                    }
                 }
-                self.idx += curr;
-                self.idx &= 0xFFF;
+                self.idx = self.idx.wrapping_add(curr);
                 self.next_opcode();
             }
             NewSprite(x) => {
-                self.idx = (self.register_get(x) * 0x5) as u16;
+                self.idx = (self.register_get(x) & 0xF) as u16 * 5;
+                self.next_opcode();
+            }
+            NewBigSprite(x) => {
+                self.idx = BIG_FONTSET_START + (self.register_get(x) & 0xF) as u16 * 10;
                 self.next_opcode();
             }
             BCD(x) => {
                 let reg_x = self.register_get(x);
-                self.memory_set(self.idx, reg_x / 100);
-                self.memory_set(self.idx + 1, (reg_x / 10) % 10);
-                self.memory_set(self.idx + 2, (reg_x % 100) % 10);
+                self.memory_set(self.idx, reg_x / 100)?;
+                self.memory_set(Self::memory_offset(self.idx, 1)?, (reg_x / 10) % 10)?;
+                self.memory_set(Self::memory_offset(self.idx, 2)?, (reg_x % 100) % 10)?;
                 self.next_opcode();
             }
             DumpRegisters(x) => {
                 let start_idx = self.idx;
                 for i in 0..=x {
-                    self.memory_set(start_idx + i as u16, self.register_get(i));
+                    self.memory_set(
+                        Self::memory_offset(start_idx, i as u16)?,
+                        self.register_get(i),
+                    )?;
                 }
-                // Superchip leaves this unmodifed
-                //self.idx += (x + 1) as u16;
+                self.advance_idx_after_load_store(x);
                 self.next_opcode();
             }
             FillRegisters(x) => {
                 let start_idx = self.idx;
                 for i in 0..=x {
-                    self.register_set(i, self.memory_get(start_idx + i as u16));
+                    self.register_set(
+                        i,
+                        self.memory_get(Self::memory_offset(start_idx, i as u16)?)?,
+                    );
+                }
+                self.advance_idx_after_load_store(x);
+                self.next_opcode();
+            }
+            SetPitch(x) => {
+                self.audio.pitch = self.register_get(x);
+                self.next_opcode();
+            }
+            SaveFlags(x) => {
+                for i in 0..=x {
+                    self.rpl_flags[i as usize] = self.register_get(i);
+                }
+                self.next_opcode();
+            }
+            LoadFlags(x) => {
+                for i in 0..=x {
+                    self.register_set(i, self.rpl_flags[i as usize]);
                 }
-                // Superchip leaves this unmodified
-                //self.idx += (x + 1) as u16;
                 self.next_opcode();
             }
         }
+        Ok(())
     }
 
     /// Fetch the opcode specified by the program counter.
-    fn fetch_opcode(&self) -> Result<Opcode> {
+    fn fetch_opcode(&self) -> Result<Opcode, Chip8Error> {
         // Consume two successive bytes, then combine for the opcode
-        let first_byte = self.current_byte();
-        let second_byte = self.memory_get(self.pc + 1);
-        Ok(Opcode::new(first_byte, second_byte)?)
+        let raw = RawOpcode::from(self.memory_get_word(self.pc)?);
+        Opcode::try_from(raw).map_err(|_| Chip8Error::InvalidOpcode { pc: self.pc, raw })
     }
 
     /// Check if given key is pressed
@@ -637,29 +1302,55 @@ impl Machine {
 
     /// Initialization step to load the font bytes into main memory.
     fn load_fontset(&mut self) {
-        for (idx, &byte) in FONTSET.iter().enumerate() {
-            // Fonts go right at the beginning
-            self.memory[idx] = byte;
-        }
+        // Fonts go right at the beginning
+        self.memory[..FONTSET.len()].copy_from_slice(&FONTSET);
+        let big_start = BIG_FONTSET_START as usize;
+        self.memory[big_start..big_start + BIG_FONTSET.len()].copy_from_slice(&BIG_FONTSET);
     }
 
     /// Get the byte at memory address x
-    fn memory_get(&self, addr: u16) -> u8 {
-        self.memory[addr as usize]
+    fn memory_get(&self, addr: u16) -> Result<u8, Chip8Error> {
+        self.memory
+            .get(addr as usize)
+            .copied()
+            .ok_or(Chip8Error::MemoryOutOfBounds { addr: addr as u32 })
     }
 
-    /// Set the value at register x
-    fn memory_set(&mut self, addr: u16, val: u8) {
-        self.memory[addr as usize] = val;
+    /// Get the big-endian word starting at memory address x
+    fn memory_get_word(&self, addr: u16) -> Result<u16, Chip8Error> {
+        let first_byte = self.memory_get(addr)?;
+        let second_byte = self.memory_get(Self::memory_offset(addr, 1)?)?;
+        Ok(RawOpcode::combine_bytes(first_byte, second_byte))
+    }
+
+    /// Offset a memory address, failing if that runs off the end of the address space
+    fn memory_offset(addr: u16, offset: u16) -> Result<u16, Chip8Error> {
+        addr.checked_add(offset)
+            .ok_or(Chip8Error::MemoryOutOfBounds {
+                addr: addr as u32 + offset as u32,
+            })
+    }
+
+    /// Set the byte at memory address x
+    fn memory_set(&mut self, addr: u16, val: u8) -> Result<(), Chip8Error> {
+        let cell = self
+            .memory
+            .get_mut(addr as usize)
+            .ok_or(Chip8Error::MemoryOutOfBounds { addr: addr as u32 })?;
+        *cell = val;
+        Ok(())
     }
 
     /// Advance a single opcode
     fn next_opcode(&mut self) {
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Pop the top value off the call stack
-    fn pop_callsite(&mut self) {
+    fn pop_callsite(&mut self) -> Result<(), Chip8Error> {
+        if self.sp == 0 {
+            return Err(Chip8Error::StackUnderflow);
+        }
         // Reduce the pointer
         self.sp -= 1;
         // Grab previous addressto return
@@ -667,12 +1358,17 @@ impl Machine {
         // Clear the stack slot
         self.stack[self.sp] = 0;
         self.pc = ret;
+        Ok(())
     }
 
     /// Push the current location onto the stack
-    fn push_callsite(&mut self) {
+    fn push_callsite(&mut self) -> Result<(), Chip8Error> {
+        if self.sp >= STACK_SIZE {
+            return Err(Chip8Error::StackOverflow);
+        }
         self.stack[self.sp] = self.pc;
         self.sp += 1;
+        Ok(())
     }
 
     /// Get the value at register x
@@ -680,6 +1376,15 @@ impl Machine {
         self.registers[x as usize]
     }
 
+    /// The registers VX to VY inclusive, in reverse order if X > Y
+    fn register_range(x: u8, y: u8) -> Vec<u8> {
+        if x <= y {
+            (x..=y).collect()
+        } else {
+            (y..=x).rev().collect()
+        }
+    }
+
     /// Set the value at register x
     fn register_set(&mut self, x: u8, val: u8) {
         self.registers[x as usize] = val;
@@ -689,23 +1394,33 @@ impl Machine {
     fn reset(&mut self) {
         self.pc = PC_BEGIN;
         self.registers = [0; NUM_REGISTERS];
-        self.memory = [0; MEM_SIZE];
+        self.memory.iter_mut().for_each(|b| *b = 0);
         self.stack = [0; STACK_SIZE];
+        self.sp = 0;
         self.delay_timer = 0;
         self.sound_timer = 0;
         self.draw_flag = true;
-        self.screen = BLANK_SCREEN;
+        self.screen = Screen::default();
+        self.planes = 1;
+        self.audio = AudioPattern::default();
+        self.exited = false;
+        self.rewind.clear();
         self.load_fontset();
     }
 
-    /// Get the value at screen position (x, y)
-    fn screen_get(&self, x: u8, y: u8) -> u8 {
-        self.screen[x as usize + (y as u32 * PIXEL_COLS) as usize]
+    /// Step over the next instruction, including the address if it's F000 NNNN
+    fn skip_next(&mut self) -> Result<(), Chip8Error> {
+        let next = Self::memory_offset(self.pc, 2)?;
+        if self.memory_get_word(next)? == 0xF000 {
+            self.next_opcode();
+        }
+        self.next_opcode();
+        Ok(())
     }
 
-    /// Draw the pixel at screen position (x, y)
-    fn screen_set(&mut self, x: u8, y: u8) {
-        self.screen[x as usize + ((y as u32 * PIXEL_COLS) as usize)] ^= 1;
+    /// Name to store the state in `slot` under, kept separate per game
+    fn state_name(&self, slot: u8) -> String {
+        format!("{}-{}", self.current_game.as_deref().unwrap_or("rom"), slot)
     }
 
     /// Store a newly read key state
@@ -714,20 +1429,20 @@ impl Machine {
     }
 
     /// Update the opcode either with the passed value (for testing) or the current byte if None.
-    fn update_opcode(&mut self) -> Result<()> {
+    fn update_opcode(&mut self) -> Result<(), Chip8Error> {
         self.opcode = self.fetch_opcode()?;
         Ok(())
     }
 
-    /// Update the timer values
-    fn update_timers(&mut self) {
+    /// Count both timers down once - called at 60Hz
+    fn tick_timers(&mut self) {
+        self.frames += 1;
+        self.context.start_frame();
+        self.update_keys();
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
         if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                self.beep();
-            }
             self.sound_timer -= 1;
         }
     }
@@ -736,6 +1451,6 @@ impl Machine {
     #[cfg(test)]
     pub fn test_opcode(&mut self, opcode: u16) {
         self.opcode = Opcode::try_from(opcode).unwrap();
-        self.execute();
+        self.execute().unwrap();
     }
 }
@@ -0,0 +1,162 @@
+//! A ring buffer of recent machine states, for stepping backwards in time.
+
+use super::*;
+use std::collections::VecDeque;
+
+/// Rewind history kept by default, in seconds.
+pub const DEFAULT_REWIND_SECONDS: u64 = 10;
+
+/// The way back from one encoded state to the one before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Delta {
+    /// The older bytes at each changed offset.  Used when both states encode to the same length,
+    /// which is almost always - most of memory doesn't change from one frame to the next.
+    Runs(Vec<(usize, Vec<u8>)>),
+    /// The whole older encoding, for when the layout moved, e.g. a resolution switch.
+    Full(Vec<u8>),
+}
+
+impl Delta {
+    /// Record how to get from `newer` back to `older`.
+    fn between(older: &[u8], newer: &[u8]) -> Self {
+        if older.len() != newer.len() {
+            return Delta::Full(older.to_vec());
+        }
+        let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+        for (i, (&old, &new)) in older.iter().zip(newer).enumerate() {
+            if old == new {
+                continue;
+            }
+            match runs.last_mut() {
+                Some((start, bytes)) if *start + bytes.len() == i => bytes.push(old),
+                _ => runs.push((i, vec![old])),
+            }
+        }
+        Delta::Runs(runs)
+    }
+
+    /// Turn the newer encoding back into the older one.
+    fn apply(self, newer: &mut Vec<u8>) {
+        match self {
+            Delta::Runs(runs) => {
+                for (start, bytes) in runs {
+                    newer[start..start + bytes.len()].copy_from_slice(&bytes);
+                }
+            }
+            Delta::Full(older) => *newer = older,
+        }
+    }
+
+    /// Bytes of history this takes up, roughly.
+    fn size(&self) -> usize {
+        match self {
+            Delta::Runs(runs) => runs
+                .iter()
+                .map(|(_, bytes)| bytes.len() + std::mem::size_of::<usize>())
+                .sum(),
+            Delta::Full(bytes) => bytes.len(),
+        }
+    }
+}
+
+/// Holds the last `capacity` states pushed, newest in full and the rest as deltas back from it.
+/// ```
+/// # use chip8::{Machine, HeadlessContext, Rewind};
+/// # use pretty_assertions::assert_eq;
+/// let mut machine = Machine::new(Box::new(HeadlessContext::new(1)));
+/// let mut rewind = Rewind::new(2);
+/// rewind.push(&machine.save_state());
+/// machine.idx = 0x300;
+/// rewind.push(&machine.save_state());
+/// machine.idx = 0x400;
+/// rewind.push(&machine.save_state());
+///
+/// assert_eq!(rewind.pop().unwrap().idx, 0x400);
+/// assert_eq!(rewind.pop().unwrap().idx, 0x300);
+/// // Only room for two
+/// assert!(rewind.pop().is_none());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Rewind {
+    capacity: usize,
+    /// Encoding of the most recent state
+    newest: Option<Vec<u8>>,
+    /// One delta for each older state, oldest first
+    history: VecDeque<Delta>,
+}
+
+impl Rewind {
+    /// Keep up to `capacity` states.  Zero keeps nothing.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            newest: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Remember a state, forgetting the oldest if full.
+    pub fn push(&mut self, state: &SaveState) {
+        if self.capacity == 0 {
+            return;
+        }
+        let bytes = state.to_bytes();
+        if let Some(previous) = self.newest.replace(bytes) {
+            let delta = Delta::between(&previous, self.newest.as_ref().unwrap());
+            self.history.push_back(delta);
+        }
+        while self.len() > self.capacity {
+            self.history.pop_front();
+        }
+    }
+
+    /// Take back the most recent state.
+    pub fn pop(&mut self) -> Option<SaveState> {
+        let newest = self.newest.take()?;
+        let state = SaveState::from_bytes(&newest).ok();
+        if let Some(delta) = self.history.pop_back() {
+            let mut older = newest;
+            delta.apply(&mut older);
+            self.newest = Some(older);
+        }
+        state
+    }
+
+    /// Number of states held.
+    pub fn len(&self) -> usize {
+        self.history.len() + self.newest.is_some() as usize
+    }
+
+    /// Check if there's nothing to rewind to.
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// Forget everything.
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.history.clear();
+    }
+
+    /// How many states it holds at most.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Change how many states it holds, dropping the oldest if there are too many.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        if capacity == 0 {
+            self.clear();
+        }
+        while self.len() > capacity && !self.history.is_empty() {
+            self.history.pop_front();
+        }
+    }
+
+    /// Roughly how many bytes the history takes up.
+    pub fn memory_usage(&self) -> usize {
+        self.newest.as_ref().map_or(0, Vec::len)
+            + self.history.iter().map(Delta::size).sum::<usize>()
+    }
+}
@@ -0,0 +1,80 @@
+//! Paces the CPU against a clock, and the timers against the CPU.
+
+/// How often the delay and sound timers count down.
+pub const TIMER_HZ: u64 = 60;
+/// Longest gap the scheduler will make up for in one go.
+/// Anything longer - a stall, a debugger pause, a hidden browser tab - is dropped rather than fast-forwarded.
+const MAX_CATCH_UP_MILLIS: u64 = 100;
+
+/// Works out how many instructions are due from a millisecond clock, and when the 60Hz timers tick.
+/// Timers are driven by emulated time - every `instructions_per_second` instructions is exactly one second,
+/// with exactly 60 timer ticks spread evenly through it.  That keeps them right whether the clock is
+/// wall time, virtual time, or a debugger stepping one instruction at a time.
+/// ```
+/// # use chip8::Scheduler;
+/// # use pretty_assertions::assert_eq;
+/// let mut scheduler = Scheduler::new(600);
+/// scheduler.instructions_due(1000);
+/// assert_eq!(scheduler.instructions_due(1050), 30);
+/// let ticks: u32 = (0..600).map(|_| scheduler.instruction_executed()).sum();
+/// assert_eq!(ticks, 60);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scheduler {
+    instructions_per_second: u64,
+    /// Clock reading at the last call to `instructions_due`
+    last_millis: Option<u64>,
+    /// Fraction of an instruction owed, in thousandths
+    instruction_remainder: u64,
+    /// Progress towards the next timer tick, which happens at `instructions_per_second`
+    timer_progress: u64,
+}
+
+impl Scheduler {
+    /// Run `instructions_per_second` instructions per second, at least one.
+    pub fn new(instructions_per_second: u64) -> Self {
+        Self {
+            instructions_per_second: instructions_per_second.max(1),
+            last_millis: None,
+            instruction_remainder: 0,
+            timer_progress: 0,
+        }
+    }
+
+    /// The CPU speed.
+    pub fn instructions_per_second(&self) -> u64 {
+        self.instructions_per_second
+    }
+
+    /// Change the CPU speed, keeping the timers' place.
+    pub fn set_instructions_per_second(&mut self, instructions_per_second: u64) {
+        let instructions_per_second = instructions_per_second.max(1);
+        self.timer_progress =
+            self.timer_progress * instructions_per_second / self.instructions_per_second;
+        self.instructions_per_second = instructions_per_second;
+    }
+
+    /// How many instructions to run to catch up with a clock reading of `now_millis`.
+    /// The first call only starts the clock.
+    pub fn instructions_due(&mut self, now_millis: u64) -> u64 {
+        let elapsed = match self.last_millis {
+            Some(last) => now_millis.saturating_sub(last).min(MAX_CATCH_UP_MILLIS),
+            None => 0,
+        };
+        self.last_millis = Some(now_millis);
+        let owed = self.instruction_remainder + elapsed * self.instructions_per_second;
+        self.instruction_remainder = owed % 1000;
+        owed / 1000
+    }
+
+    /// Move emulated time along by one instruction, returning how many timer ticks happened during it.
+    pub fn instruction_executed(&mut self) -> u32 {
+        self.timer_progress += TIMER_HZ;
+        let mut ticks = 0;
+        while self.timer_progress >= self.instructions_per_second {
+            self.timer_progress -= self.instructions_per_second;
+            ticks += 1;
+        }
+        ticks
+    }
+}
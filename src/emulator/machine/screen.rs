@@ -0,0 +1,189 @@
+//! The display buffer, which can switch between low and high resolution.
+//! XO-CHIP splits it into two bitplanes, giving four colours.
+
+use anyhow::{anyhow, Result};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Low resolution screen height - the original CHIP-8 display.
+pub const PIXEL_ROWS: u32 = 32;
+/// Low resolution screen width - the original CHIP-8 display.
+pub const PIXEL_COLS: u32 = 64;
+/// High resolution screen height, added by SUPER-CHIP.
+pub const HIRES_PIXEL_ROWS: u32 = 64;
+/// High resolution screen width, added by SUPER-CHIP.
+pub const HIRES_PIXEL_COLS: u32 = 128;
+/// Number of XO-CHIP bitplanes.
+pub const NUM_PLANES: u8 = 2;
+/// Bitmask selecting every plane at once.
+pub const ALL_PLANES: u8 = (1 << NUM_PLANES) - 1;
+
+/// Write RGBA bytes for a `width` by `height` grid of pixels into `buffer`, each `size` image pixels square,
+/// row by row - the layout textures and `ImageData` take.  `colour` gives each pixel's colour.  Only the top
+/// left `lit` width and height of a pixel is filled with it, the rest is `gap`, for scanlines and grids.
+/// Each colour is worked out once and copied along, so even high resolution stays cheap.
+/// ```
+/// # use chip8::write_rgba;
+/// # use pretty_assertions::assert_eq;
+/// let mut buffer = Vec::new();
+/// write_rgba(&mut buffer, (1, 1), 2, (2, 1), [0; 3], |_, _| [9, 9, 9]);
+/// assert_eq!(buffer, [9, 9, 9, 255, 9, 9, 9, 255, 0, 0, 0, 255, 0, 0, 0, 255]);
+/// ```
+pub fn write_rgba<F: Fn(u32, u32) -> [u8; 3]>(
+    buffer: &mut Vec<u8>,
+    (width, height): (u32, u32),
+    size: u32,
+    (lit_width, lit_height): (u32, u32),
+    gap: [u8; 3],
+    colour: F,
+) {
+    let gap = [gap[0], gap[1], gap[2], 255];
+    let row_pixels = (width * size) as usize;
+    let gap_row = gap.repeat(row_pixels);
+    let mut row = Vec::with_capacity(row_pixels * 4);
+    buffer.clear();
+    buffer.reserve(row_pixels * 4 * (height * size) as usize);
+    for y in 0..height {
+        row.clear();
+        for x in 0..width {
+            let [r, g, b] = colour(x, y);
+            let lit = [r, g, b, 255];
+            for dx in 0..size {
+                row.extend_from_slice(if dx < lit_width { &lit } else { &gap });
+            }
+        }
+        for dy in 0..size {
+            buffer.extend_from_slice(if dy < lit_height { &row } else { &gap_row });
+        }
+    }
+}
+
+/// The pixel array, stored row by row.
+/// Each pixel is a bitmask of the planes it is set in - 0 is unset, 1 is the only value plain CHIP-8 programs produce.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Screen {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Self::new(PIXEL_COLS, PIXEL_ROWS)
+    }
+}
+
+impl Screen {
+    /// A blank screen of any size.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; (width * height) as usize],
+        }
+    }
+
+    /// Rebuild a screen from its pixels, row by row.
+    pub fn from_pixels(width: u32, height: u32, pixels: Vec<u8>) -> Result<Self> {
+        if pixels.len() != width as usize * height as usize {
+            return Err(anyhow!(
+                "{} pixels don't make a {}x{} screen",
+                pixels.len(),
+                width,
+                height
+            ));
+        }
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// A blank 128x64 SUPER-CHIP screen.
+    pub fn hires() -> Self {
+        Self::new(HIRES_PIXEL_COLS, HIRES_PIXEL_ROWS)
+    }
+
+    /// Width in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Height in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Check if this is the 128x64 SUPER-CHIP resolution.
+    pub fn is_hires(&self) -> bool {
+        self.width == HIRES_PIXEL_COLS && self.height == HIRES_PIXEL_ROWS
+    }
+
+    /// All pixels, row by row.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Get the value at screen position (x, y).
+    pub fn get(&self, x: u32, y: u32) -> u8 {
+        self.pixels[(x + y * self.width) as usize]
+    }
+
+    /// XOR the pixel at screen position (x, y) in a single `plane`.
+    /// Returns true if it was set in that plane beforehand, i.e. a collision.
+    pub fn toggle(&mut self, x: u32, y: u32, plane: u8) -> bool {
+        let pixel = &mut self.pixels[(x + y * self.width) as usize];
+        let collision = *pixel & plane != 0;
+        *pixel ^= plane;
+        collision
+    }
+
+    /// Unset every pixel in every plane, keeping the resolution.
+    pub fn clear(&mut self) {
+        self.clear_planes(ALL_PLANES);
+    }
+
+    /// Unset every pixel in the selected `planes`, leaving the others alone.
+    pub fn clear_planes(&mut self, planes: u8) {
+        self.pixels.iter_mut().for_each(|p| *p &= !planes);
+    }
+
+    /// Move the selected `planes` up `n` rows, blanking the bottom.
+    pub fn scroll_up(&mut self, n: u32, planes: u8) {
+        self.scroll(0, -(n as i32), planes);
+    }
+
+    /// Move the selected `planes` down `n` rows, blanking the top.
+    pub fn scroll_down(&mut self, n: u32, planes: u8) {
+        self.scroll(0, n as i32, planes);
+    }
+
+    /// Move the selected `planes` left `n` columns, blanking the right edge.
+    pub fn scroll_left(&mut self, n: u32, planes: u8) {
+        self.scroll(-(n as i32), 0, planes);
+    }
+
+    /// Move the selected `planes` right `n` columns, blanking the left edge.
+    pub fn scroll_right(&mut self, n: u32, planes: u8) {
+        self.scroll(n as i32, 0, planes);
+    }
+
+    /// Shift the selected `planes` by (dx, dy).  Pixels pushed off the edge are lost.
+    fn scroll(&mut self, dx: i32, dy: i32, planes: u8) {
+        let (width, height) = (self.width as i32, self.height as i32);
+        // Start from just the planes that stay put
+        let mut scrolled: Vec<u8> = self.pixels.iter().map(|p| p & !planes).collect();
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x - dx, y - dy);
+                if src_x >= 0 && src_x < width && src_y >= 0 && src_y < height {
+                    scrolled[(x + y * width) as usize] |=
+                        self.pixels[(src_x + src_y * width) as usize] & planes;
+                }
+            }
+        }
+        self.pixels = scrolled;
+    }
+}
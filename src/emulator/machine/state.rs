@@ -0,0 +1,205 @@
+//! Snapshots of the whole machine, and their binary encoding.
+
+use super::*;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Identifies an encoded save state.
+const STATE_MAGIC: &[u8; 4] = b"C8ST";
+/// Bump whenever the encoding changes.  Older versions must keep decoding.
+pub const STATE_VERSION: u16 = 1;
+
+/// Everything needed to resume a machine exactly where it left off.
+/// The quirks profile isn't included, it belongs to whoever runs the machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SaveState {
+    pub memory: Vec<u8>,
+    pub registers: [u8; NUM_REGISTERS],
+    pub idx: u16,
+    pub pc: u16,
+    pub stack: [u16; STACK_SIZE],
+    pub sp: usize,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub screen: Screen,
+    pub planes: u8,
+    pub keys: [bool; NUM_KEYS],
+    pub rpl_flags: [u8; NUM_RPL_FLAGS],
+    pub audio: AudioPattern,
+    pub exited: bool,
+    pub current_game: Option<String>,
+}
+
+impl SaveState {
+    /// Encode in the stable binary format.  All numbers are little-endian.
+    ///
+    /// | Field          | Encoding                                          |
+    /// |----------------|---------------------------------------------------|
+    /// | magic          | `C8ST`                                            |
+    /// | version        | u16                                               |
+    /// | memory         | u32 length, then bytes                            |
+    /// | registers      | 16 bytes                                          |
+    /// | idx, pc        | u16 each                                          |
+    /// | stack, sp      | 16 u16s, then u8                                  |
+    /// | timers         | delay u8, sound u8                                |
+    /// | screen         | u16 width, u16 height, then one byte per pixel    |
+    /// | planes         | u8                                                |
+    /// | keys           | 16 bytes, 0 or 1                                  |
+    /// | rpl_flags      | 16 bytes                                          |
+    /// | audio          | 16 pattern bytes, then u8 pitch                   |
+    /// | exited         | u8, 0 or 1                                        |
+    /// | current_game   | u8 0 for none, or 1 then u16 length and UTF-8     |
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(self.memory.len() + self.screen.pixels().len() + 128);
+        ret.extend_from_slice(STATE_MAGIC);
+        ret.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        ret.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        ret.extend_from_slice(&self.memory);
+        ret.extend_from_slice(&self.registers);
+        ret.extend_from_slice(&self.idx.to_le_bytes());
+        ret.extend_from_slice(&self.pc.to_le_bytes());
+        for addr in self.stack.iter() {
+            ret.extend_from_slice(&addr.to_le_bytes());
+        }
+        ret.push(self.sp as u8);
+        ret.push(self.delay_timer);
+        ret.push(self.sound_timer);
+        ret.extend_from_slice(&(self.screen.width() as u16).to_le_bytes());
+        ret.extend_from_slice(&(self.screen.height() as u16).to_le_bytes());
+        ret.extend_from_slice(self.screen.pixels());
+        ret.push(self.planes);
+        ret.extend(self.keys.iter().map(|&k| k as u8));
+        ret.extend_from_slice(&self.rpl_flags);
+        ret.extend_from_slice(&self.audio.buffer);
+        ret.push(self.audio.pitch);
+        ret.push(self.exited as u8);
+        match &self.current_game {
+            Some(name) => {
+                ret.push(1);
+                ret.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                ret.extend_from_slice(name.as_bytes());
+            }
+            None => ret.push(0),
+        }
+        ret
+    }
+
+    /// Decode the binary format written by `to_bytes`.
+    /// This only checks the encoding - `Machine::load_state` checks the contents make sense.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes };
+        if reader.take(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err(anyhow!("Not a save state"));
+        }
+        let version = reader.u16()?;
+        if version != STATE_VERSION {
+            return Err(anyhow!("Unsupported save state version {}", version));
+        }
+
+        let memory_len = reader.u32()? as usize;
+        let memory = reader.take(memory_len)?.to_vec();
+        let mut registers = [0; NUM_REGISTERS];
+        registers.copy_from_slice(reader.take(NUM_REGISTERS)?);
+        let idx = reader.u16()?;
+        let pc = reader.u16()?;
+        let mut stack = [0; STACK_SIZE];
+        for addr in stack.iter_mut() {
+            *addr = reader.u16()?;
+        }
+        let sp = reader.u8()? as usize;
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let width = reader.u16()? as u32;
+        let height = reader.u16()? as u32;
+        let pixels = reader.take(width as usize * height as usize)?.to_vec();
+        let screen = Screen::from_pixels(width, height, pixels)?;
+        let planes = reader.u8()?;
+        let mut keys = [false; NUM_KEYS];
+        for key in keys.iter_mut() {
+            *key = reader.u8()? != 0;
+        }
+        let mut rpl_flags = [0; NUM_RPL_FLAGS];
+        rpl_flags.copy_from_slice(reader.take(NUM_RPL_FLAGS)?);
+        let mut audio = AudioPattern::default();
+        audio
+            .buffer
+            .copy_from_slice(reader.take(AUDIO_PATTERN_BYTES)?);
+        audio.pitch = reader.u8()?;
+        let exited = reader.u8()? != 0;
+        let current_game = match reader.u8()? {
+            0 => None,
+            _ => {
+                let len = reader.u16()? as usize;
+                Some(String::from_utf8(reader.take(len)?.to_vec())?)
+            }
+        };
+        if !reader.bytes.is_empty() {
+            return Err(anyhow!(
+                "Save state has {} unexpected trailing bytes",
+                reader.bytes.len()
+            ));
+        }
+
+        Ok(Self {
+            memory,
+            registers,
+            idx,
+            pc,
+            stack,
+            sp,
+            delay_timer,
+            sound_timer,
+            screen,
+            planes,
+            keys,
+            rpl_flags,
+            audio,
+            exited,
+            current_game,
+        })
+    }
+
+    /// Encode as JSON.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// Decode from JSON written by `to_json`.
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+/// Consumes a byte slice from the front.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < n {
+            return Err(anyhow!("Save state is truncated"));
+        }
+        let (ret, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(ret)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
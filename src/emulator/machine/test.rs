@@ -1,14 +1,19 @@
-use super::super::context::TestContext;
+use super::super::context::{
+    Command, HeadlessContext, KeyEvent, Movie, RecordingContext, ReplayContext, TestContext,
+};
+use super::super::debugger::*;
 use super::*;
 use pretty_assertions::assert_eq;
 
 #[test]
 fn test_load_fonts() {
     let machine = Machine::new(TestContext::new());
-    // The constructor should properly load the full fontset and nothing else.
-    assert_eq!(machine.memory_get(0), 0xF0);
-    assert_eq!(machine.memory_get(79), 0x80);
-    assert_eq!(machine.memory_get(80), 0);
+    // The constructor should properly load both full fontsets and nothing else.
+    assert_eq!(machine.memory_get(0).unwrap(), 0xF0);
+    assert_eq!(machine.memory_get(79).unwrap(), 0x80);
+    assert_eq!(machine.memory_get(80).unwrap(), 0xFF);
+    assert_eq!(machine.memory_get(239).unwrap(), 0xC0);
+    assert_eq!(machine.memory_get(240).unwrap(), 0);
 }
 
 #[test]
@@ -16,7 +21,7 @@ fn test_load_game() {
     let mut machine = Machine::new(TestContext::new());
     let bytes = machine.load_game("pong").unwrap();
     assert_eq!(bytes, 246);
-    assert_eq!(machine.current_byte(), 0x6A)
+    assert_eq!(machine.current_byte().unwrap(), 0x6A)
 }
 
 #[test]
@@ -26,7 +31,7 @@ fn test_load_second_game() {
     let _: usize = machine.load_game("pong").unwrap();
     let bytes = machine.load_game("tank").unwrap();
     assert_eq!(bytes, 560);
-    assert_eq!(machine.current_byte(), 0x12)
+    assert_eq!(machine.current_byte().unwrap(), 0x12)
 }
 
 #[test]
@@ -38,6 +43,69 @@ fn test_game_not_found() {
     );
 }
 
+#[test]
+fn test_load_rom() {
+    let mut machine = Machine::new(TestContext::new());
+    let _: usize = machine.load_game("pong").unwrap();
+    let bytes = machine.load_rom(&[0x12, 0x34, 0x56]).unwrap();
+    assert_eq!(bytes, 3);
+    assert_eq!(machine.current_byte().unwrap(), 0x12);
+    assert_eq!(machine.memory_get(PC_BEGIN + 2).unwrap(), 0x56);
+    // Should clear out the previous game
+    assert_eq!(machine.memory_get(PC_BEGIN + 3).unwrap(), 0);
+    assert_eq!(machine.current_game, None);
+}
+
+#[test]
+fn test_load_rom_max_size() {
+    let mut machine = Machine::new(TestContext::new());
+    // Should fill memory right up to 0xFFFF
+    let bytes = machine.load_rom(&[0xAB; MAX_ROM_SIZE]).unwrap();
+    assert_eq!(bytes, MAX_ROM_SIZE);
+    assert_eq!(machine.memory_get((MEM_SIZE - 1) as u16).unwrap(), 0xAB);
+}
+
+#[test]
+fn test_load_rom_too_large() {
+    let mut machine = Machine::new(TestContext::new());
+    let _: usize = machine.load_game("pong").unwrap();
+    assert_eq!(
+        machine
+            .load_rom(&[0; MAX_ROM_SIZE + 1])
+            .err()
+            .unwrap()
+            .to_string(),
+        "ROM is 65025 bytes, but only 65024 bytes fit in memory".to_string()
+    );
+    // Should leave the current game in place
+    assert_eq!(machine.current_game, Some("pong".to_string()));
+    assert_eq!(machine.current_byte().unwrap(), 0x6A);
+}
+
+#[test]
+fn test_load_rom_file() {
+    let path = std::env::temp_dir().join("CHIP8_LOAD_ROM_FILE.ch8");
+    std::fs::write(&path, [0x00, 0xE0, 0x12, 0x00]).unwrap();
+    let mut machine = Machine::new(TestContext::new());
+    let bytes = machine.load_rom_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(bytes, 4);
+    assert_eq!(machine.current_byte().unwrap(), 0x00);
+    assert_eq!(machine.memory_get(PC_BEGIN + 2).unwrap(), 0x12);
+    assert_eq!(
+        machine.current_game,
+        Some("chip8_load_rom_file".to_string())
+    );
+}
+
+#[test]
+fn test_load_rom_file_not_found() {
+    let mut machine = Machine::new(TestContext::new());
+    assert!(machine
+        .load_rom_file("definitely/not/a/real/rom.ch8")
+        .is_err());
+}
+
 #[test]
 fn test_1nnn_jump() {
     let mut machine = Machine::new(TestContext::new());
@@ -343,8 +411,8 @@ fn test_8xye_shift_left() {
     machine.register_set(0xC, 0xD);
     machine.test_opcode(0x8BCE);
 
-    // Should store most significant bit of VX as the borrow flag
-    assert_eq!(machine.register_get(0xF), (0xA >> (8 - 1) & 1));
+    // Should store most significant bit of VX as the borrow flag - 0xA has no high bit
+    assert_eq!(machine.register_get(0xF), 0);
     // Should shift VX left 1
     assert_eq!(machine.register_get(0xB), (0xA << 1));
     // Should not affect VY
@@ -438,9 +506,9 @@ fn test_fx33_bcd() {
     machine.idx = 0xAB;
     machine.test_opcode(0xFB33);
     // Should store the BCD of V[X] to the right memory locations
-    assert_eq!(machine.memory_get(0xAB), 1);
-    assert_eq!(machine.memory_get(0xAB + 1), 9);
-    assert_eq!(machine.memory_get(0xAB + 2), 5);
+    assert_eq!(machine.memory_get(0xAB).unwrap(), 1);
+    assert_eq!(machine.memory_get(0xAB + 1).unwrap(), 9);
+    assert_eq!(machine.memory_get(0xAB + 2).unwrap(), 5);
     // Should increment program counter by two
     assert_eq!(machine.pc, PC_BEGIN + 2);
 }
@@ -457,12 +525,12 @@ fn test_fx55_dump_registers() {
     machine.test_opcode(0xF355);
     // Should store V0 thorugh VX inclusive to memory starting at the index pointer
     let i = machine.idx;
-    assert_eq!(machine.memory_get(i), 0xC);
-    assert_eq!(machine.memory_get(i + 1), 4);
-    assert_eq!(machine.memory_get(i + 2), 123);
-    assert_eq!(machine.memory_get(i + 3), 98);
+    assert_eq!(machine.memory_get(i).unwrap(), 0xC);
+    assert_eq!(machine.memory_get(i + 1).unwrap(), 4);
+    assert_eq!(machine.memory_get(i + 2).unwrap(), 123);
+    assert_eq!(machine.memory_get(i + 3).unwrap(), 98);
     // Should only affect that memory, no further
-    assert_eq!(machine.memory_get(i + 4), 0);
+    assert_eq!(machine.memory_get(i + 4).unwrap(), 0);
     // Should not modify the index pointer
     assert_eq!(machine.idx, 0xBCD);
     // Should not modify the registers
@@ -479,20 +547,20 @@ fn test_fx55_dump_registers() {
 fn test_fx65_fill_registers() {
     let mut machine = Machine::new(TestContext::new());
     machine.idx = 0xBCD;
-    machine.memory_set(0xBCD, 0xC);
-    machine.memory_set(0xBCD + 1, 4);
-    machine.memory_set(0xBCD + 2, 123);
-    machine.memory_set(0xBCD + 3, 98);
-    machine.memory_set(0xBCD + 4, 12);
+    machine.memory_set(0xBCD, 0xC).unwrap();
+    machine.memory_set(0xBCD + 1, 4).unwrap();
+    machine.memory_set(0xBCD + 2, 123).unwrap();
+    machine.memory_set(0xBCD + 3, 98).unwrap();
+    machine.memory_set(0xBCD + 4, 12).unwrap();
     machine.test_opcode(0xF365);
     // Should not touch the memory
     let i = machine.idx;
-    assert_eq!(machine.memory_get(i), 0xC);
-    assert_eq!(machine.memory_get(i + 1), 4);
-    assert_eq!(machine.memory_get(i + 2), 123);
-    assert_eq!(machine.memory_get(i + 3), 98);
-    assert_eq!(machine.memory_get(i + 4), 12);
-    assert_eq!(machine.memory_get(i + 5), 0);
+    assert_eq!(machine.memory_get(i).unwrap(), 0xC);
+    assert_eq!(machine.memory_get(i + 1).unwrap(), 4);
+    assert_eq!(machine.memory_get(i + 2).unwrap(), 123);
+    assert_eq!(machine.memory_get(i + 3).unwrap(), 98);
+    assert_eq!(machine.memory_get(i + 4).unwrap(), 12);
+    assert_eq!(machine.memory_get(i + 5).unwrap(), 0);
     // SHould not modify the index pointer
     assert_eq!(machine.idx, 0xBCD);
     // Should store the registers
@@ -559,17 +627,1259 @@ fn test_9xy0_skip_if_mismatch_reg() {
 #[test]
 fn test_dxyn_draw() {
     let mut machine = Machine::new(TestContext::new());
-    machine.memory_set(machine.idx, 0x3C);
-    machine.memory_set(machine.idx + 1, 0xC3);
-    machine.memory_set(machine.idx + 2, 0xFF);
+    machine.memory_set(machine.idx, 0x3C).unwrap();
+    machine.memory_set(machine.idx + 1, 0xC3).unwrap();
+    machine.memory_set(machine.idx + 2, 0xFF).unwrap();
     machine.test_opcode(0xD003);
     let expected_top = [0, 0, 1, 1, 1, 1, 0, 0];
     let expected_middle = [1, 1, 0, 0, 0, 0, 1, 1];
     let expected_bottom = [1, 1, 1, 1, 1, 1, 1, 1];
-    let actual_top = &machine.screen[0..8];
-    let actual_middle = &machine.screen[PIXEL_COLS as usize..(PIXEL_COLS + 8) as usize];
-    let actual_bottom = &machine.screen[(PIXEL_COLS * 2) as usize..((2 * PIXEL_COLS) + 8) as usize];
+    let actual_top = &machine.screen.pixels()[0..8];
+    let actual_middle = &machine.screen.pixels()[PIXEL_COLS as usize..(PIXEL_COLS + 8) as usize];
+    let actual_bottom =
+        &machine.screen.pixels()[(PIXEL_COLS * 2) as usize..((2 * PIXEL_COLS) + 8) as usize];
     assert_eq!(&expected_top, actual_top);
     assert_eq!(&expected_middle, actual_middle);
     assert_eq!(&expected_bottom, actual_bottom);
 }
+
+#[test]
+fn test_0nnn_machine_call_unsupported() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.load_rom(&[0x01, 0x23]).unwrap();
+    assert_eq!(
+        machine.cycle(),
+        Err(Chip8Error::UnsupportedMachineCall { addr: 0x123 })
+    );
+}
+
+#[test]
+fn test_0nnn_machine_call_ignored() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.set_machine_calls(MachineCalls::Ignore);
+    machine.load_rom(&[0x01, 0x23, 0x61, 0x20]).unwrap();
+    machine.cycle().unwrap();
+    machine.cycle().unwrap();
+    assert_eq!(machine.pc, 0x204);
+    assert_eq!(machine.register_get(1), 0x20);
+}
+
+#[test]
+fn test_0nnn_machine_call_dispatched() {
+    /// Sets V0 to the low byte of the address called, for anything below 0x100
+    struct LowByte;
+    impl MachineCallHandler for LowByte {
+        fn call(&mut self, addr: u16, state: MachineCallState) -> bool {
+            state.registers[0] = addr as u8;
+            addr < 0x100
+        }
+    }
+    let mut machine = Machine::new(TestContext::new());
+    machine.set_machine_calls(MachineCalls::Dispatch(Box::new(LowByte)));
+    machine.load_rom(&[0x00, 0x42, 0x01, 0x23]).unwrap();
+    machine.cycle().unwrap();
+    assert_eq!(machine.register_get(0), 0x42);
+    assert_eq!(machine.pc, 0x202);
+    assert_eq!(
+        machine.cycle(),
+        Err(Chip8Error::UnsupportedMachineCall { addr: 0x123 })
+    );
+}
+
+#[test]
+fn test_vip_hires_program() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.set_machine_calls("vip".parse().unwrap());
+    let mut rom = vec![0x12, 0x60];
+    rom.resize(0xC0, 0);
+    // drw v0, v0, 1 with idx on a font byte, then the hi-res clear
+    rom.extend_from_slice(&[0xD0, 0x01, 0x02, 0x30]);
+    machine.load_rom(&rom).unwrap();
+    assert_eq!(machine.pc, 0x2C0);
+    assert_eq!(
+        machine.screen,
+        Screen::new(PIXEL_COLS, VIP_HIRES_PIXEL_ROWS)
+    );
+
+    machine.cycle().unwrap();
+    assert_ne!(
+        machine.screen,
+        Screen::new(PIXEL_COLS, VIP_HIRES_PIXEL_ROWS)
+    );
+    machine.cycle().unwrap();
+    assert_eq!(
+        machine.screen,
+        Screen::new(PIXEL_COLS, VIP_HIRES_PIXEL_ROWS)
+    );
+    assert_eq!(machine.pc, 0x2C4);
+
+    // The screen survives a save state round trip
+    let state = machine.save_state();
+    machine.load_state(&state).unwrap();
+
+    // Anything else is still a plain 64x32 program
+    machine.load_rom(&[0x12, 0x00]).unwrap();
+    assert_eq!(machine.pc, 0x200);
+    assert_eq!(machine.screen, Screen::default());
+}
+
+#[test]
+fn test_00ee_stack_underflow() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.load_rom(&[0x00, 0xEE]).unwrap();
+    assert_eq!(machine.cycle(), Err(Chip8Error::StackUnderflow));
+    // Should leave the stack pointer alone
+    assert_eq!(machine.sp, 0);
+}
+
+#[test]
+fn test_2nnn_stack_overflow() {
+    let mut machine = Machine::new(TestContext::new());
+    // Call 0x200 forever
+    machine.load_rom(&[0x22, 0x00]).unwrap();
+    for _ in 0..STACK_SIZE {
+        machine.cycle().unwrap();
+    }
+    assert_eq!(machine.cycle(), Err(Chip8Error::StackOverflow));
+    assert_eq!(machine.sp, STACK_SIZE);
+}
+
+#[test]
+fn test_invalid_opcode() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.load_rom(&[0x60, 0x01, 0x8B, 0xCF]).unwrap();
+    machine.cycle().unwrap();
+    assert_eq!(
+        machine.cycle(),
+        Err(Chip8Error::InvalidOpcode {
+            pc: PC_BEGIN + 2,
+            raw: RawOpcode::from(0x8BCF)
+        })
+    );
+}
+
+#[test]
+fn test_memory_out_of_bounds() {
+    let mut machine = Machine::new(TestContext::new());
+    // Point idx at the last byte and fill two registers from there
+    machine
+        .load_rom(&[0xF0, 0x00, 0xFF, 0xFF, 0xF1, 0x65])
+        .unwrap();
+    machine.cycle().unwrap();
+    assert_eq!(
+        machine.cycle(),
+        Err(Chip8Error::MemoryOutOfBounds { addr: 0x10000 })
+    );
+
+    // Running off the end of memory should fail to fetch
+    machine.load_rom(&[]).unwrap();
+    machine.pc = 0xFFFF;
+    assert_eq!(
+        machine.cycle(),
+        Err(Chip8Error::MemoryOutOfBounds { addr: 0x10000 })
+    );
+}
+
+#[test]
+fn test_reset_clears_stack_pointer() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.test_opcode(0x2BCD);
+    machine.reset();
+    assert_eq!(machine.sp, 0);
+}
+
+#[test]
+fn test_8xy6_shift_right_uses_vy_quirk() {
+    let mut machine = Machine::with_quirks(TestContext::new(), Quirks::COSMAC_VIP);
+    machine.register_set(0xB, 0xA);
+    machine.register_set(0xC, 0xD);
+    machine.test_opcode(0x8BC6);
+    // Should shift VY into VX, flag from VY
+    assert_eq!(machine.register_get(0xB), 0xD >> 1);
+    assert_eq!(machine.register_get(0xF), 1);
+    assert_eq!(machine.register_get(0xC), 0xD);
+}
+
+#[test]
+fn test_8xye_shift_left_uses_vy_quirk() {
+    let mut machine = Machine::with_quirks(TestContext::new(), Quirks::COSMAC_VIP);
+    machine.register_set(0xB, 0xA);
+    machine.register_set(0xC, 0x81);
+    machine.test_opcode(0x8BCE);
+    // Should shift VY into VX, flag from VY
+    assert_eq!(machine.register_get(0xB), 0x02);
+    assert_eq!(machine.register_get(0xF), 1);
+    assert_eq!(machine.register_get(0xC), 0x81);
+}
+
+#[test]
+fn test_bxnn_jump_uses_vx_quirk() {
+    let mut machine = Machine::with_quirks(TestContext::new(), Quirks::SUPER_CHIP);
+    machine.register_set(0, 4);
+    machine.register_set(0xC, 8);
+    machine.test_opcode(0xBCDE);
+    // Should advance to XNN plus VX
+    assert_eq!(machine.pc, 0xCDE + 8);
+}
+
+#[test]
+fn test_fx1e_increment_idx_overflow() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.idx = 0xFFE;
+    machine.register_set(0xA, 4);
+    machine.test_opcode(0xFA1E);
+    // By default, going past 0xFFF sets VF
+    assert_eq!(machine.idx, 0x1002);
+    assert!(machine.carry_flag_set());
+
+    // Other profiles should leave VF alone
+    machine.set_quirks(Quirks::COSMAC_VIP);
+    machine.idx = 0xFFE;
+    machine.register_set(0xF, 0);
+    machine.test_opcode(0xFA1E);
+    assert_eq!(machine.idx, 0x1002);
+    assert!(!machine.carry_flag_set());
+}
+
+#[test]
+fn test_fx55_fx65_idx_increment_quirk() {
+    let mut machine = Machine::with_quirks(TestContext::new(), Quirks::COSMAC_VIP);
+    machine.idx = 0xBCD;
+    machine.test_opcode(0xF355);
+    // Should leave idx just past V3
+    assert_eq!(machine.idx, 0xBCD + 4);
+    machine.test_opcode(0xF365);
+    assert_eq!(machine.idx, 0xBCD + 8);
+
+    machine.set_quirks(Quirks::CHIP_48);
+    machine.idx = 0xBCD;
+    machine.test_opcode(0xF355);
+    // Should leave idx on V3
+    assert_eq!(machine.idx, 0xBCD + 3);
+}
+
+#[test]
+fn test_dxyn_draw_wraps() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.memory_set(machine.idx, 0xFF).unwrap();
+    machine.register_set(0, (PIXEL_COLS - 4) as u8);
+    machine.register_set(1, (PIXEL_ROWS - 1) as u8);
+    machine.memory_set(machine.idx + 1, 0xFF).unwrap();
+    machine.test_opcode(0xD012);
+    let last_row = ((PIXEL_ROWS - 1) * PIXEL_COLS) as usize;
+    // Should draw the right half of each row on the left edge, and the second row at the top
+    assert_eq!(
+        &machine.screen.pixels()[last_row..last_row + 4],
+        &[1, 1, 1, 1]
+    );
+    assert_eq!(
+        &machine.screen.pixels()[last_row + 60..last_row + 64],
+        &[1, 1, 1, 1]
+    );
+    assert_eq!(&machine.screen.pixels()[0..4], &[1, 1, 1, 1]);
+    assert_eq!(&machine.screen.pixels()[60..64], &[1, 1, 1, 1]);
+}
+
+#[test]
+fn test_dxyn_draw_clips_quirk() {
+    let mut machine = Machine::with_quirks(TestContext::new(), Quirks::COSMAC_VIP);
+    machine.memory_set(machine.idx, 0xFF).unwrap();
+    machine.memory_set(machine.idx + 1, 0xFF).unwrap();
+    machine.register_set(0, (PIXEL_COLS - 4) as u8);
+    machine.register_set(1, (PIXEL_ROWS - 1) as u8);
+    machine.test_opcode(0xD012);
+    let last_row = ((PIXEL_ROWS - 1) * PIXEL_COLS) as usize;
+    // Should only draw the part of the sprite that fits
+    assert_eq!(
+        &machine.screen.pixels()[last_row + 60..last_row + 64],
+        &[1, 1, 1, 1]
+    );
+    assert_eq!(
+        &machine.screen.pixels()[last_row..last_row + 4],
+        &[0, 0, 0, 0]
+    );
+    assert_eq!(
+        &machine.screen.pixels()[0..PIXEL_COLS as usize],
+        &[0; PIXEL_COLS as usize][..]
+    );
+}
+
+#[test]
+fn test_00ff_00fe_resolution() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.screen.toggle(0, 0, 1);
+    machine.test_opcode(0x00FF);
+    // Should switch to a blank high resolution screen
+    assert!(machine.screen.is_hires());
+    assert_eq!(machine.screen, Screen::hires());
+    machine.test_opcode(0x00FE);
+    // Should switch back
+    assert!(!machine.screen.is_hires());
+    assert_eq!(machine.screen, Screen::default());
+    assert_eq!(machine.pc, PC_BEGIN + 4);
+}
+
+#[test]
+fn test_00cn_scroll_down() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.screen.toggle(3, 0, 1);
+    machine.screen.toggle(3, PIXEL_ROWS - 1, 1);
+    machine.test_opcode(0x00C2);
+    // Should move the pixel down, and drop the one that falls off
+    assert_eq!(machine.screen.get(3, 0), 0);
+    assert_eq!(machine.screen.get(3, 2), 1);
+    assert_eq!(
+        machine.screen.pixels().iter().filter(|&&p| p == 1).count(),
+        1
+    );
+    assert_eq!(machine.pc, PC_BEGIN + 2);
+}
+
+#[test]
+fn test_00fb_00fc_scroll_sideways() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.screen.toggle(10, 5, 1);
+    machine.test_opcode(0x00FB);
+    // Should move 4 pixels right
+    assert_eq!(machine.screen.get(10, 5), 0);
+    assert_eq!(machine.screen.get(14, 5), 1);
+    machine.test_opcode(0x00FC);
+    machine.test_opcode(0x00FC);
+    // Should move 8 pixels left
+    assert_eq!(machine.screen.get(6, 5), 1);
+    assert_eq!(
+        machine.screen.pixels().iter().filter(|&&p| p == 1).count(),
+        1
+    );
+}
+
+#[test]
+fn test_00fd_exit() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.load_rom(&[0x00, 0xFD, 0x60, 0x01]).unwrap();
+    machine.cycle().unwrap();
+    assert!(machine.has_exited());
+    // Should stop executing
+    machine.cycle().unwrap();
+    assert_eq!(machine.pc, PC_BEGIN);
+    assert_eq!(machine.register_get(0), 0);
+    // Should start again with a new game
+    machine.load_rom(&[0x60, 0x01]).unwrap();
+    assert!(!machine.has_exited());
+}
+
+#[test]
+fn test_dxy0_draw_16x16() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.test_opcode(0x00FF);
+    for row in 0..16 {
+        machine.memory_set(machine.idx + row * 2, 0x80).unwrap();
+        machine.memory_set(machine.idx + row * 2 + 1, 0x01).unwrap();
+    }
+    machine.register_set(0, 100);
+    machine.register_set(1, 40);
+    machine.test_opcode(0xD010);
+    // Should draw the leftmost and rightmost columns of all 16 rows
+    for row in 40..56 {
+        assert_eq!(machine.screen.get(100, row), 1);
+        assert_eq!(machine.screen.get(115, row), 1);
+    }
+    assert_eq!(
+        machine.screen.pixels().iter().filter(|&&p| p == 1).count(),
+        32
+    );
+    assert!(!machine.carry_flag_set());
+    // Should report collisions the same way
+    machine.test_opcode(0xD010);
+    assert!(machine.carry_flag_set());
+    assert_eq!(machine.screen, Screen::hires());
+}
+
+#[test]
+fn test_fx29_new_sprite() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.register_set(0xB, 0xA);
+    machine.test_opcode(0xFB29);
+    // Should point at the small glyph
+    assert_eq!(machine.idx, 0xA * 5);
+    assert_eq!(machine.memory_get(machine.idx).unwrap(), 0xF0);
+    assert_eq!(machine.pc, PC_BEGIN + 2);
+}
+
+#[test]
+fn test_fx30_new_big_sprite() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.register_set(0xB, 7);
+    machine.test_opcode(0xFB30);
+    // Should point at the big glyph
+    assert_eq!(machine.idx, BIG_FONTSET_START + 70);
+    let glyph = (0..10)
+        .map(|i| machine.memory_get(machine.idx + i).unwrap())
+        .collect::<Vec<u8>>();
+    assert_eq!(glyph, BIG_FONTSET[70..80].to_vec());
+    assert_eq!(machine.pc, PC_BEGIN + 2);
+}
+
+#[test]
+fn test_fx75_fx85_rpl_flags() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.register_set(0, 0xC);
+    machine.register_set(1, 4);
+    machine.register_set(2, 123);
+    machine.test_opcode(0xF175);
+    machine.register_set(0, 0);
+    machine.register_set(1, 0);
+    machine.register_set(2, 0);
+    // Should survive loading a new game
+    machine.load_rom(&[]).unwrap();
+    machine.test_opcode(0xF285);
+    assert_eq!(machine.register_get(0), 0xC);
+    assert_eq!(machine.register_get(1), 4);
+    // Only V0 and V1 were saved
+    assert_eq!(machine.register_get(2), 0);
+}
+
+#[test]
+fn test_00dn_scroll_up() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.screen.toggle(3, 0, 1);
+    machine.screen.toggle(3, 5, 1);
+    machine.test_opcode(0x00D2);
+    // Should move the pixel up, and drop the one that falls off
+    assert_eq!(machine.screen.get(3, 5), 0);
+    assert_eq!(machine.screen.get(3, 3), 1);
+    assert_eq!(
+        machine.screen.pixels().iter().filter(|&&p| p == 1).count(),
+        1
+    );
+    assert_eq!(machine.pc, PC_BEGIN + 2);
+}
+
+#[test]
+fn test_5xy2_save_range() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.idx = 0xBCD;
+    machine.register_set(2, 0xC);
+    machine.register_set(3, 4);
+    machine.register_set(4, 123);
+    machine.test_opcode(0x5242);
+    // Should store V2 to V4 without moving idx
+    assert_eq!(machine.memory_get(0xBCD).unwrap(), 0xC);
+    assert_eq!(machine.memory_get(0xBCE).unwrap(), 4);
+    assert_eq!(machine.memory_get(0xBCF).unwrap(), 123);
+    assert_eq!(machine.memory_get(0xBD0).unwrap(), 0);
+    assert_eq!(machine.idx, 0xBCD);
+    // Should store in reverse if X > Y
+    machine.test_opcode(0x5422);
+    assert_eq!(machine.memory_get(0xBCD).unwrap(), 123);
+    assert_eq!(machine.memory_get(0xBCF).unwrap(), 0xC);
+    assert_eq!(machine.pc, PC_BEGIN + 4);
+}
+
+#[test]
+fn test_5xy3_load_range() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.idx = 0xBCD;
+    machine.memory_set(0xBCD, 0xC).unwrap();
+    machine.memory_set(0xBCE, 4).unwrap();
+    machine.test_opcode(0x5673);
+    // Should fill V6 and V7 without moving idx
+    assert_eq!(machine.register_get(6), 0xC);
+    assert_eq!(machine.register_get(7), 4);
+    assert_eq!(machine.idx, 0xBCD);
+    // Should fill in reverse if X > Y
+    machine.test_opcode(0x5983);
+    assert_eq!(machine.register_get(9), 0xC);
+    assert_eq!(machine.register_get(8), 4);
+}
+
+#[test]
+fn test_f000_long_set_idx() {
+    let mut machine = Machine::new(TestContext::new());
+    machine
+        .load_rom(&[0xF0, 0x00, 0xAB, 0xCD, 0x30, 0x00, 0xF0, 0x00, 0x12, 0x34])
+        .unwrap();
+    machine.cycle().unwrap();
+    // Should load the full 16-bit address and step over it
+    assert_eq!(machine.idx, 0xABCD);
+    assert_eq!(machine.pc, PC_BEGIN + 4);
+    // Skips should step over the whole four byte instruction
+    machine.cycle().unwrap();
+    assert_eq!(machine.pc, PC_BEGIN + 10);
+    assert_eq!(machine.idx, 0xABCD);
+}
+
+#[test]
+fn test_fn01_select_planes() {
+    let mut machine = Machine::new(TestContext::new());
+    // One sprite row per plane
+    machine.memory_set(machine.idx, 0xF0).unwrap();
+    machine.memory_set(machine.idx + 1, 0x3C).unwrap();
+    machine.test_opcode(0xF301);
+    machine.test_opcode(0xD001);
+    // Should draw the first row to plane 1 and the second to plane 2
+    assert_eq!(&machine.screen.pixels()[0..8], &[1, 1, 3, 3, 2, 2, 0, 0]);
+    assert!(!machine.carry_flag_set());
+    // Should only clear the selected plane
+    machine.test_opcode(0xF201);
+    machine.test_opcode(0x00E0);
+    assert_eq!(&machine.screen.pixels()[0..8], &[1, 1, 1, 1, 0, 0, 0, 0]);
+    // Should only draw to the selected plane, with collisions from that plane
+    machine.test_opcode(0xD001);
+    assert_eq!(&machine.screen.pixels()[0..8], &[3, 3, 3, 3, 0, 0, 0, 0]);
+    assert!(!machine.carry_flag_set());
+    machine.test_opcode(0xD001);
+    assert!(machine.carry_flag_set());
+    // Selecting no planes draws nothing
+    machine.test_opcode(0xF001);
+    machine.test_opcode(0xD001);
+    assert_eq!(&machine.screen.pixels()[0..8], &[1, 1, 1, 1, 0, 0, 0, 0]);
+}
+
+#[test]
+fn test_f002_fx3a_audio() {
+    let mut machine = Machine::new(TestContext::new());
+    assert_eq!(machine.audio, AudioPattern::default());
+    machine.idx = 0xBCD;
+    for i in 0..AUDIO_PATTERN_BYTES as u16 {
+        machine.memory_set(0xBCD + i, i as u8).unwrap();
+    }
+    machine.test_opcode(0xF002);
+    // Should copy the pattern from idx
+    assert_eq!(machine.audio.buffer[0], 0);
+    assert_eq!(machine.audio.buffer[15], 15);
+    assert!(machine.audio.sample(15));
+    assert!(!machine.audio.sample(14));
+    machine.register_set(0xB, 112);
+    machine.test_opcode(0xFB3A);
+    // Should double the playback rate an octave up
+    assert_eq!(machine.audio.pitch, 112);
+    assert_eq!(machine.audio.sample_rate(), 8000.0);
+    // Should go back to the default tone with a new game
+    machine.load_rom(&[]).unwrap();
+    assert_eq!(machine.audio, AudioPattern::default());
+}
+
+#[test]
+fn test_headless_run_until_quit() {
+    let context = HeadlessContext::new(1);
+    context.quit_after(200);
+    let mut machine = Machine::new(Box::new(context.clone()));
+    machine.load_game("test_opcode").unwrap();
+    machine.run().unwrap();
+    // Should stop on the virtual clock without really sleeping, having drawn something
+    assert_eq!(context.now(), 200);
+    let frames = context.frames().len();
+    assert!(frames > 0);
+    assert_eq!(context.take_frames().len(), frames);
+    assert!(context.frames().is_empty());
+}
+
+#[test]
+fn test_headless_scripted_keys() {
+    let context = HeadlessContext::new(1);
+    context.push_key_event(KeyEvent::release(150, 0xA));
+    context.push_key_event(KeyEvent::press(100, 0xA));
+    context.quit_after(300);
+    let mut machine = Machine::new(Box::new(context.clone()));
+    // Wait for a key, then spin
+    machine.load_rom(&[0xF0, 0x0A, 0x12, 0x02]).unwrap();
+    machine.run().unwrap();
+    assert_eq!(machine.register_get(0), 0xA);
+    assert_eq!(machine.pc, PC_BEGIN + 2);
+    // Should have let the key go again
+    assert_eq!(context.get_key_state(), [false; NUM_KEYS]);
+}
+
+#[test]
+fn test_headless_scripted_keys_through_run_due() {
+    let context = HeadlessContext::new(1);
+    context.push_key_event(KeyEvent::press(100, 0x7));
+    context.push_key_event(KeyEvent::release(150, 0x7));
+    let mut machine = Machine::new(Box::new(context.clone()));
+    // Wait for a key, then spin
+    machine.load_rom(&[0xF0, 0x0A, 0x12, 0x02]).unwrap();
+    // Drive it the way chip8-headless does, without ever polling for input
+    while context.now() < 300 {
+        machine.run_due().unwrap();
+        context.sleep(1);
+    }
+    assert_eq!(machine.register_get(0), 0x7);
+    assert_eq!(machine.pc, PC_BEGIN + 2);
+    assert_eq!(context.get_key_state(), [false; NUM_KEYS]);
+}
+
+#[test]
+fn test_headless_ignores_keys_off_the_keypad() {
+    let mut context = HeadlessContext::new(1);
+    context.push_key_event(KeyEvent::press(0, 0x10));
+    context.push_key_event(KeyEvent::press(0, 0x3));
+    context.listen_for_input();
+    let mut expected = [false; NUM_KEYS];
+    expected[0x3] = true;
+    assert_eq!(context.get_key_state(), expected);
+}
+
+#[test]
+fn test_headless_seeded_random() {
+    let bytes = |seed| {
+        let context = HeadlessContext::new(seed);
+        (0..16).map(|_| context.random_byte()).collect::<Vec<u8>>()
+    };
+    // Should repeat for the same seed, and not for a different one
+    assert_eq!(bytes(42), bytes(42));
+    assert_ne!(bytes(42), bytes(43));
+    assert_ne!(bytes(0), vec![0; 16]);
+}
+
+#[test]
+fn test_save_state_round_trip() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.load_game("pong").unwrap();
+    for _ in 0..100 {
+        machine.cycle().unwrap();
+    }
+    machine.key.key_down(0xC);
+    let state = machine.save_state();
+    // Should put everything back after running on
+    for _ in 0..100 {
+        machine.cycle().unwrap();
+    }
+    machine.load_game("tank").unwrap();
+    machine.load_state(&state).unwrap();
+    assert_eq!(machine.save_state(), state);
+    assert_eq!(machine.current_game, Some("pong".to_string()));
+    assert!(machine.key_pressed(0xC));
+    assert!(machine.draw_flag);
+}
+
+#[test]
+fn test_save_state_bytes_round_trip() {
+    let mut machine = Machine::with_quirks(TestContext::new(), Quirks::XO_CHIP);
+    machine.load_game("pong").unwrap();
+    for _ in 0..100 {
+        machine.cycle().unwrap();
+    }
+    machine.test_opcode(0x00FF);
+    machine.test_opcode(0xF301);
+    let state = machine.save_state();
+    let bytes = state.to_bytes();
+    assert_eq!(&bytes[0..6], b"C8ST\x01\x00");
+    assert_eq!(SaveState::from_bytes(&bytes).unwrap(), state);
+
+    // Should handle a state without a game name too
+    machine.load_rom(&[0x12, 0x00]).unwrap();
+    let state = machine.save_state();
+    assert_eq!(SaveState::from_bytes(&state.to_bytes()).unwrap(), state);
+}
+
+#[test]
+fn test_save_state_bytes_errors() {
+    let machine = Machine::new(TestContext::new());
+    let bytes = machine.save_state().to_bytes();
+    let error = |bytes: &[u8]| SaveState::from_bytes(bytes).err().unwrap().to_string();
+    assert_eq!(error(b"NOPE\x01\x00"), "Not a save state");
+    assert_eq!(error(b"C8ST\x02\x00"), "Unsupported save state version 2");
+    assert_eq!(error(&bytes[..bytes.len() - 1]), "Save state is truncated");
+    let mut extra = bytes;
+    extra.push(0);
+    assert_eq!(error(&extra), "Save state has 1 unexpected trailing bytes");
+}
+
+#[test]
+fn test_load_state_rejects_nonsense() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.load_game("pong").unwrap();
+    let good = machine.save_state();
+
+    let mut state = good.clone();
+    state.sp = STACK_SIZE + 1;
+    assert!(machine.load_state(&state).is_err());
+    let mut state = good.clone();
+    state.memory.truncate(0x1000);
+    assert!(machine.load_state(&state).is_err());
+    let mut state = good.clone();
+    state.screen = Screen::new(10, 10);
+    assert!(machine.load_state(&state).is_err());
+    // Should leave the machine alone
+    assert_eq!(machine.save_state(), good);
+}
+
+#[test]
+fn test_state_commands() {
+    let context = HeadlessContext::new(1);
+    let mut machine = Machine::new(Box::new(context.clone()));
+    machine.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+    // Count up V0, saving partway through
+    for _ in 0..10 {
+        machine.step().unwrap();
+    }
+    context.push_command(Command::SaveState(1));
+    machine.step().unwrap();
+    assert_eq!(machine.register_get(0), 6);
+    for _ in 0..10 {
+        machine.step().unwrap();
+    }
+    // Should come back to the saved point, and ignore an empty slot
+    context.push_command(Command::LoadState(2));
+    context.push_command(Command::LoadState(1));
+    machine.step().unwrap();
+    assert_eq!(machine.register_get(0), 6);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_save_state_json_round_trip() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.load_game("pong").unwrap();
+    for _ in 0..100 {
+        machine.cycle().unwrap();
+    }
+    let state = machine.save_state();
+    assert_eq!(
+        SaveState::from_json(&state.to_json().unwrap()).unwrap(),
+        state
+    );
+}
+
+/// Sets V0, calls a subroutine that stores it as BCD at 0x300, then adds one and loops forever
+const DEBUG_ROM: &[u8] = &[
+    0x60, 0x05, 0x22, 0x08, 0x70, 0x01, 0x12, 0x06, 0xA3, 0x00, 0xF0, 0x33, 0x00, 0xEE,
+];
+
+#[test]
+fn test_debugger_breakpoint() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.load_rom(DEBUG_ROM).unwrap();
+    let mut debugger = Debugger::new();
+    debugger.add_breakpoint(0x20A);
+    assert_eq!(debugger.resume(&mut machine), StopReason::Breakpoint(0x20A));
+    assert_eq!(machine.call_stack(), &[0x202]);
+    // Resuming runs the instruction at the breakpoint rather than stopping straight away
+    debugger.add_breakpoint(0x204);
+    assert_eq!(debugger.resume(&mut machine), StopReason::Breakpoint(0x204));
+    assert!(debugger.remove_breakpoint(0x204));
+    assert!(!debugger.remove_breakpoint(0x204));
+}
+
+#[test]
+fn test_debugger_step() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.load_rom(DEBUG_ROM).unwrap();
+    let debugger = Debugger::new();
+    assert_eq!(debugger.step(&mut machine, 3), StopReason::Done);
+    assert_eq!(machine.pc, 0x20A);
+    assert_eq!(machine.idx, 0x300);
+}
+
+#[test]
+fn test_debugger_watchpoint() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.load_rom(DEBUG_ROM).unwrap();
+    let mut debugger = Debugger::new();
+    // Reads of the tens digit shouldn't stop anything
+    debugger.add_watchpoint(0x301, WatchKind::Read);
+    debugger.add_watchpoint(0x302, WatchKind::Write);
+    assert_eq!(
+        debugger.resume(&mut machine),
+        StopReason::Watchpoint {
+            addr: 0x302,
+            access: Access::Write
+        }
+    );
+    // Stops before the write happens
+    assert_eq!(machine.pc, 0x20A);
+    assert_eq!(machine.memory()[0x302], 0);
+}
+
+#[test]
+fn test_debugger_condition() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.load_rom(DEBUG_ROM).unwrap();
+    let mut debugger = Debugger::new();
+    let condition = Condition {
+        register: 0,
+        comparison: Comparison::Ge,
+        value: 6,
+    };
+    debugger.add_condition(condition);
+    assert_eq!(
+        debugger.resume(&mut machine),
+        StopReason::Condition(condition)
+    );
+    assert_eq!(machine.registers()[0], 6);
+    // Only fires when the condition becomes true, so this runs the full count
+    assert_eq!(debugger.step(&mut machine, 10), StopReason::Done);
+}
+
+#[test]
+fn test_debugger_step_over_and_out() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.load_rom(DEBUG_ROM).unwrap();
+    let debugger = Debugger::new();
+    debugger.step(&mut machine, 1);
+    assert_eq!(debugger.step_over(&mut machine), StopReason::Done);
+    assert_eq!(machine.pc, 0x204);
+    assert_eq!(&machine.memory()[0x300..0x303], &[0, 0, 5]);
+
+    machine.load_rom(DEBUG_ROM).unwrap();
+    debugger.step(&mut machine, 3);
+    assert_eq!(debugger.step_out(&mut machine), StopReason::Done);
+    assert_eq!(machine.pc, 0x204);
+    assert!(machine.call_stack().is_empty());
+    assert_eq!(
+        debugger.step_out(&mut machine),
+        StopReason::Error(Chip8Error::StackUnderflow)
+    );
+}
+
+#[test]
+fn test_debugger_pumps_events() {
+    // Wait for a key, then spin at 0x202
+    let rom = [0xF0, 0x0A, 0x12, 0x02];
+    let context = HeadlessContext::new(1);
+    context.push_key_event(KeyEvent::press(0, 5));
+    let mut machine = Machine::new(Box::new(context.clone()));
+    machine.load_rom(&rom).unwrap();
+    let mut debugger = Debugger::new();
+    debugger.add_breakpoint(0x202);
+    assert_eq!(debugger.resume(&mut machine), StopReason::Breakpoint(0x202));
+    assert_eq!(machine.registers()[0], 5);
+
+    // Closing the window stops a continue stuck waiting for a key
+    let context = HeadlessContext::new(1);
+    context.quit_after(0);
+    let mut machine = Machine::new(Box::new(context));
+    machine.load_rom(&rom).unwrap();
+    assert_eq!(debugger.resume(&mut machine), StopReason::Quit);
+    assert_eq!(machine.pc, 0x200);
+}
+
+#[test]
+fn test_debugger_run_limit() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.load_rom(&[0x12, 0x00]).unwrap();
+    let mut debugger = Debugger::new();
+    debugger.set_run_limit(1000);
+    assert_eq!(debugger.resume(&mut machine), StopReason::Limit(1000));
+    // A plain step isn't held to it
+    assert_eq!(debugger.step(&mut machine, 2000), StopReason::Done);
+}
+
+#[test]
+fn test_pending_accesses() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.load_rom(DEBUG_ROM).unwrap();
+    assert_eq!(machine.pending_accesses().unwrap(), vec![]);
+    Debugger::new().step(&mut machine, 3);
+    assert_eq!(machine.current_opcode().unwrap(), Opcode::BCD(0));
+    let writes: Vec<u16> = machine
+        .pending_accesses()
+        .unwrap()
+        .iter()
+        .map(|a| a.addr)
+        .collect();
+    assert_eq!(writes, vec![0x300, 0x301, 0x302]);
+}
+
+#[test]
+fn test_parse_debug_command() {
+    assert_eq!("".parse::<DebugCommand>().unwrap(), DebugCommand::Step(1));
+    assert_eq!(
+        "s 10".parse::<DebugCommand>().unwrap(),
+        DebugCommand::Step(10)
+    );
+    assert_eq!(
+        "w 0x300 rw".parse::<DebugCommand>().unwrap(),
+        DebugCommand::Watch(0x300, WatchKind::ReadWrite)
+    );
+    assert_eq!(
+        "if vA != 0x10".parse::<DebugCommand>().unwrap(),
+        DebugCommand::When(Condition {
+            register: 0xA,
+            comparison: Comparison::Ne,
+            value: 0x10
+        })
+    );
+    assert!("if VG == 1".parse::<DebugCommand>().is_err());
+    assert!("b".parse::<DebugCommand>().is_err());
+    assert!("frobnicate".parse::<DebugCommand>().is_err());
+}
+
+#[test]
+fn test_timers_tick_at_60hz() {
+    let mut machine = Machine::new(TestContext::new());
+    // Spin in place
+    machine.load_rom(&[0x12, 0x00]).unwrap();
+    machine.delay_timer = 100;
+    machine.sound_timer = 30;
+    // One second of emulated time
+    for _ in 0..CYCLES_PER_SECOND {
+        machine.cycle().unwrap();
+    }
+    assert_eq!(machine.delay_timer, 40);
+    assert_eq!(machine.sound_timer, 0);
+    // Changing speed shouldn't change the timer rate
+    machine.set_speed(CYCLES_PER_SECOND * 2);
+    machine.delay_timer = 60;
+    for _ in 0..CYCLES_PER_SECOND {
+        machine.cycle().unwrap();
+    }
+    assert_eq!(machine.delay_timer, 30);
+}
+
+#[test]
+fn test_headless_delay_lasts_a_second() {
+    let context = HeadlessContext::new(1);
+    context.quit_after(5000);
+    let mut machine = Machine::new(Box::new(context.clone()));
+    // Set the delay timer to 60, wait for it to run out, then exit
+    machine
+        .load_rom(&[
+            0x60, 0x3C, 0xF0, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x04, 0x00, 0xFD,
+        ])
+        .unwrap();
+    machine.run().unwrap();
+    assert!(machine.has_exited());
+    // Instructions are paced off the virtual clock, so this should take one second of it
+    assert!((1000..1010).contains(&context.now()), "{}", context.now());
+}
+
+#[test]
+fn test_tone_lasts_as_long_as_the_sound_timer() {
+    let context = HeadlessContext::new(1);
+    context.quit_after(1000);
+    let mut machine = Machine::new(Box::new(context.clone()));
+    // Set the sound timer to 30, then spin
+    machine
+        .load_rom(&[0x60, 0x1E, 0xF0, 0x18, 0x12, 0x04])
+        .unwrap();
+    machine.run().unwrap();
+    let tones = context.tones();
+    assert_eq!(tones.len(), 1, "{:?}", tones);
+    let (start, stop) = tones[0];
+    // Half a second at 60Hz, give or take a tick
+    let length = stop.unwrap() - start;
+    assert!((480..=520).contains(&length), "{}", length);
+}
+
+#[test]
+fn test_scheduler_catch_up_is_capped() {
+    let mut scheduler = Scheduler::new(1000);
+    assert_eq!(scheduler.instructions_due(0), 0);
+    assert_eq!(scheduler.instructions_due(10), 10);
+    // A long stall shouldn't turn into a burst of instructions
+    assert_eq!(scheduler.instructions_due(60_000), 100);
+    // Time going backwards is ignored
+    assert_eq!(scheduler.instructions_due(50_000), 0);
+    // Fractions carry over
+    let mut scheduler = Scheduler::new(500);
+    scheduler.instructions_due(0);
+    let total: u64 = (1..=10).map(|t| scheduler.instructions_due(t)).sum();
+    assert_eq!(total, 5);
+}
+
+#[test]
+fn test_pause_and_frame_advance() {
+    let context = HeadlessContext::new(1);
+    let mut machine = Machine::new(Box::new(context.clone()));
+    // Count up V0 forever
+    machine.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+    machine.run_due().unwrap();
+    context.push_command(Command::TogglePause);
+    machine.poll_commands();
+    assert!(machine.is_paused());
+    context.sleep(50);
+    machine.run_due().unwrap();
+    assert_eq!(machine.register_get(0), 0);
+    assert_eq!(machine.frame_count(), 0);
+
+    // A frame is a 60th of a second of instructions
+    context.push_command(Command::FrameAdvance);
+    machine.poll_commands();
+    machine.run_due().unwrap();
+    assert_eq!(machine.frame_count(), 1);
+    let after_frame = machine.register_get(0);
+    // The first tick comes 9 instructions in at 500 per second, 5 of them adds
+    assert_eq!(after_frame, 5);
+    machine.run_due().unwrap();
+    assert_eq!(machine.register_get(0), after_frame);
+
+    context.push_command(Command::TogglePause);
+    machine.poll_commands();
+    context.sleep(10);
+    machine.run_due().unwrap();
+    assert!(machine.register_get(0) > after_frame);
+}
+
+#[test]
+fn test_speed_and_turbo() {
+    let context = HeadlessContext::new(1);
+    let mut machine = Machine::new(Box::new(context.clone()));
+    // Count up V0 forever
+    machine.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+    machine.run_due().unwrap();
+    context.push_command(Command::SetSpeed(1000));
+    machine.poll_commands();
+    context.sleep(20);
+    machine.run_due().unwrap();
+    assert_eq!(machine.register_get(0), 10);
+
+    machine.set_turbo(true);
+    context.sleep(20);
+    machine.run_due().unwrap();
+    assert_eq!(machine.register_get(0), 10 + 10 * TURBO_FACTOR as u8);
+
+    context.push_command(Command::SpeedUp);
+    machine.poll_commands();
+    assert_eq!(machine.speed(), 1250);
+    context.push_command(Command::SpeedDown);
+    machine.poll_commands();
+    assert_eq!(machine.speed(), 1000);
+}
+
+#[test]
+fn test_rewind() {
+    let context = HeadlessContext::new(1);
+    let mut machine = Machine::new(Box::new(context.clone()));
+    // Count up V0 forever
+    machine.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+    machine.run_due().unwrap();
+    let mut seen = Vec::new();
+    for _ in 0..30 {
+        context.sleep(20);
+        machine.run_due().unwrap();
+        seen.push(machine.register_get(0));
+    }
+    assert_eq!(machine.rewind_len(), 30);
+    // Only the bytes that changed are kept for each older frame
+    assert!(machine.rewind.memory_usage() < 2 * MEM_SIZE);
+
+    // Each 20ms of holding rewind steps back a frame
+    context.push_command(Command::SetRewind(true));
+    machine.poll_commands();
+    context.sleep(20);
+    machine.run_due().unwrap();
+    assert_eq!(machine.register_get(0), seen[29]);
+    context.sleep(20);
+    machine.run_due().unwrap();
+    assert_eq!(machine.register_get(0), seen[28]);
+    // Holding on stops at the oldest frame
+    for _ in 0..50 {
+        context.sleep(20);
+        machine.run_due().unwrap();
+    }
+    assert_eq!(machine.register_get(0), seen[0]);
+    assert_eq!(machine.rewind_len(), 0);
+
+    // Letting go carries on from there
+    context.push_command(Command::SetRewind(false));
+    machine.poll_commands();
+    context.sleep(20);
+    machine.run_due().unwrap();
+    assert!(machine.register_get(0) > seen[0]);
+    assert_eq!(machine.rewind_len(), 1);
+
+    // A new game starts with no history
+    machine.load_rom(&[0x12, 0x00]).unwrap();
+    assert_eq!(machine.rewind_len(), 0);
+}
+
+#[test]
+fn test_movie_replays_exactly() {
+    let rom = ROMS.get("pong").unwrap().as_slice();
+    let live = HeadlessContext::new(1);
+    live.push_key_event(KeyEvent::press(200, 0x1));
+    live.push_key_event(KeyEvent::release(900, 0x1));
+    live.push_key_event(KeyEvent::press(1200, 0x4));
+    let recorder = RecordingContext::new(
+        Box::new(live.clone()),
+        Movie::new(rom, Quirks::default(), 700, 0xC8),
+    );
+    let recording = recorder.movie();
+    let mut machine = Machine::new(Box::new(recorder));
+    machine.set_speed(700);
+    machine.load_rom(rom).unwrap();
+    // Loading a state mid-recording would go out of sync
+    live.push_command(Command::LoadState(1));
+    for _ in 0..120 {
+        live.sleep(16);
+        assert!(!machine.handle_events());
+        machine.run_frame().unwrap();
+    }
+    let recorded = machine.save_state();
+
+    let movie = Movie::from_bytes(&recording.read().unwrap().to_bytes()).unwrap();
+    assert_eq!(movie.frames.len(), 120);
+    assert_eq!(movie.frames[20], 0b10);
+    assert_eq!(movie.frames[100], 0b1_0000);
+    movie.check_rom(rom).unwrap();
+    assert!(movie.check_rom(&rom[1..]).is_err());
+
+    // Different seed and nothing scripted - everything has to come from the movie
+    let mut machine = Machine::with_quirks(
+        Box::new(ReplayContext::new(
+            Box::new(HeadlessContext::new(2)),
+            movie.clone(),
+        )),
+        movie.quirks,
+    );
+    machine.set_speed(movie.speed);
+    machine.load_rom(rom).unwrap();
+    for _ in 0..120 {
+        assert!(!machine.handle_events());
+        machine.run_frame().unwrap();
+    }
+    assert_eq!(machine.save_state(), recorded);
+    // All played out
+    assert!(machine.handle_events());
+}
+
+#[test]
+fn test_movie_bytes_errors() {
+    let movie = Movie::new(&[0x12, 0x00], Quirks::COSMAC_VIP, 500, 7);
+    let bytes = movie.to_bytes();
+    assert_eq!(Movie::from_bytes(&bytes).unwrap(), movie);
+    let error = |bytes: &[u8]| Movie::from_bytes(bytes).unwrap_err().to_string();
+    assert_eq!(error(&bytes[..10]), "Movie is truncated");
+    assert_eq!(
+        error(b"C8ST and then some more bytes here..."),
+        "Not a movie"
+    );
+    let mut extra = bytes;
+    extra.push(0);
+    assert_eq!(
+        error(&extra),
+        "Movie should have 0 frames, but has 1 bytes of them"
+    );
+}
+
+/// Trace output that can still be read once the tracer belongs to the machine
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<RwLock<Vec<u8>>>);
+
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_trace_records_register_changes() {
+    let mut machine = Machine::new(TestContext::new());
+    // ld v1, 0x20; add v1, v1; ld i, 0x2EA; jp 0x206
+    machine
+        .load_rom(&[0x61, 0x20, 0x81, 0x14, 0xA2, 0xEA, 0x12, 0x06])
+        .unwrap();
+    let output = SharedBuffer::default();
+    machine.set_tracer(Tracer::new(Box::new(output.clone()), TraceFormat::Text));
+    for _ in 0..4 {
+        machine.cycle().unwrap();
+    }
+    let trace = String::from_utf8(output.0.read().unwrap().clone()).unwrap();
+    let lines: Vec<&str> = trace.lines().map(|line| &line[7..]).collect();
+    assert_eq!(
+        lines,
+        vec![
+            "0x200 0x6120 ld v1, 0x20          I=0x000 VF=0x00 V1=0x00->0x20",
+            "0x202 0x8114 add v1, v1           I=0x000 VF=0x00 V1=0x20->0x40",
+            "0x204 0xA2EA ld i, 0x2EA          I=0x2EA VF=0x00",
+            "0x206 0x1206 jp 0x206             I=0x2EA VF=0x00",
+        ]
+    );
+
+    // Only the jumps, as JSON, and nothing more once the tracer is taken back
+    let output = SharedBuffer::default();
+    machine.set_tracer(
+        Tracer::new(Box::new(output.clone()), TraceFormat::Json).with_filter(TraceFilter {
+            pcs: None,
+            classes: vec![OpcodeClass::Flow],
+        }),
+    );
+    machine.cycle().unwrap();
+    assert!(machine.take_tracer().is_some());
+    machine.cycle().unwrap();
+    let trace = String::from_utf8(output.0.read().unwrap().clone()).unwrap();
+    assert_eq!(trace.lines().count(), 1);
+    assert!(trace.contains(r#""pc":518,"raw":4614,"opcode":"jp 0x206""#));
+}
+
+#[test]
+fn test_profile_finds_idle_time() {
+    let mut machine = Machine::new(TestContext::new());
+    // ld v0, 3; ld dt, v0; ld v1, dt; se v1, 0; jp 0x204; ld v2, k
+    machine
+        .load_rom(&[
+            0x60, 0x03, 0xF0, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x04, 0xF2, 0x0A,
+        ])
+        .unwrap();
+    machine.set_profiler(Profiler::new());
+    for _ in 0..5 {
+        machine.run_frame().unwrap();
+    }
+    let profiler = machine.take_profiler().unwrap();
+    let per_frame = machine.speed() / TIMER_HZ;
+    // Three frames spent polling the timer, then waiting for a key
+    assert!(profiler.delay_poll() > 2 * per_frame);
+    assert!(profiler.key_wait() > per_frame);
+    // The last time round skips the jump back
+    assert_eq!(profiler.hits(0x204), profiler.hits(0x208) + 1);
+    assert!(profiler
+        .report(3)
+        .starts_with(&format!("Ran {} instructions\n", profiler.total())));
+}
+
+#[test]
+fn test_access_map_flags_suspicious_memory_use() {
+    let mut machine = Machine::new(TestContext::new());
+    machine.set_access_map(AccessMap::new());
+    // ld i, 0x800; drw v0, v0, 2; ld i, 0x200; ld b, v0; ld b, v0; ld i, 0x900; ld b, v0; (data)
+    machine
+        .load_rom(&[
+            0xA8, 0x00, 0xD0, 0x02, 0xA2, 0x00, 0xF0, 0x33, 0xF0, 0x33, 0xA9, 0x00, 0xF0, 0x33,
+            0xFF,
+        ])
+        .unwrap();
+    for _ in 0..7 {
+        machine.cycle().unwrap();
+    }
+    let map = machine.access_map().unwrap();
+    assert_eq!(map.kind(0x800), ByteKind::Data);
+    assert_eq!(map.kind(0x900), ByteKind::Written);
+    assert_eq!(map.kind(0x20E), ByteKind::Data);
+    assert_eq!(map.kind(0x20F), ByteKind::Unused);
+    assert!(map.get(0x200).written && map.get(0x200).executed);
+    assert_eq!(
+        map.executed().collect::<Vec<_>>(),
+        vec![0x200, 0x202, 0x204, 0x206, 0x208, 0x20A, 0x20C]
+    );
+    // BCD writes over the first three bytes, but each address only comes up once
+    let issue = |pc, addr| MemoryIssue::SelfModifyingCode { pc, addr };
+    assert_eq!(
+        map.issues(),
+        &[
+            MemoryIssue::UninitialisedRead {
+                pc: 0x202,
+                addr: 0x800
+            },
+            MemoryIssue::UninitialisedRead {
+                pc: 0x202,
+                addr: 0x801
+            },
+            issue(0x206, 0x200),
+            issue(0x206, 0x201),
+            issue(0x206, 0x202),
+        ]
+    );
+
+    // Loading another ROM starts a fresh map
+    machine.load_rom(&[0x00, 0xE0]).unwrap();
+    let map = machine.access_map().unwrap();
+    assert!(map.issues().is_empty());
+    assert_eq!(map.kind(0x202), ByteKind::Unused);
+}
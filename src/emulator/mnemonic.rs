@@ -0,0 +1,294 @@
+//! Mnemonics for the opcodes, as `Opcode`'s `Display` writes them.
+//!
+//! `Opcode`'s `FromStr` reads single instructions with plain numbers through here, and the assembler
+//! uses the same encoding with its labels and expressions filling in the numbers.
+
+use super::opcode::Opcode;
+use std::{error::Error, fmt};
+
+/// Some text from an instruction, with the 1-based column it started at.
+#[derive(Debug, Clone)]
+pub(crate) struct Token {
+    pub text: String,
+    pub column: usize,
+}
+
+/// A problem with an instruction, and the column it's at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MnemonicError {
+    pub column: usize,
+    pub message: String,
+}
+
+impl MnemonicError {
+    pub fn new<S: Into<String>>(column: usize, message: S) -> Self {
+        Self {
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for MnemonicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Column {}: {}", self.column, self.message)
+    }
+}
+
+impl Error for MnemonicError {}
+
+/// Parse a single instruction, where every operand is a register, a keyword or a plain number.
+/// `ld i, long` leaves its address out, like `Opcode::LongSetIdx`.
+pub(crate) fn parse_instruction(source: &str) -> Result<Opcode, MnemonicError> {
+    let (mnemonic, operands) = split(source)?;
+    if mnemonic.text.eq_ignore_ascii_case("ld")
+        && matches!(operands.as_slice(), [i, long]
+            if i.text.eq_ignore_ascii_case("i") && long.text.eq_ignore_ascii_case("long"))
+    {
+        return Ok(Opcode::LongSetIdx);
+    }
+    match encode(&mnemonic, &operands, number)? {
+        (opcode, None) => Ok(opcode),
+        (_, Some(_)) => Err(MnemonicError::new(
+            operands[1].column,
+            "The address for `ld i, long` goes in the next two bytes",
+        )),
+    }
+}
+
+/// Pick the opcode a mnemonic and its operands stand for, with `evaluate` working out numeric operands.
+/// Also returns the extra address word that follows `ld i, long`.
+pub(crate) fn encode<F>(
+    mnemonic: &Token,
+    operands: &[Token],
+    evaluate: F,
+) -> Result<(Opcode, Option<u16>), MnemonicError>
+where
+    F: Fn(&Token) -> Result<i64, MnemonicError>,
+{
+    use Opcode::*;
+    let name = mnemonic.text.to_ascii_lowercase();
+    let arity: &[usize] = match name.as_str() {
+        "cls" | "ret" | "scr" | "scl" | "exit" | "low" | "high" | "audio" => &[0],
+        "scd" | "scu" | "sys" | "call" | "skp" | "sknp" | "plane" | "pitch" => &[1],
+        "se" | "sne" | "save" | "load" | "add" | "or" | "and" | "xor" | "sub" | "subn" | "rnd"
+        | "ld" => &[2],
+        "drw" => &[3],
+        // A computed jump names v0, and the second shift register is only used under some quirks
+        "jp" | "shr" | "shl" => &[1, 2],
+        _ => {
+            return Err(MnemonicError::new(
+                mnemonic.column,
+                format!("Unknown instruction `{}`", mnemonic.text),
+            ))
+        }
+    };
+    if !arity.contains(&operands.len()) {
+        let expected: Vec<String> = arity.iter().map(usize::to_string).collect();
+        return Err(MnemonicError::new(
+            mnemonic.column,
+            format!(
+                "`{}` takes {} operands, found {}",
+                name,
+                expected.join(" or "),
+                operands.len()
+            ),
+        ));
+    }
+
+    // Operand parsers
+    let reg = |i: usize| {
+        register(&operands[i]).ok_or_else(|| {
+            MnemonicError::new(
+                operands[i].column,
+                format!("Expected a register, found `{}`", operands[i].text),
+            )
+        })
+    };
+    let value =
+        |i: usize, min: i64, max: i64| check_range(&operands[i], evaluate(&operands[i])?, min, max);
+    let byte = |i: usize| value(i, -128, 0xFF).map(|n| n as u8);
+    let nibble = |i: usize| value(i, 0, 0xF).map(|n| n as u8);
+    let addr = |i: usize| value(i, 0, 0xFFF).map(|n| n as u16);
+    let keyword = |i: usize| operands[i].text.to_ascii_lowercase();
+
+    let opcode = match name.as_str() {
+        "cls" => ClearScreen,
+        "ret" => Return,
+        "scr" => ScrollRight,
+        "scl" => ScrollLeft,
+        "exit" => Exit,
+        "low" => LowRes,
+        "high" => HighRes,
+        "audio" => LoadAudio,
+        "scd" => ScrollDown(nibble(0)?),
+        "scu" => ScrollUp(nibble(0)?),
+        "sys" => MachineCall(addr(0)?),
+        "call" => Call(addr(0)?),
+        "jp" if operands.len() == 1 => Jump(addr(0)?),
+        "jp" => {
+            if register(&operands[0]) != Some(0) {
+                return Err(MnemonicError::new(
+                    operands[0].column,
+                    "Computed jumps can only use v0",
+                ));
+            }
+            JumpTo(addr(1)?)
+        }
+        "se" => match register(&operands[1]) {
+            Some(y) => SkipIfMatchReg(reg(0)?, y),
+            None => SkipIfEqVal(reg(0)?, byte(1)?),
+        },
+        "sne" => match register(&operands[1]) {
+            Some(y) => SkipIfMismatchReg(reg(0)?, y),
+            None => SkipIfNotEqVal(reg(0)?, byte(1)?),
+        },
+        "save" => SaveRange(reg(0)?, reg(1)?),
+        "load" => LoadRange(reg(0)?, reg(1)?),
+        "add" if keyword(0) == "i" => IncrementIdx(reg(1)?),
+        "add" => match register(&operands[1]) {
+            Some(y) => AddAssign(reg(0)?, y),
+            None => Add(reg(0)?, byte(1)?),
+        },
+        "or" => AssignOr(reg(0)?, reg(1)?),
+        "and" => AssignAnd(reg(0)?, reg(1)?),
+        "xor" => AssignXor(reg(0)?, reg(1)?),
+        "sub" => SubAssign(reg(0)?, reg(1)?),
+        "subn" => FlippedSubAssign(reg(0)?, reg(1)?),
+        "shr" => ShiftRight(reg(0)?, reg(operands.len() - 1)?),
+        "shl" => ShiftLeft(reg(0)?, reg(operands.len() - 1)?),
+        "rnd" => Rand(reg(0)?, byte(1)?),
+        "drw" => Draw(reg(0)?, reg(1)?, nibble(2)?),
+        "skp" => SkipIfPressed(reg(0)?),
+        "sknp" => SkipIfNotPressed(reg(0)?),
+        "plane" => SelectPlanes(nibble(0)?),
+        "pitch" => SetPitch(reg(0)?),
+        // Everything else is some flavour of `ld`
+        _ => match (register(&operands[0]), keyword(0).as_str()) {
+            (Some(x), _) => match (register(&operands[1]), keyword(1).as_str()) {
+                (Some(y), _) => Assign(x, y),
+                (None, "dt") => StoreDelay(x),
+                (None, "k") => WaitKey(x),
+                (None, "[i]") => FillRegisters(x),
+                (None, "r") => LoadFlags(x),
+                (None, _) => SetRegister(x, byte(1)?),
+            },
+            (None, "i") => match long_operand(&operands[1]) {
+                Some(target) => {
+                    let n = check_range(&target, evaluate(&target)?, 0, 0xFFFF)?;
+                    return Ok((LongSetIdx, Some(n as u16)));
+                }
+                None => SetIdx(addr(1)?),
+            },
+            (None, "dt") => SetDelay(reg(1)?),
+            (None, "st") => SetSound(reg(1)?),
+            (None, "f") => NewSprite(reg(1)?),
+            (None, "hf") => NewBigSprite(reg(1)?),
+            (None, "b") => BCD(reg(1)?),
+            (None, "[i]") => DumpRegisters(reg(1)?),
+            (None, "r") => SaveFlags(reg(1)?),
+            (None, _) => {
+                return Err(MnemonicError::new(
+                    operands[0].column,
+                    format!("Can't load into `{}`", operands[0].text),
+                ))
+            }
+        },
+    };
+    Ok((opcode, None))
+}
+
+/// Check a number fits in an operand.
+pub(crate) fn check_range(token: &Token, n: i64, min: i64, max: i64) -> Result<i64, MnemonicError> {
+    if n < min || n > max {
+        Err(MnemonicError::new(
+            token.column,
+            format!(
+                "`{}` is out of range, expected {} to {:#X}",
+                token.text, min, max
+            ),
+        ))
+    } else {
+        Ok(n)
+    }
+}
+
+/// Parse a plain number, decimal, `0x` hex or `0b` binary.
+pub(crate) fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()
+    } else if !text.is_empty() && text.chars().all(|c| c.is_ascii_digit()) {
+        text.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// A numeric operand with nothing to look up.
+fn number(token: &Token) -> Result<i64, MnemonicError> {
+    parse_number(&token.text).ok_or_else(|| {
+        MnemonicError::new(
+            token.column,
+            format!("Expected a number, found `{}`", token.text),
+        )
+    })
+}
+
+/// Split an instruction into its mnemonic and comma separated operands.
+fn split(source: &str) -> Result<(Token, Vec<Token>), MnemonicError> {
+    let start = source.len() - source.trim_start().len();
+    let rest = &source[start..];
+    let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    if len == 0 {
+        return Err(MnemonicError::new(1, "Expected an instruction"));
+    }
+    let mnemonic = Token {
+        text: rest[..len].to_string(),
+        column: start + 1,
+    };
+    let mut operands = Vec::new();
+    let mut pos = start + len;
+    if !source[pos..].trim().is_empty() {
+        for piece in source[pos..].split(',') {
+            let trimmed = piece.trim();
+            let column = pos + piece.len() - piece.trim_start().len() + 1;
+            if trimmed.is_empty() {
+                return Err(MnemonicError::new(column, "Missing operand"));
+            }
+            operands.push(Token {
+                text: trimmed.to_string(),
+                column,
+            });
+            pos += piece.len() + 1;
+        }
+    }
+    Ok((mnemonic, operands))
+}
+
+/// The address part of a `long NNNN` operand.
+pub(crate) fn long_operand(token: &Token) -> Option<Token> {
+    let lower = token.text.to_ascii_lowercase();
+    let rest = lower.strip_prefix("long")?;
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let offset = token.text.len() - rest.trim_start().len();
+    Some(Token {
+        text: token.text[offset..].to_string(),
+        column: token.column + offset,
+    })
+}
+
+/// Parse `vX`.
+pub(crate) fn register(token: &Token) -> Option<u8> {
+    let mut chars = token.text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => {
+            digit.to_digit(16).map(|d| d as u8)
+        }
+        _ => None,
+    }
+}
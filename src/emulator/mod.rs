@@ -1,8 +1,18 @@
 //! The Chip8 machine itself
 
+mod capture;
 mod context;
+mod debugger;
+mod error;
+mod keymap;
 mod machine;
+pub(crate) mod mnemonic;
 mod opcode;
+mod palette;
+mod profile;
+mod quirks;
+mod render;
+mod trace;
 
 #[cfg(feature = "sdl")]
 pub use context::SdlContext;
@@ -10,5 +20,32 @@ pub use context::SdlContext;
 #[cfg(feature = "wasm")]
 pub use context::wasm;
 
-pub use machine::Machine;
+pub use capture::{encode_pbm, save_screenshot};
+#[cfg(feature = "capture")]
+pub use capture::{encode_png, GifRecorder};
+pub use context::{
+    Command, Context, HeadlessContext, KeyEvent, Movie, RecordingContext, ReplayContext,
+};
+pub use debugger::{
+    Comparison, Condition, DebugCommand, Debugger, StopReason, WatchKind, DEBUG_HELP,
+    DEFAULT_RUN_LIMIT,
+};
+pub use error::Chip8Error;
+#[cfg(feature = "keymap")]
+pub use keymap::KeyConfig;
+pub use keymap::{key_name_from_code, KeyMap};
+pub use machine::{
+    write_rgba, Access, AccessMap, AudioPattern, ByteKind, ByteUse, Machine, MachineCallHandler,
+    MachineCallState, MachineCalls, MemoryAccess, MemoryIssue, Rewind, SaveState, Scheduler,
+    Screen, VipRoutines, CYCLES_PER_SECOND, DEFAULT_REWIND_SECONDS, HIRES_PIXEL_COLS,
+    HIRES_PIXEL_ROWS, NUM_KEYS, PIXEL_COLS, PIXEL_ROWS, TIMER_HZ, TURBO_FACTOR,
+    VIP_HIRES_PIXEL_ROWS,
+};
 pub use opcode::*;
+pub use palette::{Palette, NUM_COLOURS, PALETTES};
+pub use profile::{Profiler, SubroutineProfile};
+pub use quirks::{IndexIncrement, Quirks};
+pub use render::{Effect, RenderOptions, Renderer, DEFAULT_PERSISTENCE};
+pub use trace::{
+    parse_address_range, RegisterChange, TraceFilter, TraceFormat, TraceRecord, Tracer,
+};
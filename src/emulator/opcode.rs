@@ -2,8 +2,9 @@
 //!
 //! Largely written by staring at [the Chip8 Wikipedia article](https://en.wikipedia.org/wiki/CHIP-8#Opcode_table) for a while.
 
+use super::mnemonic::parse_instruction;
 use anyhow::{anyhow, Result};
-use std::{convert::TryFrom, fmt};
+use std::{convert::TryFrom, fmt, str::FromStr};
 
 /// Wrapper struct with some helper methods for working with u16 values
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -99,8 +100,14 @@ impl From<RawOpcode> for u16 {
 }
 
 impl fmt::Display for RawOpcode {
+    /// Four hex digits, like `0xD123`.
+    /// ```
+    /// # use chip8::RawOpcode;
+    /// # use pretty_assertions::assert_eq;
+    /// assert_eq!(RawOpcode::from(0x00E0).to_string(), "0x00E0");
+    /// ```
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{:#06X}", self.0)
     }
 }
 
@@ -114,6 +121,20 @@ pub enum Opcode {
     ClearScreen,
     /// 00EE - Return from a subroutine.
     Return,
+    /// 00CN - Scroll the screen down N pixels.  Carries N.  SUPER-CHIP.
+    ScrollDown(u8),
+    /// 00DN - Scroll the screen up N pixels.  Carries N.  XO-CHIP.
+    ScrollUp(u8),
+    /// 00FB - Scroll the screen right 4 pixels.  SUPER-CHIP.
+    ScrollRight,
+    /// 00FC - Scroll the screen left 4 pixels.  SUPER-CHIP.
+    ScrollLeft,
+    /// 00FD - Exit the interpreter.  SUPER-CHIP.
+    Exit,
+    /// 00FE - Switch to 64x32 low resolution and clear the screen.  SUPER-CHIP.
+    LowRes,
+    /// 00FF - Switch to 128x64 high resolution and clear the screen.  SUPER-CHIP.
+    HighRes,
     /// 1NNN - Jump to addr NNN.
     Jump(u16),
     /// 2NNN - Call subroutine at NNN.
@@ -124,6 +145,12 @@ pub enum Opcode {
     SkipIfNotEqVal(u8, u8),
     /// 5XY0 - Skip next if VX == VY.  Carries (X, Y).
     SkipIfMatchReg(u8, u8),
+    /// 5XY2 - Store VX to VY inclusive in memory starting at idx, which doesn't change.
+    /// If X > Y the registers are stored in reverse order.  Carries (X, Y).  XO-CHIP.
+    SaveRange(u8, u8),
+    /// 5XY3 - Fill VX to VY inclusive from memory starting at idx, which doesn't change.
+    /// If X > Y the registers are filled in reverse order.  Carries (X, Y).  XO-CHIP.
+    LoadRange(u8, u8),
     /// 6XNN - Set VX to NN.  Carries (X, NN).
     SetRegister(u8, u8),
     /// 7XNN - Add NN to VX, does not change carry flag.  Carries (X, NN).
@@ -140,29 +167,40 @@ pub enum Opcode {
     AddAssign(u8, u8),
     /// 8XY5 - VX -= VY.  Set VF to 0 if there's a borrow and 1 if there isn't.  Carries (X, Y).
     SubAssign(u8, u8),
-    /// 8XY6 - Store least significant bit of VX to VF, shift VX right 1.  Carries X, Y is unused.
-    ShiftRight(u8),
+    /// 8XY6 - Store least significant bit of VX to VF, shift VX right 1.  Carries (X, Y).
+    /// Depending on `Quirks`, VY is shifted into VX instead.
+    ShiftRight(u8, u8),
     /// 8XY7 - Set VX=VY-VX.  Set VF to 0 if there's a borrow and one if there isn't.  Carries (X, Y).
     FlippedSubAssign(u8, u8),
-    /// 8XYE - Store most significant bit of VX to VF, shift VX left 1.  Carries X, Y is unusued.
-    ShiftLeft(u8),
+    /// 8XYE - Store most significant bit of VX to VF, shift VX left 1.  Carries (X, Y).
+    /// Depending on `Quirks`, VY is shifted into VX instead.
+    ShiftLeft(u8, u8),
     /// 9XY0 - Skip next if VX != VY.  Carries (X, Y).
     SkipIfMismatchReg(u8, u8),
     /// ANNN - Set idx pointer to address NNN.  Carries NNN.
     SetIdx(u16),
     /// BNNN - Jump to address NNN plus V0.  Carries NNN.
+    /// Depending on `Quirks`, this is BXNN - jump to XNN plus VX.
     JumpTo(u16),
     /// CXNN - Pick a random number 0-255 as r, set VX to (r & NN).  Carries (X, NN).
     Rand(u8, u8),
     /// DXYN - Draw sprite at (VX, VY).  Width 8px, height Npx.
     /// Each row of 8px is read starting from location at the `idx` pointer, which doesn't change here.
     /// VF is set to 1 if any pixels are flipped from set to unset when the sprite is drawn, and 0 if not.
+    /// DXY0 draws a 16x16 sprite instead, reading two bytes per row.  SUPER-CHIP.
     /// Carries (X, Y, N).
     Draw(u8, u8, u8),
     /// EX9E - Skip next if key stored in VX is pressed.  Carries X.
     SkipIfPressed(u8),
     /// EXA1 - Skip next if key stored in VX is not pressed.  Carries X.
     SkipIfNotPressed(u8),
+    /// F000 NNNN - Set idx pointer to the 16-bit address NNNN stored in the following two bytes.
+    /// This is the only four byte instruction, so skips have to jump over all of it.  XO-CHIP.
+    LongSetIdx,
+    /// FN01 - Select the bitplanes that drawing, clearing and scrolling apply to.  Carries N.  XO-CHIP.
+    SelectPlanes(u8),
+    /// F002 - Fill the audio pattern buffer with the 16 bytes starting at idx.  XO-CHIP.
+    LoadAudio,
     /// FX07 - Set VX to the value of the delay timer.  Carries X.
     StoreDelay(u8),
     /// FX0A - Block until keypress, store key pressed to VX.  Carries X.
@@ -177,14 +215,24 @@ pub enum Opcode {
     /// Hex digits 0-F are all stored as 4x5 glyphs.
     /// Carries X.
     NewSprite(u8),
+    /// FX30 - Set index pointer to the big 8x10 sprite for the character in VX.  Carries X.  SUPER-CHIP.
+    NewBigSprite(u8),
+    /// FX3A - Set the audio pattern playback pitch to VX.  Carries X.  XO-CHIP.
+    SetPitch(u8),
     /// FX33 - Store the binary-coded decimal representation of VX starting at the index pointer.
     /// Hundreds digit at `tape[idx]`, tens to `tape[idx+1]`, ones to `tape[idx+2]`.
     /// Carries X.
     BCD(u8),
-    /// FX55 - Store V0 to VX inclusive in memory starting at idx.  Leave idx itself unmodified.  Carries X.
+    /// FX55 - Store V0 to VX inclusive in memory starting at idx.  Carries X.
+    /// Whether idx is advanced afterwards depends on `Quirks`.
     DumpRegisters(u8),
-    /// FX65 - Fill V0 to VX inclusive in memory starting from idx.  Leave idx itself unmodified.  Carries X.
+    /// FX65 - Fill V0 to VX inclusive in memory starting from idx.  Carries X.
+    /// Whether idx is advanced afterwards depends on `Quirks`.
     FillRegisters(u8),
+    /// FX75 - Store V0 to VX inclusive in the RPL user flags.  Carries X.  SUPER-CHIP.
+    SaveFlags(u8),
+    /// FX85 - Fill V0 to VX inclusive from the RPL user flags.  Carries X.  SUPER-CHIP.
+    LoadFlags(u8),
 }
 
 impl Opcode {
@@ -195,11 +243,20 @@ impl Opcode {
     /// assert_eq!(Opcode::new(0x0A, 0xBC).unwrap(), Opcode::MachineCall(0xABC));
     /// assert_eq!(Opcode::new(0x00, 0xE0).unwrap(), Opcode::ClearScreen);
     /// assert_eq!(Opcode::new(0x00, 0xEE).unwrap(), Opcode::Return);
+    /// assert_eq!(Opcode::new(0x00, 0xC4).unwrap(), Opcode::ScrollDown(4));
+    /// assert_eq!(Opcode::new(0x00, 0xD4).unwrap(), Opcode::ScrollUp(4));
+    /// assert_eq!(Opcode::new(0x00, 0xFB).unwrap(), Opcode::ScrollRight);
+    /// assert_eq!(Opcode::new(0x00, 0xFC).unwrap(), Opcode::ScrollLeft);
+    /// assert_eq!(Opcode::new(0x00, 0xFD).unwrap(), Opcode::Exit);
+    /// assert_eq!(Opcode::new(0x00, 0xFE).unwrap(), Opcode::LowRes);
+    /// assert_eq!(Opcode::new(0x00, 0xFF).unwrap(), Opcode::HighRes);
     /// assert_eq!(Opcode::new(0x1F, 0xFF).unwrap(), Opcode::Jump(0xFFF));
     /// assert_eq!(Opcode::new(0x2F, 0xFF).unwrap(), Opcode::Call(0xFFF));
     /// assert_eq!(Opcode::new(0x32, 0xFF).unwrap(), Opcode::SkipIfEqVal(2, 0xFF));
     /// assert_eq!(Opcode::new(0x42, 0xFF).unwrap(), Opcode::SkipIfNotEqVal(2, 0xFF));
     /// assert_eq!(Opcode::new(0x52, 0x30).unwrap(), Opcode::SkipIfMatchReg(2, 3));
+    /// assert_eq!(Opcode::new(0x52, 0x32).unwrap(), Opcode::SaveRange(2, 3));
+    /// assert_eq!(Opcode::new(0x52, 0x33).unwrap(), Opcode::LoadRange(2, 3));
     /// assert_eq!(Opcode::new(0x62, 0xBC).unwrap(), Opcode::SetRegister(2, 0xBC));
     /// assert_eq!(Opcode::new(0x79, 0xED).unwrap(), Opcode::Add(9,0xED));
     /// assert_eq!(Opcode::new(0x8B, 0xC0).unwrap(), Opcode::Assign(0xB,0xC));
@@ -208,9 +265,9 @@ impl Opcode {
     /// assert_eq!(Opcode::new(0x8B, 0xC3).unwrap(), Opcode::AssignXor(0xB,0xC));
     /// assert_eq!(Opcode::new(0x8B, 0xC4).unwrap(), Opcode::AddAssign(0xB,0xC));
     /// assert_eq!(Opcode::new(0x8B, 0xC5).unwrap(), Opcode::SubAssign(0xB,0xC));
-    /// assert_eq!(Opcode::new(0x8B, 0xC6).unwrap(), Opcode::ShiftRight(0xB));
+    /// assert_eq!(Opcode::new(0x8B, 0xC6).unwrap(), Opcode::ShiftRight(0xB, 0xC));
     /// assert_eq!(Opcode::new(0x8B, 0xC7).unwrap(), Opcode::FlippedSubAssign(0xB,0xC));
-    /// assert_eq!(Opcode::new(0x8B, 0xCE).unwrap(), Opcode::ShiftLeft(0xB));
+    /// assert_eq!(Opcode::new(0x8B, 0xCE).unwrap(), Opcode::ShiftLeft(0xB, 0xC));
     /// assert_eq!(Opcode::new(0x9B, 0xC0).unwrap(), Opcode::SkipIfMismatchReg(0xB, 0xC));
     /// assert_eq!(Opcode::new(0xAF, 0xAB).unwrap(), Opcode::SetIdx(0xFAB));
     /// assert_eq!(Opcode::new(0xBF, 0xAB).unwrap(), Opcode::JumpTo(0xFAB));
@@ -218,24 +275,219 @@ impl Opcode {
     /// assert_eq!(Opcode::new(0xD0, 0x03).unwrap(), Opcode::Draw(0, 0, 3));
     /// assert_eq!(Opcode::new(0xEB, 0x9E).unwrap(), Opcode::SkipIfPressed(0xB));
     /// assert_eq!(Opcode::new(0xEB, 0xA1).unwrap(), Opcode::SkipIfNotPressed(0xB));
+    /// assert_eq!(Opcode::new(0xF0, 0x00).unwrap(), Opcode::LongSetIdx);
+    /// assert_eq!(Opcode::new(0xF3, 0x01).unwrap(), Opcode::SelectPlanes(3));
+    /// assert_eq!(Opcode::new(0xF0, 0x02).unwrap(), Opcode::LoadAudio);
     /// assert_eq!(Opcode::new(0xFB, 0x07).unwrap(), Opcode::StoreDelay(0xB));
     /// assert_eq!(Opcode::new(0xFB, 0x0A).unwrap(), Opcode::WaitKey(0xB));
     /// assert_eq!(Opcode::new(0xFB, 0x15).unwrap(), Opcode::SetDelay(0xB));
     /// assert_eq!(Opcode::new(0xFB, 0x18).unwrap(), Opcode::SetSound(0xB));
     /// assert_eq!(Opcode::new(0xFB, 0x1E).unwrap(), Opcode::IncrementIdx(0xB));
     /// assert_eq!(Opcode::new(0xFB, 0x29).unwrap(), Opcode::NewSprite(0xB));
+    /// assert_eq!(Opcode::new(0xFB, 0x30).unwrap(), Opcode::NewBigSprite(0xB));
+    /// assert_eq!(Opcode::new(0xFB, 0x3A).unwrap(), Opcode::SetPitch(0xB));
     /// assert_eq!(Opcode::new(0xFF, 0x33).unwrap(), Opcode::BCD(0xF));
     /// assert_eq!(Opcode::new(0xFF, 0x55).unwrap(), Opcode::DumpRegisters(0xF));
     /// assert_eq!(Opcode::new(0xFF, 0x65).unwrap(), Opcode::FillRegisters(0xF));
+    /// assert_eq!(Opcode::new(0xF7, 0x75).unwrap(), Opcode::SaveFlags(7));
+    /// assert_eq!(Opcode::new(0xF7, 0x85).unwrap(), Opcode::LoadFlags(7));
     /// ```
     /// Will pass up the raw opcode in an error if it doesn't match the table.
-    /// ```should_panic
+    /// ```
     /// # use chip8::*;
     /// # use pretty_assertions::assert_eq;
-    /// assert_eq!(Opcode::new(0x8B, 0xCF).err().unwrap().to_string(), "Invalid Code: 0x8BCF".to_string());
+    /// assert_eq!(Opcode::new(0x8B, 0xCF).err().unwrap().to_string(), "Invalid opcode 0x8BCF".to_string());
     /// ```
     pub fn new(first: u8, second: u8) -> Result<Self> {
-        Ok(Self::try_from(RawOpcode::new(first, second))?)
+        Self::try_from(RawOpcode::new(first, second))
+    }
+
+    /// Which group the instruction belongs to.
+    /// ```
+    /// # use chip8::{Opcode, OpcodeClass};
+    /// # use pretty_assertions::assert_eq;
+    /// assert_eq!(Opcode::Draw(0, 1, 5).class(), OpcodeClass::Display);
+    /// assert_eq!(Opcode::SkipIfPressed(2).class(), OpcodeClass::Input);
+    /// ```
+    pub fn class(&self) -> OpcodeClass {
+        use Opcode::*;
+        use OpcodeClass::*;
+        match self {
+            MachineCall(_) | Return | Exit | Jump(_) | Call(_) | JumpTo(_) => Flow,
+            SkipIfEqVal(..) | SkipIfNotEqVal(..) | SkipIfMatchReg(..) | SkipIfMismatchReg(..) => {
+                Skip
+            }
+            SetRegister(..) | Add(..) | Assign(..) | AssignOr(..) | AssignAnd(..)
+            | AssignXor(..) | AddAssign(..) | SubAssign(..) | ShiftRight(..)
+            | FlippedSubAssign(..) | ShiftLeft(..) | Rand(..) => Register,
+            SaveRange(..) | LoadRange(..) | SetIdx(_) | LongSetIdx | IncrementIdx(_)
+            | NewSprite(_) | NewBigSprite(_) | BCD(_) | DumpRegisters(_) | FillRegisters(_)
+            | SaveFlags(_) | LoadFlags(_) => Memory,
+            ClearScreen | ScrollDown(_) | ScrollUp(_) | ScrollRight | ScrollLeft | LowRes
+            | HighRes | Draw(..) | SelectPlanes(_) => Display,
+            SkipIfPressed(_) | SkipIfNotPressed(_) | WaitKey(_) => Input,
+            StoreDelay(_) | SetDelay(_) => Timer,
+            SetSound(_) | LoadAudio | SetPitch(_) => Sound,
+        }
+    }
+}
+
+impl fmt::Display for Opcode {
+    /// Write the instruction as an assembly mnemonic, mostly following Cowgod's reference.
+    /// Addresses print as plain hex, it's up to the caller to swap in labels.
+    /// ```
+    /// # use chip8::Opcode;
+    /// # use pretty_assertions::assert_eq;
+    /// assert_eq!(Opcode::SetRegister(1, 0x20).to_string(), "ld v1, 0x20");
+    /// assert_eq!(Opcode::Draw(0, 1, 5).to_string(), "drw v0, v1, 5");
+    /// assert_eq!(Opcode::Jump(0x23A).to_string(), "jp 0x23A");
+    /// assert_eq!(Opcode::FillRegisters(0xA).to_string(), "ld va, [i]");
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Opcode::*;
+        match *self {
+            MachineCall(addr) => write!(f, "sys {:#05X}", addr),
+            ClearScreen => write!(f, "cls"),
+            Return => write!(f, "ret"),
+            ScrollDown(n) => write!(f, "scd {}", n),
+            ScrollUp(n) => write!(f, "scu {}", n),
+            ScrollRight => write!(f, "scr"),
+            ScrollLeft => write!(f, "scl"),
+            Exit => write!(f, "exit"),
+            LowRes => write!(f, "low"),
+            HighRes => write!(f, "high"),
+            Jump(addr) => write!(f, "jp {:#05X}", addr),
+            Call(addr) => write!(f, "call {:#05X}", addr),
+            SkipIfEqVal(x, nn) => write!(f, "se v{:x}, {:#04X}", x, nn),
+            SkipIfNotEqVal(x, nn) => write!(f, "sne v{:x}, {:#04X}", x, nn),
+            SkipIfMatchReg(x, y) => write!(f, "se v{:x}, v{:x}", x, y),
+            SaveRange(x, y) => write!(f, "save v{:x}, v{:x}", x, y),
+            LoadRange(x, y) => write!(f, "load v{:x}, v{:x}", x, y),
+            SetRegister(x, nn) => write!(f, "ld v{:x}, {:#04X}", x, nn),
+            Add(x, nn) => write!(f, "add v{:x}, {:#04X}", x, nn),
+            Assign(x, y) => write!(f, "ld v{:x}, v{:x}", x, y),
+            AssignOr(x, y) => write!(f, "or v{:x}, v{:x}", x, y),
+            AssignAnd(x, y) => write!(f, "and v{:x}, v{:x}", x, y),
+            AssignXor(x, y) => write!(f, "xor v{:x}, v{:x}", x, y),
+            AddAssign(x, y) => write!(f, "add v{:x}, v{:x}", x, y),
+            SubAssign(x, y) => write!(f, "sub v{:x}, v{:x}", x, y),
+            ShiftRight(x, y) => write!(f, "shr v{:x}, v{:x}", x, y),
+            FlippedSubAssign(x, y) => write!(f, "subn v{:x}, v{:x}", x, y),
+            ShiftLeft(x, y) => write!(f, "shl v{:x}, v{:x}", x, y),
+            SkipIfMismatchReg(x, y) => write!(f, "sne v{:x}, v{:x}", x, y),
+            SetIdx(addr) => write!(f, "ld i, {:#05X}", addr),
+            JumpTo(addr) => write!(f, "jp v0, {:#05X}", addr),
+            Rand(x, nn) => write!(f, "rnd v{:x}, {:#04X}", x, nn),
+            Draw(x, y, n) => write!(f, "drw v{:x}, v{:x}, {}", x, y, n),
+            SkipIfPressed(x) => write!(f, "skp v{:x}", x),
+            SkipIfNotPressed(x) => write!(f, "sknp v{:x}", x),
+            LongSetIdx => write!(f, "ld i, long"),
+            SelectPlanes(n) => write!(f, "plane {}", n),
+            LoadAudio => write!(f, "audio"),
+            StoreDelay(x) => write!(f, "ld v{:x}, dt", x),
+            WaitKey(x) => write!(f, "ld v{:x}, k", x),
+            SetDelay(x) => write!(f, "ld dt, v{:x}", x),
+            SetSound(x) => write!(f, "ld st, v{:x}", x),
+            IncrementIdx(x) => write!(f, "add i, v{:x}", x),
+            NewSprite(x) => write!(f, "ld f, v{:x}", x),
+            NewBigSprite(x) => write!(f, "ld hf, v{:x}", x),
+            SetPitch(x) => write!(f, "pitch v{:x}", x),
+            BCD(x) => write!(f, "ld b, v{:x}", x),
+            DumpRegisters(x) => write!(f, "ld [i], v{:x}", x),
+            FillRegisters(x) => write!(f, "ld v{:x}, [i]", x),
+            SaveFlags(x) => write!(f, "ld r, v{:x}", x),
+            LoadFlags(x) => write!(f, "ld v{:x}, r", x),
+        }
+    }
+}
+
+/// Rough groups of instructions, for picking out the ones of interest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OpcodeClass {
+    /// Jumps, calls, returns and exiting
+    Flow,
+    /// Skips on a register or value
+    Skip,
+    /// Setting and doing arithmetic on registers
+    Register,
+    /// Anything to do with `I` or reading and writing memory
+    Memory,
+    /// Drawing, clearing, scrolling and switching resolution or planes
+    Display,
+    /// Reading the keypad
+    Input,
+    /// The delay timer
+    Timer,
+    /// The sound timer and audio pattern
+    Sound,
+}
+
+impl OpcodeClass {
+    pub const ALL: [OpcodeClass; 8] = [
+        Self::Flow,
+        Self::Skip,
+        Self::Register,
+        Self::Memory,
+        Self::Display,
+        Self::Input,
+        Self::Timer,
+        Self::Sound,
+    ];
+
+    fn name(self) -> &'static str {
+        use OpcodeClass::*;
+        match self {
+            Flow => "flow",
+            Skip => "skip",
+            Register => "register",
+            Memory => "memory",
+            Display => "display",
+            Input => "input",
+            Timer => "timer",
+            Sound => "sound",
+        }
+    }
+}
+
+impl fmt::Display for OpcodeClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for OpcodeClass {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|class| class.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::ALL.iter().map(|class| class.name()).collect();
+                anyhow!(
+                    "Unknown opcode class {} - expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+impl FromStr for Opcode {
+    type Err = anyhow::Error;
+
+    /// Read back a mnemonic written by `Display`, the same way the assembler reads it.
+    /// Numbers can be decimal, `0x` hex or `0b` binary.
+    /// ```
+    /// # use chip8::Opcode;
+    /// # use pretty_assertions::assert_eq;
+    /// assert_eq!("drw v0, v1, 5".parse::<Opcode>().unwrap(), Opcode::Draw(0, 1, 5));
+    /// assert_eq!("LD I, 0x2EA".parse::<Opcode>().unwrap(), Opcode::SetIdx(0x2EA));
+    /// assert_eq!("ld i, long".parse::<Opcode>().unwrap(), Opcode::LongSetIdx);
+    /// assert!("drw v0, v1".parse::<Opcode>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(parse_instruction(s)?)
     }
 }
 
@@ -256,6 +508,13 @@ impl TryFrom<RawOpcode> for Opcode {
                 match addr {
                     0x0E0 => Ok(ClearScreen),
                     0x0EE => Ok(Return),
+                    0x0C0..=0x0CF => Ok(ScrollDown(raw.hex_digit_from_left(3))),
+                    0x0D0..=0x0DF => Ok(ScrollUp(raw.hex_digit_from_left(3))),
+                    0x0FB => Ok(ScrollRight),
+                    0x0FC => Ok(ScrollLeft),
+                    0x0FD => Ok(Exit),
+                    0x0FE => Ok(LowRes),
+                    0x0FF => Ok(HighRes),
                     _ => Ok(MachineCall(addr)),
                 }
             }
@@ -264,11 +523,12 @@ impl TryFrom<RawOpcode> for Opcode {
             3 => Ok(SkipIfEqVal(raw.hex_digit_from_left(1), raw.last_byte())),
             4 => Ok(SkipIfNotEqVal(raw.hex_digit_from_left(1), raw.last_byte())),
             5 => {
-                if raw.hex_digit_from_left(3) != 0 {
-                    error_val
-                } else {
-                    let (x, y) = raw.middle_digits();
-                    Ok(SkipIfMatchReg(x, y))
+                let (x, y) = raw.middle_digits();
+                match raw.hex_digit_from_left(3) {
+                    0 => Ok(SkipIfMatchReg(x, y)),
+                    2 => Ok(SaveRange(x, y)),
+                    3 => Ok(LoadRange(x, y)),
+                    _ => error_val,
                 }
             }
             6 => Ok(SetRegister(raw.hex_digit_from_left(1), raw.last_byte())),
@@ -286,9 +546,9 @@ impl TryFrom<RawOpcode> for Opcode {
                         3 => Ok(AssignXor(x, y)),
                         4 => Ok(AddAssign(x, y)),
                         5 => Ok(SubAssign(x, y)),
-                        6 => Ok(ShiftRight(x)),
+                        6 => Ok(ShiftRight(x, y)),
                         7 => Ok(FlippedSubAssign(x, y)),
-                        0xE => Ok(ShiftLeft(x)),
+                        0xE => Ok(ShiftLeft(x, y)),
                         _ => error_val,
                     }
                 }
@@ -321,15 +581,22 @@ impl TryFrom<RawOpcode> for Opcode {
             0xF => {
                 let r = raw.hex_digit_from_left(1);
                 match (raw.hex_digit_from_left(2), raw.hex_digit_from_left(3)) {
+                    (0, 0) if r == 0 => Ok(LongSetIdx),
+                    (0, 1) => Ok(SelectPlanes(r)),
+                    (0, 2) if r == 0 => Ok(LoadAudio),
                     (0, 7) => Ok(StoreDelay(r)),
                     (0, 0xA) => Ok(WaitKey(r)),
                     (1, 5) => Ok(SetDelay(r)),
                     (1, 8) => Ok(SetSound(r)),
                     (1, 0xE) => Ok(IncrementIdx(r)),
                     (2, 9) => Ok(NewSprite(r)),
+                    (3, 0) => Ok(NewBigSprite(r)),
                     (3, 3) => Ok(BCD(r)),
+                    (3, 0xA) => Ok(SetPitch(r)),
                     (5, 5) => Ok(DumpRegisters(r)),
                     (6, 5) => Ok(FillRegisters(r)),
+                    (7, 5) => Ok(SaveFlags(r)),
+                    (8, 5) => Ok(LoadFlags(r)),
                     (_, _) => error_val,
                 }
             }
@@ -338,9 +605,127 @@ impl TryFrom<RawOpcode> for Opcode {
     }
 }
 
+impl From<Opcode> for RawOpcode {
+    /// Encode an instruction, the reverse of `Opcode::try_from`.
+    /// Fields are masked to fit, so out of range values are truncated rather than spilling into other digits.
+    /// `LongSetIdx` only encodes the F000 prefix, its address goes in the next two bytes.
+    /// ```
+    /// # use chip8::{Opcode, RawOpcode};
+    /// # use pretty_assertions::assert_eq;
+    /// assert_eq!(u16::from(RawOpcode::from(Opcode::Draw(1, 2, 3))), 0xD123);
+    /// assert_eq!(u16::from(RawOpcode::from(Opcode::SetIdx(0x2EA))), 0xA2EA);
+    /// ```
+    fn from(opcode: Opcode) -> Self {
+        use Opcode::*;
+        let addr = |prefix: u16, nnn: u16| prefix << 12 | nnn & 0xFFF;
+        let xnn = |prefix: u16, x: u8, nn: u8| prefix << 12 | (x as u16 & 0xF) << 8 | nn as u16;
+        let xyn = |prefix: u16, x: u8, y: u8, n: u8| {
+            prefix << 12 | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4 | n as u16 & 0xF
+        };
+        let fx = |x: u8, suffix: u8| xnn(0xF, x, suffix);
+        Self(match opcode {
+            MachineCall(nnn) => addr(0, nnn),
+            ClearScreen => 0x00E0,
+            Return => 0x00EE,
+            ScrollDown(n) => 0x00C0 | n as u16 & 0xF,
+            ScrollUp(n) => 0x00D0 | n as u16 & 0xF,
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            LowRes => 0x00FE,
+            HighRes => 0x00FF,
+            Jump(nnn) => addr(1, nnn),
+            Call(nnn) => addr(2, nnn),
+            SkipIfEqVal(x, nn) => xnn(3, x, nn),
+            SkipIfNotEqVal(x, nn) => xnn(4, x, nn),
+            SkipIfMatchReg(x, y) => xyn(5, x, y, 0),
+            SaveRange(x, y) => xyn(5, x, y, 2),
+            LoadRange(x, y) => xyn(5, x, y, 3),
+            SetRegister(x, nn) => xnn(6, x, nn),
+            Add(x, nn) => xnn(7, x, nn),
+            Assign(x, y) => xyn(8, x, y, 0),
+            AssignOr(x, y) => xyn(8, x, y, 1),
+            AssignAnd(x, y) => xyn(8, x, y, 2),
+            AssignXor(x, y) => xyn(8, x, y, 3),
+            AddAssign(x, y) => xyn(8, x, y, 4),
+            SubAssign(x, y) => xyn(8, x, y, 5),
+            ShiftRight(x, y) => xyn(8, x, y, 6),
+            FlippedSubAssign(x, y) => xyn(8, x, y, 7),
+            ShiftLeft(x, y) => xyn(8, x, y, 0xE),
+            SkipIfMismatchReg(x, y) => xyn(9, x, y, 0),
+            SetIdx(nnn) => addr(0xA, nnn),
+            JumpTo(nnn) => addr(0xB, nnn),
+            Rand(x, nn) => xnn(0xC, x, nn),
+            Draw(x, y, n) => xyn(0xD, x, y, n),
+            SkipIfPressed(x) => xnn(0xE, x, 0x9E),
+            SkipIfNotPressed(x) => xnn(0xE, x, 0xA1),
+            LongSetIdx => 0xF000,
+            SelectPlanes(n) => fx(n, 0x01),
+            LoadAudio => 0xF002,
+            StoreDelay(x) => fx(x, 0x07),
+            WaitKey(x) => fx(x, 0x0A),
+            SetDelay(x) => fx(x, 0x15),
+            SetSound(x) => fx(x, 0x18),
+            IncrementIdx(x) => fx(x, 0x1E),
+            NewSprite(x) => fx(x, 0x29),
+            NewBigSprite(x) => fx(x, 0x30),
+            BCD(x) => fx(x, 0x33),
+            SetPitch(x) => fx(x, 0x3A),
+            DumpRegisters(x) => fx(x, 0x55),
+            FillRegisters(x) => fx(x, 0x65),
+            SaveFlags(x) => fx(x, 0x75),
+            LoadFlags(x) => fx(x, 0x85),
+        })
+    }
+}
+
 impl TryFrom<u16> for Opcode {
     type Error = anyhow::Error;
     fn try_from(x: u16) -> Result<Self, Self::Error> {
-        Ok(Self::try_from(RawOpcode::try_from(x)?)?)
+        Self::try_from(RawOpcode::from(x))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// Every 16-bit word is small enough to try, so check each one rather than sampling.
+    #[test]
+    fn test_every_opcode_round_trips() {
+        for word in 0..=u16::MAX {
+            let op = match Opcode::try_from(word) {
+                Ok(op) => op,
+                Err(_) => continue,
+            };
+            assert_eq!(u16::from(RawOpcode::from(op)), word, "{}", op);
+            assert_eq!(Opcode::try_from(RawOpcode::from(op)).unwrap(), op);
+            assert_eq!(
+                op.to_string().parse::<Opcode>().unwrap(),
+                op,
+                "{:#06X}",
+                word
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |s: &str| s.parse::<Opcode>().unwrap_err().to_string();
+        assert_eq!(
+            error("drw v0, vz, 5"),
+            "Column 9: Expected a register, found `vz`"
+        );
+        assert_eq!(
+            error("loop: jp loop"),
+            "Column 1: Unknown instruction `loop:`"
+        );
+        assert_eq!(error("db 1"), "Column 1: Unknown instruction `db`");
+        assert_eq!(
+            error("jp start"),
+            "Column 4: Expected a number, found `start`"
+        );
+        assert_eq!(error(""), "Column 1: Expected an instruction");
     }
 }
@@ -0,0 +1,132 @@
+//! Colours to show the screen in.
+
+use super::machine::ALL_PLANES;
+use anyhow::{anyhow, Error, Result};
+use std::str::FromStr;
+
+/// Number of distinct pixel values - one for each combination of XO-CHIP planes.
+pub const NUM_COLOURS: usize = ALL_PLANES as usize + 1;
+
+/// RGB colour for each combination of XO-CHIP planes: neither, first, second, both.
+/// Plain CHIP-8 programs only use the first two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub colours: [[u8; 3]; NUM_COLOURS],
+}
+
+/// Built-in palettes, by the name `FromStr` knows them by.
+pub const PALETTES: &[(&str, Palette)] = &[
+    ("classic", Palette::CLASSIC),
+    ("amber", Palette::AMBER),
+    ("green", Palette::GREEN),
+    ("lcd", Palette::LCD),
+];
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::CLASSIC
+    }
+}
+
+impl Palette {
+    /// Black and white, with greys for the second plane.
+    pub const CLASSIC: Self = Self {
+        colours: [[0, 0, 0], [255, 255, 255], [170, 170, 170], [85, 85, 85]],
+    };
+    /// An amber monochrome monitor.
+    pub const AMBER: Self = Self {
+        colours: [
+            [0x1A, 0x0F, 0x00],
+            [0xFF, 0xB0, 0x00],
+            [0xB3, 0x6B, 0x00],
+            [0x66, 0x3D, 0x00],
+        ],
+    };
+    /// A green phosphor terminal.
+    pub const GREEN: Self = Self {
+        colours: [
+            [0x00, 0x14, 0x00],
+            [0x33, 0xFF, 0x33],
+            [0x22, 0xAA, 0x22],
+            [0x11, 0x55, 0x11],
+        ],
+    };
+    /// Dark pixels on a pea-green handheld LCD.
+    pub const LCD: Self = Self {
+        colours: [
+            [0x9B, 0xBC, 0x0F],
+            [0x0F, 0x38, 0x0F],
+            [0x30, 0x62, 0x30],
+            [0x8B, 0xAC, 0x0F],
+        ],
+    };
+
+    /// One of the built-in palettes, by name.
+    pub fn named(name: &str) -> Option<Self> {
+        PALETTES
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, palette)| palette)
+    }
+
+    /// Colour to show a pixel value in.
+    pub fn rgb(&self, pixel: u8) -> [u8; 3] {
+        self.colours[pixel as usize % NUM_COLOURS]
+    }
+
+    /// Every colour packed one after another, as image formats want their palettes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.colours.iter().flatten().copied().collect()
+    }
+}
+
+/// Parses the name of a built-in palette, or comma-separated hex colours, background first.
+/// Any colours left off keep their default.
+/// ```
+/// # use chip8::Palette;
+/// # use pretty_assertions::assert_eq;
+/// assert_eq!("amber".parse::<Palette>().unwrap(), Palette::AMBER);
+/// let palette: Palette = "#102010, 9bbc0f".parse().unwrap();
+/// assert_eq!(palette.rgb(0), [0x10, 0x20, 0x10]);
+/// assert_eq!(palette.rgb(1), [0x9B, 0xBC, 0x0F]);
+/// assert_eq!(palette.rgb(3), Palette::default().rgb(3));
+/// ```
+impl FromStr for Palette {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(palette) = Self::named(s.trim()) {
+            return Ok(palette);
+        }
+        let mut ret = Self::default();
+        let colours: Vec<&str> = s.split(',').map(str::trim).collect();
+        if colours.len() > NUM_COLOURS {
+            return Err(anyhow!(
+                "A palette has at most {} colours, got {}",
+                NUM_COLOURS,
+                colours.len()
+            ));
+        }
+        for (colour, hex) in ret.colours.iter_mut().zip(colours) {
+            let digits = hex.trim_start_matches('#');
+            let value = u32::from_str_radix(digits, 16)
+                .ok()
+                .filter(|_| digits.len() == 6)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "{} isn't a colour like #RRGGBB, or a palette: {}",
+                        hex,
+                        palette_names()
+                    )
+                })?;
+            *colour = [(value >> 16) as u8, (value >> 8) as u8, value as u8];
+        }
+        Ok(ret)
+    }
+}
+
+/// Names of the built-in palettes, for messages
+fn palette_names() -> String {
+    let names: Vec<&str> = PALETTES.iter().map(|&(name, _)| name).collect();
+    names.join(", ")
+}
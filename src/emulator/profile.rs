@@ -0,0 +1,342 @@
+//! Where a ROM spends its time - how often each address runs, how long each subroutine takes,
+//! and how much goes on waiting for a key or for the delay timer to run out.
+//!
+//! Heatmaps of the first 4K can be saved as plain PPM, or PNG with the `capture` feature.
+
+use super::opcode::Opcode;
+use anyhow::{anyhow, Result};
+use std::{collections::BTreeMap, convert::TryFrom, fmt::Write as _, fs, path::Path};
+
+/// Addresses covered by the heatmap - the original 4K machine.
+const HEATMAP_BYTES: usize = 0x1000;
+
+/// Addresses per row of the heatmap, so the 4K makes a square.
+const HEATMAP_WIDTH: u32 = 64;
+
+/// Longest loop, in instructions including the jump back, that counts as polling the delay timer.
+const MAX_POLL_LOOP: u16 = 4;
+
+/// Time spent in one subroutine.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SubroutineProfile {
+    /// Calls that have returned
+    pub calls: u64,
+    /// Instructions from each call to its return, including the call, the return and any nested calls
+    pub cycles: u64,
+}
+
+/// Counts instructions as a `Machine` runs them, once set with `Machine::set_profiler`.
+/// ```
+/// # use chip8::{Opcode, Profiler};
+/// # use pretty_assertions::assert_eq;
+/// let mut profiler = Profiler::new();
+/// let memory = vec![0; 0x1000];
+/// profiler.record(0x200, Opcode::Call(0x300), 0x300, &memory);
+/// profiler.record(0x300, Opcode::Return, 0x202, &memory);
+/// assert_eq!(profiler.hits(0x200), 1);
+/// assert_eq!(profiler.subroutines()[&0x300].cycles, 2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    /// Times each address has been run, across all 64K
+    hits: Vec<u64>,
+    /// Last instruction run from each address, for the report
+    opcodes: Vec<Option<Opcode>>,
+    total: u64,
+    /// Instructions spent on `WaitKey` with no key down
+    key_wait: u64,
+    /// Instructions spent going round short loops that read the delay timer
+    delay_poll: u64,
+    /// Subroutines entered and not yet returned from, with the instruction count at the call
+    calls: Vec<(u16, u64)>,
+    subroutines: BTreeMap<u16, SubroutineProfile>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            hits: vec![0; 0x10000],
+            opcodes: vec![None; 0x10000],
+            total: 0,
+            key_wait: 0,
+            delay_poll: 0,
+            calls: Vec::new(),
+            subroutines: BTreeMap::new(),
+        }
+    }
+
+    /// Count `opcode`, which just ran from `pc` and left the program counter at `next_pc`.
+    /// `memory` is used to recognise delay timer polling loops.
+    pub fn record(&mut self, pc: u16, opcode: Opcode, next_pc: u16, memory: &[u8]) {
+        use Opcode::*;
+        self.total += 1;
+        self.hits[pc as usize] += 1;
+        self.opcodes[pc as usize] = Some(opcode);
+        match opcode {
+            Call(target) => self.calls.push((target, self.total - 1)),
+            // A return with nothing to match, like after loading a state, just goes uncounted
+            Return => {
+                if let Some((target, start)) = self.calls.pop() {
+                    let subroutine = self.subroutines.entry(target).or_default();
+                    subroutine.calls += 1;
+                    subroutine.cycles += self.total - start;
+                }
+            }
+            WaitKey(_) if next_pc == pc => self.key_wait += 1,
+            Jump(target) if target < pc && is_delay_poll(&memory[target as usize..pc as usize]) => {
+                // One trip round the loop, which ends here
+                self.delay_poll += (pc - target) as u64 / 2 + 1;
+            }
+            _ => {}
+        }
+    }
+
+    /// Instructions run in total.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Times the instruction at `addr` has run.
+    pub fn hits(&self, addr: u16) -> u64 {
+        self.hits[addr as usize]
+    }
+
+    /// Instructions spent waiting on `WaitKey` for a key to go down.
+    pub fn key_wait(&self) -> u64 {
+        self.key_wait
+    }
+
+    /// Instructions spent in short loops waiting for the delay timer.
+    pub fn delay_poll(&self) -> u64 {
+        self.delay_poll
+    }
+
+    /// Time spent in each subroutine that has returned at least once, by address.
+    pub fn subroutines(&self) -> &BTreeMap<u16, SubroutineProfile> {
+        &self.subroutines
+    }
+
+    /// A summary with the `top` busiest addresses and subroutines.
+    pub fn report(&self, top: usize) -> String {
+        let percent = |n: u64| n as f64 * 100.0 / self.total.max(1) as f64;
+        let mut ret = String::new();
+        // Writing to a String can't fail
+        writeln!(ret, "Ran {} instructions", self.total).unwrap();
+        writeln!(
+            ret,
+            "Waiting for a key: {} ({:.1}%)",
+            self.key_wait,
+            percent(self.key_wait)
+        )
+        .unwrap();
+        writeln!(
+            ret,
+            "Polling the delay timer: {} ({:.1}%)",
+            self.delay_poll,
+            percent(self.delay_poll)
+        )
+        .unwrap();
+
+        let mut addresses: Vec<usize> =
+            (0..self.hits.len()).filter(|&a| self.hits[a] > 0).collect();
+        addresses.sort_by_key(|&a| std::cmp::Reverse(self.hits[a]));
+        writeln!(ret, "Hottest addresses:").unwrap();
+        for &addr in addresses.iter().take(top) {
+            let opcode = self.opcodes[addr]
+                .map(|o| o.to_string())
+                .unwrap_or_default();
+            writeln!(
+                ret,
+                "  {:#05X} {:>10} {:>5.1}%  {}",
+                addr,
+                self.hits[addr],
+                percent(self.hits[addr]),
+                opcode
+            )
+            .unwrap();
+        }
+
+        let mut subroutines: Vec<(&u16, &SubroutineProfile)> = self.subroutines.iter().collect();
+        subroutines.sort_by_key(|(_, s)| std::cmp::Reverse(s.cycles));
+        writeln!(ret, "Subroutines:").unwrap();
+        for (addr, subroutine) in subroutines.into_iter().take(top) {
+            writeln!(
+                ret,
+                "  {:#05X} {:>10} {:>5.1}%  {} calls",
+                addr,
+                subroutine.cycles,
+                percent(subroutine.cycles),
+                subroutine.calls
+            )
+            .unwrap();
+        }
+        ret
+    }
+
+    /// RGB colour for each of the first 4K addresses, 64 to a row.
+    /// Addresses that never ran are black, the rest go from dark red to white on a log scale.
+    pub fn heatmap(&self) -> Vec<[u8; 3]> {
+        let hits = &self.hits[..HEATMAP_BYTES];
+        let max = (*hits.iter().max().unwrap_or(&0) as f64).ln_1p();
+        hits.iter()
+            .map(|&n| {
+                if n == 0 {
+                    return [0, 0, 0];
+                }
+                // Anything that ran at all should show up
+                let heat = 0.1 + 0.9 * (n as f64).ln_1p() / max.max(f64::MIN_POSITIVE);
+                let channel =
+                    |from: f64| (((heat - from) * 3.0).clamp(0.0, 1.0) * 255.0).round() as u8;
+                [channel(0.0), channel(1.0 / 3.0), channel(2.0 / 3.0)]
+            })
+            .collect()
+    }
+
+    /// Plain PPM of the heatmap, `scale` image pixels to an address.
+    pub fn encode_heatmap_ppm(&self, scale: u32) -> String {
+        let (width, height, pixels) = self.scaled_heatmap(scale);
+        let mut ret = format!("P3\n{} {}\n255\n", width, height);
+        for row in pixels.chunks(width as usize * 3) {
+            let row: Vec<String> = row.iter().map(u8::to_string).collect();
+            ret.push_str(&row.join(" "));
+            ret.push('\n');
+        }
+        ret
+    }
+
+    /// PNG of the heatmap, `scale` image pixels to an address.
+    #[cfg(feature = "capture")]
+    pub fn encode_heatmap_png(&self, scale: u32) -> Result<Vec<u8>> {
+        let (width, height, pixels) = self.scaled_heatmap(scale);
+        let mut ret = Vec::new();
+        let mut encoder = png::Encoder::new(&mut ret, width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&pixels)?;
+        writer.finish()?;
+        Ok(ret)
+    }
+
+    /// Write the heatmap to `path`, picking PPM or PNG from the extension.
+    pub fn save_heatmap<P: AsRef<Path>>(&self, path: P, scale: u32) -> Result<()> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_lowercase);
+        let bytes = match extension.as_deref() {
+            Some("ppm") => self.encode_heatmap_ppm(scale).into_bytes(),
+            #[cfg(feature = "capture")]
+            Some("png") => self.encode_heatmap_png(scale)?,
+            _ => {
+                return Err(anyhow!(
+                    "Can't save a heatmap as {} - use .ppm{}",
+                    path.display(),
+                    if cfg!(feature = "capture") {
+                        " or .png"
+                    } else {
+                        ""
+                    }
+                ));
+            }
+        };
+        fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    /// Width, height and RGB bytes of the heatmap blown up by `scale`.
+    fn scaled_heatmap(&self, scale: u32) -> (u32, u32, Vec<u8>) {
+        let scale = scale.max(1);
+        let colours = self.heatmap();
+        let width = HEATMAP_WIDTH * scale;
+        let height = (HEATMAP_BYTES as u32 / HEATMAP_WIDTH) * scale;
+        let mut pixels = Vec::with_capacity((width * height * 3) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let addr = (y / scale) * HEATMAP_WIDTH + x / scale;
+                pixels.extend_from_slice(&colours[addr as usize]);
+            }
+        }
+        (width, height, pixels)
+    }
+}
+
+/// Whether the loop body in `code`, everything before the jump back, only reads the delay timer and
+/// tests registers - a game waiting for the timer to run out.
+fn is_delay_poll(code: &[u8]) -> bool {
+    use Opcode::*;
+    if code.is_empty() || !code.len().is_multiple_of(2) || code.len() as u16 / 2 >= MAX_POLL_LOOP {
+        return false;
+    }
+    let opcodes: Vec<Option<Opcode>> = code
+        .chunks(2)
+        .map(|word| Opcode::try_from(u16::from_be_bytes([word[0], word[1]])).ok())
+        .collect();
+    opcodes.iter().any(|o| matches!(o, Some(StoreDelay(_))))
+        && opcodes.iter().all(|o| {
+            matches!(
+                o,
+                Some(StoreDelay(_))
+                    | Some(SkipIfEqVal(..))
+                    | Some(SkipIfNotEqVal(..))
+                    | Some(SkipIfMatchReg(..))
+                    | Some(SkipIfMismatchReg(..))
+            )
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_recognises_delay_polling() {
+        // ld v0, dt; se v0, 0; jp back
+        assert!(is_delay_poll(&[0xF0, 0x07, 0x30, 0x00]));
+        // Drawing in the loop means it's doing real work
+        assert!(!is_delay_poll(&[0xF0, 0x07, 0xD0, 0x15]));
+        assert!(!is_delay_poll(&[0x30, 0x00]));
+    }
+
+    #[test]
+    fn test_nested_calls() {
+        let memory = vec![0; 0x1000];
+        let mut profiler = Profiler::new();
+        profiler.record(0x200, Opcode::Call(0x300), 0x300, &memory);
+        profiler.record(0x300, Opcode::Call(0x400), 0x400, &memory);
+        profiler.record(0x400, Opcode::Return, 0x302, &memory);
+        profiler.record(0x302, Opcode::Return, 0x202, &memory);
+        // Unmatched, so ignored
+        profiler.record(0x202, Opcode::Return, 0x204, &memory);
+        let subroutines = profiler.subroutines();
+        assert_eq!(subroutines[&0x300].cycles, 4);
+        assert_eq!(subroutines[&0x400].cycles, 2);
+        assert_eq!(subroutines.len(), 2);
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let mut profiler = Profiler::new();
+        let memory = vec![0; 0x1000];
+        for _ in 0..100 {
+            profiler.record(0x200, Opcode::Jump(0x200), 0x200, &memory);
+        }
+        profiler.record(0x202, Opcode::ClearScreen, 0x204, &memory);
+        let heatmap = profiler.heatmap();
+        assert_eq!(heatmap.len(), 0x1000);
+        assert_eq!(heatmap[0x200], [255, 255, 255]);
+        assert_eq!(heatmap[0x204], [0, 0, 0]);
+        assert!(heatmap[0x202][0] > 0 && heatmap[0x202][2] == 0);
+        let ppm = profiler.encode_heatmap_ppm(2);
+        assert!(ppm.starts_with("P3\n128 128\n255\n"));
+        assert!(profiler.save_heatmap("heat.bmp", 1).is_err());
+    }
+}
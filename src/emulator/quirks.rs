@@ -0,0 +1,109 @@
+//! Switches for the instructions that CHIP-8 interpreters historically disagree about.
+//!
+//! See [the CHIP-8 extensions reference](https://chip-8.github.io/extensions/) for the gory details.
+
+use anyhow::{anyhow, Error};
+use std::str::FromStr;
+
+/// What FX55 and FX65 do to the index register once they're finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexIncrement {
+    /// Leave idx where it was.
+    Unchanged,
+    /// Advance idx by X.
+    ByX,
+    /// Advance idx by X + 1, leaving it just past the last byte touched.
+    ByXPlusOne,
+}
+
+/// Behaviour of each ambiguous instruction.
+/// The `Default` is the behaviour this emulator has always had.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VY and store the result in VX, instead of shifting VX in place.
+    pub shift_uses_vy: bool,
+    /// How FX55/FX65 leave the index register.
+    pub load_store_idx: IndexIncrement,
+    /// BXNN jumps to XNN + VX, instead of BNNN jumping to NNN + V0.
+    pub jump_uses_vx: bool,
+    /// FX1E sets VF when idx overflows past 0xFFF.
+    pub idx_overflow_sets_vf: bool,
+    /// DXYN cuts sprites off at the screen edge, instead of wrapping them around to the other side.
+    pub clip_sprites: bool,
+}
+
+impl Quirks {
+    /// The original interpreter on the RCA COSMAC VIP.
+    pub const COSMAC_VIP: Self = Self {
+        shift_uses_vy: true,
+        load_store_idx: IndexIncrement::ByXPlusOne,
+        jump_uses_vx: false,
+        idx_overflow_sets_vf: false,
+        clip_sprites: true,
+    };
+
+    /// CHIP-48 on the HP-48 calculators.
+    pub const CHIP_48: Self = Self {
+        shift_uses_vy: false,
+        load_store_idx: IndexIncrement::ByX,
+        jump_uses_vx: true,
+        idx_overflow_sets_vf: false,
+        clip_sprites: true,
+    };
+
+    /// SUPER-CHIP 1.1.
+    pub const SUPER_CHIP: Self = Self {
+        shift_uses_vy: false,
+        load_store_idx: IndexIncrement::Unchanged,
+        jump_uses_vx: true,
+        idx_overflow_sets_vf: false,
+        clip_sprites: true,
+    };
+
+    /// XO-CHIP, as implemented by Octo.
+    pub const XO_CHIP: Self = Self {
+        shift_uses_vy: true,
+        load_store_idx: IndexIncrement::ByXPlusOne,
+        jump_uses_vx: false,
+        idx_overflow_sets_vf: false,
+        clip_sprites: false,
+    };
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store_idx: IndexIncrement::Unchanged,
+            jump_uses_vx: false,
+            idx_overflow_sets_vf: true,
+            clip_sprites: false,
+        }
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = Error;
+
+    /// Look up a preset by name.
+    /// ```
+    /// # use chip8::Quirks;
+    /// # use pretty_assertions::assert_eq;
+    /// assert_eq!("vip".parse::<Quirks>().unwrap(), Quirks::COSMAC_VIP);
+    /// assert_eq!("SCHIP".parse::<Quirks>().unwrap(), Quirks::SUPER_CHIP);
+    /// assert!("chip9".parse::<Quirks>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "default" => Ok(Self::default()),
+            "vip" | "cosmac" | "chip8" => Ok(Self::COSMAC_VIP),
+            "chip48" => Ok(Self::CHIP_48),
+            "schip" | "superchip" => Ok(Self::SUPER_CHIP),
+            "xochip" => Ok(Self::XO_CHIP),
+            _ => Err(anyhow!(
+                "Unknown quirks preset {} - expected one of default, vip, chip48, schip, xochip",
+                s
+            )),
+        }
+    }
+}
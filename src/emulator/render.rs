@@ -0,0 +1,312 @@
+//! Turning the screen into colours, shared by the SDL and WebAssembly renderers.
+//!
+//! Pixels that go dark fade out over a few frames, like the phosphor on an old monitor.  Games that
+//! erase and redraw their sprites with XOR every frame would otherwise flicker whenever a frame is
+//! shown between the two.
+
+use super::{
+    machine::{write_rgba, Screen, TIMER_HZ},
+    palette::Palette,
+};
+use anyhow::{anyhow, Error, Result};
+use std::str::FromStr;
+
+/// Brightness a dark pixel keeps from one 60Hz frame to the next, unless told otherwise.
+pub const DEFAULT_PERSISTENCE: f32 = 0.5;
+
+/// Glow below this is too faint to see, and counts as fully faded.
+const MIN_GLOW: f32 = 1.0 / 255.0;
+
+/// Extra lines drawn between pixels to look more like real hardware.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    /// Solid pixels
+    #[default]
+    None,
+    /// A dark line under each row of pixels, like a CRT
+    Scanlines,
+    /// A dark line under and beside each pixel, like an LCD
+    Grid,
+}
+
+impl Effect {
+    /// Width and height of the lit part of a pixel drawn `size` real pixels square, from its top left.
+    /// The rest shows the gap colour.  Pixels too small to split stay solid.
+    /// ```
+    /// # use chip8::Effect;
+    /// # use pretty_assertions::assert_eq;
+    /// assert_eq!(Effect::Scanlines.lit_size(6), (6, 4));
+    /// assert_eq!(Effect::Grid.lit_size(6), (5, 5));
+    /// assert_eq!(Effect::Grid.lit_size(1), (1, 1));
+    /// ```
+    pub fn lit_size(self, size: u32) -> (u32, u32) {
+        if size < 2 {
+            return (size, size);
+        }
+        match self {
+            Self::None => (size, size),
+            Self::Scanlines => (size, size - (size / 3).max(1)),
+            Self::Grid => (size - 1, size - 1),
+        }
+    }
+}
+
+impl FromStr for Effect {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "scanlines" => Ok(Self::Scanlines),
+            "grid" => Ok(Self::Grid),
+            _ => Err(anyhow!(
+                "Unknown effect {} - expected one of none, scanlines, grid",
+                s
+            )),
+        }
+    }
+}
+
+/// How the screen should look.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderOptions {
+    pub palette: Palette,
+    /// Brightness a pixel keeps each 60Hz frame after going dark, from 0 (none) to 1 (forever)
+    pub persistence: f32,
+    pub effect: Effect,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            palette: Palette::default(),
+            persistence: DEFAULT_PERSISTENCE,
+            effect: Effect::default(),
+        }
+    }
+}
+
+/// Keeps track of what each pixel is showing, fading out the ones that went dark.
+/// ```
+/// # use chip8::{RenderOptions, Renderer, Screen};
+/// # use pretty_assertions::assert_eq;
+/// let mut renderer = Renderer::new(RenderOptions::default());
+/// let mut screen = Screen::default();
+/// screen.toggle(0, 0, 1);
+/// renderer.update(&screen, 0);
+/// assert_eq!(renderer.colour(0, 0), [255, 255, 255]);
+/// // Three frames later it's an eighth as bright
+/// screen.clear();
+/// renderer.update(&screen, 50);
+/// assert_eq!(renderer.colour(0, 0), [32, 32, 32]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    options: RenderOptions,
+    width: u32,
+    height: u32,
+    /// Pixel value each pixel was last lit with, and how bright it still is
+    glow: Vec<(u8, f32)>,
+    /// Time of the last update, in milliseconds
+    updated: Option<u64>,
+}
+
+impl Renderer {
+    pub fn new(options: RenderOptions) -> Self {
+        Self {
+            options,
+            width: 0,
+            height: 0,
+            glow: Vec::new(),
+            updated: None,
+        }
+    }
+
+    pub fn options(&self) -> RenderOptions {
+        self.options
+    }
+
+    /// Change how the screen looks from the next update on.
+    pub fn set_options(&mut self, options: RenderOptions) {
+        self.options = options;
+    }
+
+    /// Width of the screen last drawn, in CHIP-8 pixels
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Height of the screen last drawn, in CHIP-8 pixels
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Catch up with `screen` as of `now_millis`, fading anything dark by the time since the last update.
+    pub fn update(&mut self, screen: &Screen, now_millis: u64) {
+        // A new resolution starts from scratch
+        if (screen.width(), screen.height()) != (self.width, self.height) {
+            self.width = screen.width();
+            self.height = screen.height();
+            self.glow = vec![(0, 0.0); screen.pixels().len()];
+        }
+        let frames = match self.updated {
+            Some(then) => now_millis.saturating_sub(then) as f32 * TIMER_HZ as f32 / 1000.0,
+            None => 0.0,
+        };
+        self.updated = Some(now_millis);
+        // No persistence means no glow at all, even between updates at the same moment
+        let fade = match self.options.persistence {
+            p if p <= 0.0 => 0.0,
+            p => p.min(1.0).powf(frames),
+        };
+        for (glow, &pixel) in self.glow.iter_mut().zip(screen.pixels()) {
+            if pixel != 0 {
+                *glow = (pixel, 1.0);
+            } else if glow.1 > 0.0 {
+                glow.1 *= fade;
+                if glow.1 < MIN_GLOW {
+                    *glow = (0, 0.0);
+                }
+            }
+        }
+    }
+
+    /// Whether anything is still fading out, and needs updating to finish.
+    pub fn is_fading(&self) -> bool {
+        self.glow
+            .iter()
+            .any(|&(_, brightness)| brightness > 0.0 && brightness < 1.0)
+    }
+
+    /// Colour to show the pixel at `x`, `y` in.
+    pub fn colour(&self, x: u32, y: u32) -> [u8; 3] {
+        let (pixel, brightness) = self.glow[(y * self.width + x) as usize];
+        let lit = self.options.palette.rgb(pixel);
+        if brightness >= 1.0 {
+            return lit;
+        }
+        let background = self.options.palette.rgb(0);
+        let mut ret = background;
+        for (channel, (&from, &to)) in ret.iter_mut().zip(background.iter().zip(lit.iter())) {
+            *channel = (from as f32 + (to as f32 - from as f32) * brightness).round() as u8;
+        }
+        ret
+    }
+
+    /// Colour of the gaps left by the effect - the background, a little darker.
+    pub fn gap_colour(&self) -> [u8; 3] {
+        let background = self.options.palette.rgb(0);
+        match self.options.effect {
+            Effect::None => background,
+            _ => [background[0] / 2, background[1] / 2, background[2] / 2],
+        }
+    }
+
+    /// RGBA bytes for the whole screen into `buffer`, `size` real pixels to a CHIP-8 pixel, effect included.
+    pub fn write_rgba(&self, size: u32, buffer: &mut Vec<u8>) {
+        write_rgba(
+            buffer,
+            (self.width, self.height),
+            size,
+            self.options.effect.lit_size(size),
+            self.gap_colour(),
+            |x, y| self.colour(x, y),
+        );
+    }
+
+    /// Size in real pixels of each CHIP-8 pixel to fit the screen in `width` by `height`,
+    /// and the offset to centre it.
+    pub fn layout(&self, width: u32, height: u32) -> (u32, (u32, u32)) {
+        if self.width == 0 || self.height == 0 {
+            return (1, (0, 0));
+        }
+        let size = (width / self.width).min(height / self.height).max(1);
+        let offset = (
+            width.saturating_sub(size * self.width) / 2,
+            height.saturating_sub(size * self.height) / 2,
+        );
+        (size, offset)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_no_persistence_goes_dark_at_once() {
+        let mut renderer = Renderer::new(RenderOptions {
+            persistence: 0.0,
+            ..RenderOptions::default()
+        });
+        let mut screen = Screen::default();
+        screen.toggle(3, 2, 1);
+        renderer.update(&screen, 0);
+        screen.clear();
+        renderer.update(&screen, 1);
+        assert_eq!(renderer.colour(3, 2), [0, 0, 0]);
+        assert!(!renderer.is_fading());
+    }
+
+    #[test]
+    fn test_fades_towards_the_background() {
+        let mut renderer = Renderer::new(RenderOptions {
+            palette: Palette::LCD,
+            ..RenderOptions::default()
+        });
+        let mut screen = Screen::default();
+        screen.toggle(0, 0, 1);
+        renderer.update(&screen, 0);
+        screen.clear();
+        renderer.update(&screen, 50);
+        assert!(renderer.is_fading());
+        let colour = renderer.colour(0, 0);
+        assert!(colour != Palette::LCD.rgb(0) && colour != Palette::LCD.rgb(1));
+        // Long enough and it's gone
+        renderer.update(&screen, 1000);
+        assert_eq!(renderer.colour(0, 0), Palette::LCD.rgb(0));
+        assert!(!renderer.is_fading());
+    }
+
+    #[test]
+    fn test_redrawn_pixels_stay_lit() {
+        let mut renderer = Renderer::new(RenderOptions::default());
+        let mut screen = Screen::default();
+        screen.toggle(1, 1, 2);
+        renderer.update(&screen, 0);
+        renderer.update(&screen, 500);
+        assert_eq!(renderer.colour(1, 1), Palette::default().rgb(2));
+    }
+
+    #[test]
+    fn test_rgba_includes_the_effect() {
+        let mut renderer = Renderer::new(RenderOptions {
+            palette: Palette::LCD,
+            effect: Effect::Grid,
+            ..RenderOptions::default()
+        });
+        let mut screen = Screen::new(2, 1);
+        screen.toggle(0, 0, 1);
+        renderer.update(&screen, 0);
+        let mut rgba = Vec::new();
+        renderer.write_rgba(2, &mut rgba);
+        assert_eq!(rgba.len(), 4 * 2 * 4);
+        let pixels: Vec<&[u8]> = rgba.chunks(4).collect();
+        let gap = [0x4D, 0x5E, 0x07, 255];
+        // Lit pixel, its gap, the dark pixel, its gap, then the row of gaps underneath
+        assert_eq!(pixels[0], &[0x0F, 0x38, 0x0F, 255]);
+        assert_eq!(pixels[1], &gap);
+        assert_eq!(pixels[2], &[0x9B, 0xBC, 0x0F, 255]);
+        assert_eq!(pixels[3], &gap);
+        assert!(pixels[4..].iter().all(|&p| p == gap));
+    }
+
+    #[test]
+    fn test_layout_centres_the_screen() {
+        let mut renderer = Renderer::new(RenderOptions::default());
+        renderer.update(&Screen::hires(), 0);
+        // 960x480 fits 7 pixels per hires pixel, with the rest split either side
+        assert_eq!(renderer.layout(960, 480), (7, (32, 16)));
+    }
+}
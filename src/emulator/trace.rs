@@ -0,0 +1,305 @@
+//! Execution traces - a record of every instruction run, for finding out what a misbehaving ROM actually did.
+//!
+//! A `Tracer` set on a `Machine` writes a line per instruction to stderr, a file, or the `log` crate
+//! under the `chip8::trace` target, as compact text or JSON lines.  Filters keep it to a range of
+//! addresses or a few kinds of instruction.
+
+use super::opcode::{Opcode, OpcodeClass, RawOpcode};
+use anyhow::{anyhow, Error, Result};
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufWriter, Write},
+    ops::RangeInclusive,
+    path::Path,
+    str::FromStr,
+};
+
+/// How each instruction is written out.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One line of text, lined up in columns
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for TraceFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(anyhow!(
+                "Unknown trace format {} - expected text or json",
+                s
+            )),
+        }
+    }
+}
+
+/// Parse an inclusive address range like `0x200-0x2FF`, or a single address.
+/// ```
+/// # use chip8::parse_address_range;
+/// # use pretty_assertions::assert_eq;
+/// assert_eq!(parse_address_range("0x200-0x2ff").unwrap(), 0x200..=0x2FF);
+/// assert_eq!(parse_address_range("0x23A").unwrap(), 0x23A..=0x23A);
+/// assert!(parse_address_range("0x300-0x200").is_err());
+/// ```
+pub fn parse_address_range(s: &str) -> Result<RangeInclusive<u16>> {
+    let addr = |s: &str| {
+        let s = s.trim();
+        let digits = s
+            .strip_prefix("0x")
+            .or_else(|| s.strip_prefix("0X"))
+            .unwrap_or(s);
+        u16::from_str_radix(digits, 16).map_err(|_| anyhow!("Invalid address {}", s))
+    };
+    let (start, end) = match s.split_once('-') {
+        Some((start, end)) => (addr(start)?, addr(end)?),
+        None => (addr(s)?, addr(s)?),
+    };
+    if start > end {
+        return Err(anyhow!("Address range {} runs backwards", s));
+    }
+    Ok(start..=end)
+}
+
+/// Which instructions get traced.  The default lets everything through.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Only instructions at these addresses
+    pub pcs: Option<RangeInclusive<u16>>,
+    /// Only instructions in these classes, or any if empty
+    pub classes: Vec<OpcodeClass>,
+}
+
+impl TraceFilter {
+    /// Whether `opcode` at `pc` should be traced.
+    pub fn matches(&self, pc: u16, opcode: Opcode) -> bool {
+        self.pcs.as_ref().is_none_or(|pcs| pcs.contains(&pc))
+            && (self.classes.is_empty() || self.classes.contains(&opcode.class()))
+    }
+}
+
+/// A register an instruction changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterChange {
+    pub register: u8,
+    pub before: u8,
+    pub after: u8,
+}
+
+/// Everything traced about one instruction.  `idx` and `vf` are as it left them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceRecord {
+    /// 60Hz frame the instruction ran in
+    pub frame: u64,
+    pub pc: u16,
+    pub raw: RawOpcode,
+    pub opcode: Opcode,
+    pub changes: Vec<RegisterChange>,
+    pub idx: u16,
+    pub vf: u8,
+}
+
+impl TraceRecord {
+    /// The record as a single line, without the newline.
+    /// ```
+    /// # use chip8::{Opcode, RawOpcode, RegisterChange, TraceFormat, TraceRecord};
+    /// # use pretty_assertions::assert_eq;
+    /// let record = TraceRecord {
+    ///     frame: 3,
+    ///     pc: 0x200,
+    ///     raw: RawOpcode::from(0x6120),
+    ///     opcode: Opcode::SetRegister(1, 0x20),
+    ///     changes: vec![RegisterChange { register: 1, before: 0, after: 0x20 }],
+    ///     idx: 0x2EA,
+    ///     vf: 0,
+    /// };
+    /// assert_eq!(
+    ///     record.format(TraceFormat::Text),
+    ///     "     3 0x200 0x6120 ld v1, 0x20          I=0x2EA VF=0x00 V1=0x00->0x20"
+    /// );
+    /// assert_eq!(
+    ///     record.format(TraceFormat::Json),
+    ///     r#"{"frame":3,"pc":512,"raw":24864,"opcode":"ld v1, 0x20","i":746,"vf":0,"changes":[{"register":1,"before":0,"after":32}]}"#
+    /// );
+    /// ```
+    pub fn format(&self, format: TraceFormat) -> String {
+        let mut ret = String::new();
+        // Writing to a String can't fail
+        match format {
+            TraceFormat::Text => {
+                write!(
+                    ret,
+                    "{:>6} {:#05X} {} {:<20} I={:#05X} VF={:#04X}",
+                    self.frame,
+                    self.pc,
+                    self.raw,
+                    self.opcode.to_string(),
+                    self.idx,
+                    self.vf
+                )
+                .unwrap();
+                for change in &self.changes {
+                    write!(
+                        ret,
+                        " V{:X}={:#04X}->{:#04X}",
+                        change.register, change.before, change.after
+                    )
+                    .unwrap();
+                }
+            }
+            // Mnemonics never contain anything that needs escaping
+            TraceFormat::Json => {
+                let changes: Vec<String> = self
+                    .changes
+                    .iter()
+                    .map(|change| {
+                        format!(
+                            r#"{{"register":{},"before":{},"after":{}}}"#,
+                            change.register, change.before, change.after
+                        )
+                    })
+                    .collect();
+                write!(
+                    ret,
+                    r#"{{"frame":{},"pc":{},"raw":{},"opcode":"{}","i":{},"vf":{},"changes":[{}]}}"#,
+                    self.frame,
+                    self.pc,
+                    u16::from(self.raw),
+                    self.opcode,
+                    self.idx,
+                    self.vf,
+                    changes.join(",")
+                )
+                .unwrap();
+            }
+        }
+        ret
+    }
+}
+
+/// Where trace lines go.
+enum Sink {
+    Writer(Box<dyn Write>),
+    Log,
+}
+
+/// Writes out each instruction a `Machine` runs, once set with `Machine::set_tracer`.
+pub struct Tracer {
+    sink: Sink,
+    format: TraceFormat,
+    filter: TraceFilter,
+}
+
+impl Tracer {
+    /// Trace into `output`.
+    pub fn new(output: Box<dyn Write>, format: TraceFormat) -> Self {
+        Self {
+            sink: Sink::Writer(output),
+            format,
+            filter: TraceFilter::default(),
+        }
+    }
+
+    /// Trace through the `log` crate, at trace level under the `chip8::trace` target.
+    pub fn to_log(format: TraceFormat) -> Self {
+        Self {
+            sink: Sink::Log,
+            format,
+            filter: TraceFilter::default(),
+        }
+    }
+
+    /// Trace to the file at `path`, or to stderr if it's `-`.
+    pub fn create<P: AsRef<Path>>(path: P, format: TraceFormat) -> Result<Self> {
+        let path = path.as_ref();
+        let output: Box<dyn Write> = if path == Path::new("-") {
+            Box::new(io::stderr())
+        } else {
+            Box::new(BufWriter::new(File::create(path)?))
+        };
+        Ok(Self::new(output, format))
+    }
+
+    /// Only trace what `filter` lets through.
+    pub fn with_filter(mut self, filter: TraceFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Whether `opcode` at `pc` is going to be traced.
+    pub fn wants(&self, pc: u16, opcode: Opcode) -> bool {
+        self.filter.matches(pc, opcode)
+    }
+
+    /// Write out one instruction.
+    /// A trace that can't be written gives up with a warning, rather than stopping the machine.
+    pub fn record(&mut self, record: &TraceRecord) {
+        let line = record.format(self.format);
+        match &mut self.sink {
+            Sink::Writer(output) => {
+                if let Err(e) = writeln!(output, "{}", line) {
+                    log::warn!("Stopped tracing: {}", e);
+                    self.sink = Sink::Writer(Box::new(io::sink()));
+                }
+            }
+            Sink::Log => log::trace!(target: "chip8::trace", "{}", line),
+        }
+    }
+
+    /// Push out anything buffered.
+    pub fn flush(&mut self) -> Result<()> {
+        if let Sink::Writer(output) = &mut self.sink {
+            output.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            log::warn!("Could not finish trace: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_filter_by_range_and_class() {
+        let filter = TraceFilter {
+            pcs: Some(0x200..=0x20F),
+            classes: vec![OpcodeClass::Display, OpcodeClass::Flow],
+        };
+        assert!(filter.matches(0x200, Opcode::Draw(0, 0, 1)));
+        assert!(filter.matches(0x20F, Opcode::Jump(0x200)));
+        assert!(!filter.matches(0x210, Opcode::Draw(0, 0, 1)));
+        assert!(!filter.matches(0x202, Opcode::SetRegister(0, 1)));
+        assert!(TraceFilter::default().matches(0xFFE, Opcode::SetRegister(0, 1)));
+    }
+
+    #[test]
+    fn test_parse_options() {
+        assert_eq!("JSON".parse::<TraceFormat>().unwrap(), TraceFormat::Json);
+        assert!("xml".parse::<TraceFormat>().is_err());
+        assert_eq!(
+            "display".parse::<OpcodeClass>().unwrap(),
+            OpcodeClass::Display
+        );
+        assert!("graphics"
+            .parse::<OpcodeClass>()
+            .unwrap_err()
+            .to_string()
+            .ends_with("flow, skip, register, memory, display, input, timer, sound"));
+        assert!(parse_address_range("0x200-zz").is_err());
+    }
+}
@@ -1,11 +1,34 @@
 //! A Chip8 VM as a library
 
+mod asm;
+mod disasm;
 mod emulator;
+// Generated by build.rs, which rewrites it on every build
+#[rustfmt::skip]
 mod roms;
 
-pub use emulator::{Machine, Opcode, RawOpcode};
+pub use asm::{assemble, assemble_file, AsmError};
+pub use disasm::{disassemble, disassemble_with_entries, Disassembly, Line};
+pub use emulator::{
+    encode_pbm, key_name_from_code, parse_address_range, save_screenshot, write_rgba, Access,
+    AccessMap, AudioPattern, ByteKind, ByteUse, Chip8Error, Command, Comparison, Condition,
+    Context, DebugCommand, Debugger, Effect, HeadlessContext, IndexIncrement, KeyEvent, KeyMap,
+    Machine, MachineCallHandler, MachineCallState, MachineCalls, MemoryAccess, MemoryIssue, Movie,
+    Opcode, OpcodeClass, Palette, Profiler, Quirks, RawOpcode, RecordingContext, RegisterChange,
+    RenderOptions, Renderer, ReplayContext, Rewind, SaveState, Scheduler, Screen, StopReason,
+    SubroutineProfile, TraceFilter, TraceFormat, TraceRecord, Tracer, VipRoutines, WatchKind,
+    CYCLES_PER_SECOND, DEBUG_HELP, DEFAULT_PERSISTENCE, DEFAULT_REWIND_SECONDS, DEFAULT_RUN_LIMIT,
+    HIRES_PIXEL_COLS, HIRES_PIXEL_ROWS, NUM_COLOURS, NUM_KEYS, PALETTES, PIXEL_COLS, PIXEL_ROWS,
+    TIMER_HZ, TURBO_FACTOR, VIP_HIRES_PIXEL_ROWS,
+};
 pub use roms::ROMS;
 
+#[cfg(feature = "capture")]
+pub use emulator::{encode_png, GifRecorder};
+
+#[cfg(feature = "keymap")]
+pub use emulator::KeyConfig;
+
 #[cfg(feature = "sdl")]
 pub use emulator::SdlContext;
 
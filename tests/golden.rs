@@ -0,0 +1,216 @@
+//! Boots every bundled ROM headlessly and checks the screen it ends up on against a golden image.
+//!
+//! Goldens are plain PBM files in `tests/golden`, one per ROM.  Run with `CHIP8_BLESS=1` to write the
+//! current screens out as the new goldens.  On a mismatch a PPM diff is written to Cargo's temporary
+//! directory for integration tests - white where both agree, red where the golden has a pixel the run
+//! didn't draw, green where the run drew one the golden doesn't have.  A ROM that leaves the screen blank
+//! fails instead of blessing a golden that checks nothing - give it a `Script` that runs long enough.
+
+use chip8::{encode_pbm, HeadlessContext, KeyEvent, Machine, Screen, ROMS};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// Virtual milliseconds to run a ROM for if it has no script.
+const DEFAULT_MILLIS: u64 = 3000;
+/// Seed for every run, so `RND` draws the same every time.
+const SEED: u64 = 0xC8;
+
+/// A ROM that needs some input to get anywhere interesting.
+struct Script {
+    name: &'static str,
+    /// Virtual milliseconds to run for
+    millis: u64,
+    /// `(millis, key, pressed)` keypad events
+    keys: &'static [(u64, u8, bool)],
+}
+
+const SCRIPTS: &[Script] = &[
+    Script {
+        name: "blinky",
+        millis: 8000,
+        keys: &[],
+    },
+    Script {
+        name: "brix",
+        millis: 4000,
+        keys: &[(500, 0x6, true), (1500, 0x6, false)],
+    },
+    Script {
+        name: "invaders",
+        millis: 4000,
+        keys: &[(500, 0x5, true), (700, 0x5, false)],
+    },
+    Script {
+        name: "kaleid",
+        millis: 6000,
+        // Draw with 6, 8, 4 and 2, then 0 plays the pattern back
+        keys: &[
+            (500, 0x6, true),
+            (1500, 0x6, false),
+            (1600, 0x8, true),
+            (2600, 0x8, false),
+            (2700, 0x4, true),
+            (3200, 0x4, false),
+            (3300, 0x2, true),
+            (3800, 0x2, false),
+            (3900, 0x0, true),
+            (4000, 0x0, false),
+        ],
+    },
+    Script {
+        name: "pong",
+        millis: 3000,
+        keys: &[(200, 0x1, true), (1200, 0x1, false)],
+    },
+    Script {
+        name: "tetris",
+        millis: 4000,
+        keys: &[(500, 0x5, true), (800, 0x5, false)],
+    },
+    Script {
+        name: "tictac",
+        millis: 3000,
+        keys: &[(500, 0x1, true), (700, 0x1, false)],
+    },
+];
+
+/// Run `name` for its scripted time and return the final screen.
+fn run(name: &str) -> Screen {
+    let (millis, keys) = SCRIPTS
+        .iter()
+        .find(|script| script.name == name)
+        .map_or((DEFAULT_MILLIS, &[][..]), |script| {
+            (script.millis, script.keys)
+        });
+    let context = HeadlessContext::new(SEED);
+    context.set_frame_limit(1);
+    for &(at_millis, key, pressed) in keys {
+        context.push_key_event(KeyEvent {
+            at_millis,
+            key,
+            pressed,
+        });
+    }
+    context.quit_after(millis);
+    let mut machine = Machine::new(Box::new(context.clone()));
+    machine.load_game(name).unwrap();
+    machine.run().unwrap();
+    machine.screen().clone()
+}
+
+/// Read back a plain PBM as its size and one bool per pixel.
+fn from_pbm(text: &str) -> Option<(u32, u32, Vec<bool>)> {
+    let mut tokens = text
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .flat_map(str::split_whitespace);
+    if tokens.next()? != "P1" {
+        return None;
+    }
+    let width = tokens.next()?.parse().ok()?;
+    let height = tokens.next()?.parse().ok()?;
+    let pixels: Vec<bool> = tokens.map(|t| t == "1").collect();
+    if pixels.len() != (width * height) as usize {
+        return None;
+    }
+    Some((width, height, pixels))
+}
+
+/// Plain PPM showing where `screen` differs from the golden.
+fn diff_ppm(width: u32, height: u32, golden: &[bool], screen: &Screen) -> String {
+    let mut ret = format!("P3\n{} {}\n255\n", width, height);
+    for (&want, &got) in golden.iter().zip(screen.pixels()) {
+        let colour = match (want, got != 0) {
+            (true, true) => "255 255 255",
+            (false, false) => "0 0 0",
+            (true, false) => "255 0 0",
+            (false, true) => "0 255 0",
+        };
+        ret.push_str(colour);
+        ret.push('\n');
+    }
+    ret
+}
+
+/// Where the golden for `name` is checked in.
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.pbm", name))
+}
+
+#[test]
+fn test_golden_frames() {
+    let bless = env::var_os("CHIP8_BLESS").is_some();
+    let mut names: Vec<&String> = ROMS.keys().collect();
+    names.sort();
+
+    let mut failures = Vec::new();
+    for name in names {
+        let screen = run(name);
+        let path = golden_path(name);
+        // A blank screen would match a ROM that never draws anything
+        if screen.pixels().iter().all(|&pixel| pixel == 0) {
+            failures.push(format!(
+                "{}: nothing on screen - give it a longer script",
+                name
+            ));
+            continue;
+        }
+        if bless {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, encode_pbm(&screen, 1)).unwrap();
+            continue;
+        }
+        let (width, height, pixels) =
+            match fs::read_to_string(&path).ok().as_deref().and_then(from_pbm) {
+                Some(golden) => golden,
+                None => {
+                    failures.push(format!(
+                        "{}: no readable golden at {}",
+                        name,
+                        path.display()
+                    ));
+                    continue;
+                }
+            };
+        if !pixels.contains(&true) {
+            failures.push(format!("{}: golden at {} is blank", name, path.display()));
+            continue;
+        }
+        if (width, height) != (screen.width(), screen.height()) {
+            failures.push(format!(
+                "{}: screen is {}x{}, golden is {}x{}",
+                name,
+                screen.width(),
+                screen.height(),
+                width,
+                height
+            ));
+            continue;
+        }
+        let wrong = pixels
+            .iter()
+            .zip(screen.pixels())
+            .filter(|(&want, &got)| want != (got != 0))
+            .count();
+        if wrong > 0 {
+            let diff = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.diff.ppm", name));
+            fs::write(&diff, diff_ppm(width, height, &pixels, &screen)).unwrap();
+            failures.push(format!(
+                "{}: {} pixels differ, see {}",
+                name,
+                wrong,
+                diff.display()
+            ));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "Golden frames don't match - rerun with CHIP8_BLESS=1 if the change is intended:\n{}",
+        failures.join("\n")
+    );
+}